@@ -0,0 +1,115 @@
+"""Converts a PolicyNetwork checkpoint saved by tch's VarStore to a TorchScript module.
+
+The input and output contract of the module is documented in src/torchscript.rs. Any network
+trained in PyTorch with the same contract can be saved with torch.jit.script(model).save(path)
+and played with `policy_player --torchscript`.
+
+The ModelConfig saved next to the checkpoint as model.ot.config.json is copied next to the module
+as model.pt.config.json, so that `policy_player` builds the inputs of the same feature set.
+
+Usage: python scripts/export_torchscript.py model.ot model.pt
+"""
+import json
+import os
+import sys
+
+import torch
+import torch.nn as nn
+import torch.nn.functional as F
+
+INPUT_CHANNELS = {"v1": 104, "v2": 113}
+BOARD_PLANES = 104
+PLANES = 27
+
+
+class PolicyNetwork(nn.Module):
+    """Same layers as src/network/policy.rs."""
+
+    def __init__(self, input_channels: int, channels: int, layers: int):
+        super().__init__()
+        self.convs = nn.ModuleList(
+            [
+                nn.Conv2d(input_channels if i == 0 else channels, channels, 3, padding=1)
+                for i in range(layers)
+            ]
+        )
+        self.head = nn.Conv2d(channels, PLANES, 1, bias=False)
+        self.bias = nn.Parameter(torch.zeros(9 * 9 * PLANES))
+
+    def forward(self, x: torch.Tensor) -> torch.Tensor:
+        for conv in self.convs:
+            x = F.relu(conv(x))
+        x = self.head(x)
+        return x.reshape(x.shape[0], -1) + self.bias
+
+
+def varstore_names(names: list) -> list:
+    """Names tch gives to variables created on the same path in the order of `names`.
+
+    A name already taken gets the number of variables created before it as suffix, so the layers
+    of src/network/policy.rs are weight, bias, weight__2, bias__3, ... and the head and its bias,
+    created without a bias and by Bias::new after it, are weight__{2L} and bias__{2L+1}.
+    """
+    taken = set()
+    result = []
+    for count, name in enumerate(names):
+        if name in taken:
+            name = "{}__{}".format(name, count)
+        taken.add(name)
+        result.append(name)
+    return result
+
+
+def load_varstore(path: str) -> dict:
+    # VarStore files are TorchScript archives whose tensors are named with '|' instead of '.'.
+    archive = torch.jit.load(path, map_location="cpu")
+    tensors = dict(archive.named_buffers())
+    tensors.update(dict(archive.named_parameters()))
+    return {name.replace("|", "."): tensor for name, tensor in tensors.items()}
+
+
+def load_config(path: str) -> dict:
+    """Checkpoints saved without a config are of the default config, as in src/network/config.rs."""
+    path = path + ".config.json"
+    if not os.path.exists(path):
+        return {}
+    with open(path) as f:
+        return json.load(f)
+
+
+def input_channels(config: dict) -> int:
+    """Same as ModelConfig::input_channels."""
+    history = config.get("history", {})
+    return (
+        INPUT_CHANNELS[config.get("feature_set", "v1")]
+        + history.get("positions", 0) * BOARD_PLANES
+        + history.get("moves", 0) * 2
+    )
+
+
+def main():
+    if len(sys.argv) != 3:
+        sys.exit(__doc__)
+    tensors = load_varstore(sys.argv[1])
+    config = load_config(sys.argv[1])
+
+    layers = config.get("layers", 12)
+    model = PolicyNetwork(input_channels(config), config.get("channels", 192), layers)
+    # Same creation order as PolicyNetwork::new.
+    parameters = []
+    for conv in model.convs:
+        parameters += [("weight", conv.weight), ("bias", conv.bias)]
+    parameters += [("weight", model.head.weight), ("bias", model.bias)]
+    names = varstore_names([name for name, _ in parameters])
+    with torch.no_grad():
+        for name, (_, parameter) in zip(names, parameters):
+            parameter.copy_(tensors[name])
+    model.eval()
+
+    torch.jit.script(model).save(sys.argv[2])
+    with open(sys.argv[2] + ".config.json", "w") as f:
+        json.dump(config, f, indent=2)
+
+
+if __name__ == "__main__":
+    main()
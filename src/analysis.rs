@@ -0,0 +1,179 @@
+//! Replays a game record and compares every played move with the prediction of `PolicyNetwork`.
+//!
+//! There is no value network yet, so positions are not given a win probability.
+use crate::csa::format_csa_move;
+use crate::features::ContextTracker;
+use crate::game::LegalMove;
+use crate::inference::{predict, MoveProbability};
+use crate::network::policy::PolicyNetwork;
+use anyhow::{anyhow, Result};
+use shogiutil::{Board, Color, Move};
+use tch::Device;
+
+/// Number of predicted moves kept for each position.
+pub const TOP_MOVES: usize = 5;
+
+#[derive(Debug, Clone)]
+pub struct MoveAnalysis {
+    pub mv: LegalMove,
+    /// 1-origin rank of the played move among the legal moves.
+    pub rank: usize,
+    pub probability: f64,
+    /// The most probable moves, the most probable first.
+    pub top_moves: Vec<MoveProbability>,
+    /// Entropy in nats of the policy renormalized over the legal moves.
+    pub entropy: f64,
+}
+
+/// Entropy in nats of `probabilities` after renormalizing them to sum up to 1.
+pub fn entropy(probabilities: &[f64]) -> f64 {
+    let sum: f64 = probabilities.iter().sum();
+    probabilities
+        .iter()
+        .map(|&p| p / sum)
+        .filter(|&p| p > 0.0)
+        .map(|p| -p * p.ln())
+        .sum()
+}
+
+pub fn analyze_game(
+    model: &PolicyNetwork,
+    device: Device,
+    moves: &[Move],
+) -> Result<Vec<MoveAnalysis>> {
+    let mut board = Board::default();
+    let mut analyses = vec![];
+    let mut contexts = ContextTracker::default();
+    let input_features = model.config().input_features();
+    for mv in moves.iter() {
+        let context = contexts.context(&board, mv.color);
+        let predictions = predict(model, device, input_features, &board, mv.color, &context);
+        contexts.push(board.clone(), mv);
+        let promoted = board.push_move(mv.clone())?.promoted;
+        // Compared in CSA notation as `Game::play_csa` does, since the piece of a promoting move
+        // parsed from a record may be the promoted one.
+        let csa = format_csa_move(mv, promoted);
+        let index = predictions
+            .iter()
+            .position(|p| p.mv.to_csa() == csa)
+            .ok_or_else(|| anyhow!("Move {} is not legal", analyses.len() + 1))?;
+
+        let probabilities = predictions
+            .iter()
+            .map(|p| p.probability)
+            .collect::<Vec<_>>();
+        analyses.push(MoveAnalysis {
+            mv: predictions[index].mv.clone(),
+            rank: index + 1,
+            probability: predictions[index].probability,
+            top_moves: predictions.into_iter().take(TOP_MOVES).collect(),
+            entropy: entropy(&probabilities),
+        });
+    }
+    Ok(analyses)
+}
+
+/// Statistics of the moves of one player.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct SideSummary {
+    pub moves: usize,
+    /// Moves the network ranked first.
+    pub top1: usize,
+    pub top5: usize,
+    /// Moves with probability under the threshold given to `summarize`.
+    pub unlikely: usize,
+    pub sum_probability: f64,
+    pub sum_entropy: f64,
+}
+
+impl SideSummary {
+    pub fn mean_probability(&self) -> f64 {
+        self.sum_probability / self.moves as f64
+    }
+
+    pub fn mean_entropy(&self) -> f64 {
+        self.sum_entropy / self.moves as f64
+    }
+}
+
+/// Summaries of Black and White in this order.
+pub fn summarize(analyses: &[MoveAnalysis], unlikely_threshold: f64) -> [SideSummary; 2] {
+    let mut summaries = [SideSummary::default(); 2];
+    for analysis in analyses.iter() {
+        let summary = &mut summaries[(analysis.mv.mv.color == Color::White) as usize];
+        summary.moves += 1;
+        if analysis.rank == 1 {
+            summary.top1 += 1;
+        }
+        if analysis.rank <= TOP_MOVES {
+            summary.top5 += 1;
+        }
+        if analysis.probability < unlikely_threshold {
+            summary.unlikely += 1;
+        }
+        summary.sum_probability += analysis.probability;
+        summary.sum_entropy += analysis.entropy;
+    }
+    summaries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::config::ModelConfig;
+    use shogiutil::{parse_csa_string, Piece, Square};
+    use tch::nn::VarStore;
+
+    #[test]
+    fn test_entropy() {
+        assert_eq!(entropy(&[1.0]), 0.0);
+        assert!((entropy(&[0.25, 0.25]) - 2f64.ln()).abs() < 1e-12);
+        assert!((entropy(&[0.1, 0.1, 0.1, 0.1, 0.0]) - 4f64.ln()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_summarize() {
+        let analysis = |color, rank, probability| MoveAnalysis {
+            mv: LegalMove {
+                mv: Move {
+                    from: Some(Square { file: 7, rank: 7 }),
+                    to: Square { file: 7, rank: 6 },
+                    piece: Piece::Pawn,
+                    color,
+                },
+                promoted: false,
+            },
+            rank,
+            probability,
+            top_moves: vec![],
+            entropy: 1.0,
+        };
+        let analyses = vec![
+            analysis(Color::Black, 1, 0.5),
+            analysis(Color::White, 3, 0.2),
+            analysis(Color::Black, 8, 0.001),
+        ];
+        let [black, white] = summarize(&analyses, 0.01);
+        assert_eq!(black.moves, 2);
+        assert_eq!(black.top1, 1);
+        assert_eq!(black.top5, 1);
+        assert_eq!(black.unlikely, 1);
+        assert!((black.mean_probability() - 0.2505).abs() < 1e-12);
+        assert_eq!(white.moves, 1);
+        assert_eq!(white.top1, 0);
+        assert_eq!(white.top5, 1);
+        assert_eq!(white.mean_entropy(), 1.0);
+    }
+
+    #[test]
+    fn test_analyze_promoting_move() {
+        let kifu =
+            parse_csa_string("V2.2\nN+a\nN-b\nPI\n+\n+7776FU\n-3334FU\n+8822UM\n%TORYO\n").unwrap();
+        let vs = VarStore::new(Device::Cpu);
+        let model = PolicyNetwork::new(&vs.root(), &ModelConfig::default());
+        let analyses = analyze_game(&model, Device::Cpu, &kifu.moves).unwrap();
+        assert_eq!(analyses.len(), 3);
+        assert_eq!(analyses[2].mv.to_csa(), "+8822UM");
+        assert!(analyses[2].mv.promoted);
+    }
+}
@@ -0,0 +1,120 @@
+//! Targets and losses of the auxiliary heads of `PolicyNetwork`, which are trained along the
+//! policy with the weights of `AuxiliaryHeads`.
+use crate::features::MAX_MOVE_NUMBER;
+use crate::model::Position;
+use crate::network::config::AuxiliaryHeads;
+use crate::network::policy::Heads;
+use tch::kind::Kind::{Double, Float};
+use tch::{Device, Tensor};
+
+/// Target of the game length head: the moves left in the game including the one of `position`,
+/// divided by `MAX_MOVE_NUMBER` and clipped to 1.
+pub fn game_length_target(position: &Position) -> f32 {
+    let left = (position.game_length + 1).saturating_sub(position.move_number);
+    left.min(MAX_MOVE_NUMBER) as f32 / MAX_MOVE_NUMBER as f32
+}
+
+/// Losses of the heads `Heads` has, over a batch of `positions`.
+pub struct AuxiliaryLosses {
+    /// Negative log likelihood of the replies, over the positions which have one.
+    pub opponent_move: Option<Tensor>,
+    /// Mean squared error of `game_length_target`.
+    pub game_length: Option<Tensor>,
+}
+
+impl AuxiliaryLosses {
+    pub fn new(heads: &Heads, positions: &[Position], device: Device) -> AuxiliaryLosses {
+        let opponent_move = heads.opponent_move.as_ref().map(|y| {
+            let labels = positions
+                .iter()
+                .map(|position| position.opponent_move_label.unwrap_or(0) as i64)
+                .collect::<Vec<_>>();
+            let mask = positions
+                .iter()
+                .map(|position| {
+                    if position.opponent_move_label.is_some() {
+                        1.0
+                    } else {
+                        0.0
+                    }
+                })
+                .collect::<Vec<_>>();
+            let count = mask.iter().sum::<f64>().max(1.0);
+            let labels = Tensor::of_slice(&labels).to_device(device);
+            let mask = Tensor::of_slice(&mask).to_device(device);
+            let nll = -y
+                .log_softmax(-1, Double)
+                .gather(1, &labels.view((-1, 1)), false)
+                .view(-1);
+            (nll * mask).sum(Double) / count
+        });
+        let game_length = heads.game_length.as_ref().map(|y| {
+            let targets = positions.iter().map(game_length_target).collect::<Vec<_>>();
+            let diff = y.totype(Float) - Tensor::of_slice(&targets).to_device(device);
+            (&diff * &diff).mean(Double)
+        });
+        AuxiliaryLosses {
+            opponent_move,
+            game_length,
+        }
+    }
+
+    /// Sum of the losses scaled by their weights, or `None` if there are no heads.
+    pub fn weighted_sum(&self, weights: &AuxiliaryHeads) -> Option<Tensor> {
+        let losses = [
+            (self.opponent_move.as_ref(), weights.opponent_move),
+            (self.game_length.as_ref(), weights.game_length),
+        ];
+        losses
+            .iter()
+            .filter_map(|(loss, weight)| loss.map(|loss| loss * *weight))
+            .fold(None, |sum, loss| match sum {
+                Some(sum) => Some(sum + loss),
+                None => Some(loss),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::set_auxiliary_targets;
+
+    fn position(move_label: i16, move_number: u16) -> Position {
+        Position {
+            move_number,
+            ..Position::new(vec![], move_label)
+        }
+    }
+
+    #[test]
+    fn test_auxiliary_losses() {
+        let mut positions = vec![position(3, 1), position(5, 2)];
+        set_auxiliary_targets(&mut positions);
+        assert_eq!(positions[0].opponent_move_label, Some(5));
+        assert_eq!(positions[1].opponent_move_label, None);
+        assert_eq!(game_length_target(&positions[0]), 2.0 / 256.0);
+        assert_eq!(game_length_target(&positions[1]), 1.0 / 256.0);
+
+        let mut logits = vec![0f32; 2 * 8];
+        logits[5] = 10.0;
+        let heads = Heads {
+            policy: Tensor::zeros(&[2, 8], (Float, Device::Cpu)),
+            opponent_move: Some(Tensor::of_slice(&logits).view((2, 8))),
+            game_length: Some(Tensor::of_slice(&[2.0f32 / 256.0, 0.0])),
+        };
+        let losses = AuxiliaryLosses::new(&heads, &positions, Device::Cpu);
+        // Only the first position has a reply, which is predicted with almost certainty.
+        let opponent_move = f64::from(losses.opponent_move.as_ref().unwrap());
+        assert!(opponent_move < 1e-3);
+        let game_length = f64::from(losses.game_length.as_ref().unwrap());
+        assert!((game_length - 0.5 * (1.0 / 256.0f64).powi(2)).abs() < 1e-9);
+
+        let weights = AuxiliaryHeads {
+            opponent_move: 0.0,
+            game_length: 2.0,
+        };
+        let sum = f64::from(&losses.weighted_sum(&weights).unwrap());
+        assert!((sum - 2.0 * game_length).abs() < 1e-9);
+    }
+}
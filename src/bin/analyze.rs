@@ -0,0 +1,141 @@
+use anyhow::Result;
+use clap::Clap;
+use shogiutil::{parse_csa_string, Color};
+use std::env;
+use std::fs::{read_to_string, File};
+use std::io::Write;
+use super_duper_dragon::analysis::{analyze_game, summarize, MoveAnalysis, TOP_MOVES};
+use super_duper_dragon::kif::{format_kif_move, KifRecord};
+use super_duper_dragon::network::config::ModelConfig;
+use super_duper_dragon::network::policy::PolicyNetwork;
+use tch::nn::VarStore;
+use tch::Device;
+
+#[derive(Clap)]
+#[clap(version = "1.0", author = "kenkoooo <kenkou.n@gmail.com>")]
+struct Opts {
+    #[clap(short, long)]
+    model_filepath: String,
+    #[clap(short, long)]
+    kifu: String,
+    /// Annotated KIF to write, in UTF-8.
+    #[clap(short, long)]
+    output: Option<String>,
+    /// Played moves less probable than this are flagged.
+    #[clap(long, default_value = "0.01")]
+    unlikely_threshold: f64,
+}
+
+fn names(content: &str) -> (String, String) {
+    let name = |prefix| {
+        content
+            .lines()
+            .find_map(|line| line.strip_prefix(prefix))
+            .unwrap_or("")
+            .to_string()
+    };
+    (name("N+"), name("N-"))
+}
+
+fn kif_moves(analyses: &[MoveAnalysis]) -> Vec<String> {
+    let mut previous_to = None;
+    analyses
+        .iter()
+        .map(|analysis| {
+            let mv = &analysis.mv;
+            let kif = format_kif_move(&mv.mv, mv.promoted, previous_to);
+            previous_to = Some(&mv.mv.to);
+            kif
+        })
+        .collect()
+}
+
+fn main() -> Result<()> {
+    env::set_var("RUST_LOG", "info");
+    env_logger::init();
+    let opts: Opts = Opts::parse();
+
+    let content = read_to_string(&opts.kifu)?;
+    let kifu = parse_csa_string(&content)?;
+    let mut vs = VarStore::new(Device::Cuda(0));
+    let config = ModelConfig::load_for(&opts.model_filepath)?;
+    let model = PolicyNetwork::new(&vs.root(), &config);
+    vs.load(&opts.model_filepath)?;
+
+    let analyses = analyze_game(&model, vs.device(), &kifu.moves)?;
+    let kif_moves = kif_moves(&analyses);
+    let (black_name, white_name) = names(&content);
+    let mut record = KifRecord::new(&black_name, &white_name);
+    println!(
+        "{:>4} {:<12} {:>4} {:>7} {:>7}  top-{}",
+        "#", "move", "rank", "prob", "entropy", TOP_MOVES
+    );
+    for (i, (analysis, kif)) in analyses.iter().zip(kif_moves.into_iter()).enumerate() {
+        let unlikely = analysis.probability < opts.unlikely_threshold;
+        let top_moves = analysis
+            .top_moves
+            .iter()
+            .map(|mv| format!("{}:{:.3}", mv.mv.to_csa(), mv.probability))
+            .collect::<Vec<_>>()
+            .join(" ");
+        println!(
+            "{:>4} {:<12} {:>4} {:>7.4} {:>7.3}  {}{}",
+            i + 1,
+            analysis.mv.to_csa(),
+            analysis.rank,
+            analysis.probability,
+            analysis.entropy,
+            top_moves,
+            if unlikely { "  <- unlikely" } else { "" }
+        );
+
+        let top_moves = analysis
+            .top_moves
+            .iter()
+            .map(|mv| {
+                let kif = format_kif_move(&mv.mv.mv, mv.mv.promoted, None);
+                format!("{} {:.3}", kif, mv.probability)
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        let mut comment = format!(
+            "順位 {} 確率 {:.4} エントロピー {:.3} 候補 {}",
+            analysis.rank, analysis.probability, analysis.entropy, top_moves
+        );
+        if unlikely {
+            comment += " ※予想外の手";
+        }
+        record.push(kif, 0, Some(comment));
+    }
+
+    record.winner = kifu.winner;
+    if kifu.winner.is_some() {
+        record.end = Some("投了".to_string());
+    }
+    if let Some(output) = opts.output.as_ref() {
+        write!(File::create(output)?, "{}", record)?;
+    }
+
+    println!();
+    println!(
+        "{:<6} {:>5} {:>6} {:>6} {:>9} {:>8} {:>8}",
+        "side", "moves", "top1", "top5", "unlikely", "avg_prob", "entropy"
+    );
+    let summaries = summarize(&analyses, opts.unlikely_threshold);
+    for (color, summary) in [Color::Black, Color::White].iter().zip(summaries.iter()) {
+        if summary.moves == 0 {
+            continue;
+        }
+        println!(
+            "{:<6} {:>5} {:>6.3} {:>6.3} {:>9} {:>8.4} {:>8.3}",
+            format!("{:?}", color),
+            summary.moves,
+            summary.top1 as f64 / summary.moves as f64,
+            summary.top5 as f64 / summary.moves as f64,
+            summary.unlikely,
+            summary.mean_probability(),
+            summary.mean_entropy()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,213 @@
+use anyhow::{bail, Result};
+use clap::Clap;
+use shogiutil::Color;
+use std::env;
+use std::fs::{create_dir_all, read_to_string, write};
+use std::path::PathBuf;
+use std::time::Instant;
+use super_duper_dragon::csa::{illegal_action, CsaRecord};
+use super_duper_dragon::elo::MatchResult;
+use super_duper_dragon::game::{opponent, Game, Sennichite};
+use super_duper_dragon::usi::GameOver;
+use super_duper_dragon::usi_engine::UsiEngine;
+
+#[derive(Clap)]
+#[clap(version = "1.0", author = "kenkoooo <kenkou.n@gmail.com>")]
+struct Opts {
+    /// Command line of the first engine, e.g. "target/release/policy_player -m new.bin".
+    #[clap(long)]
+    engine1: String,
+    #[clap(long)]
+    engine2: String,
+    /// Number of games. Each opening is played twice with colors swapped.
+    #[clap(short, long, default_value = "100")]
+    games: usize,
+    /// File of openings, one USI position such as "startpos moves 7g7f 3c3d" per line.
+    #[clap(long)]
+    openings: Option<String>,
+    #[clap(long, default_value = "1000")]
+    byoyomi: u64,
+    /// Games reaching this number of moves are drawn.
+    #[clap(long, default_value = "256")]
+    max_moves: usize,
+    /// Directory to write the games to as CSA.
+    #[clap(long, default_value = "./arena")]
+    out_dir: String,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Outcome {
+    Win(Color),
+    Draw,
+}
+
+impl Outcome {
+    fn result_for(&self, color: Color) -> GameOver {
+        match self {
+            Outcome::Win(winner) if *winner == color => GameOver::Win,
+            Outcome::Win(_) => GameOver::Lose,
+            Outcome::Draw => GameOver::Draw,
+        }
+    }
+}
+
+/// Plays a game and tells both engines its result.
+fn play_game(
+    black: &mut UsiEngine,
+    white: &mut UsiEngine,
+    opening: &str,
+    opts: &Opts,
+) -> Result<(Outcome, CsaRecord)> {
+    let (outcome, record) = play_moves(black, white, opening, opts)?;
+    black.game_over(outcome.result_for(Color::Black))?;
+    white.game_over(outcome.result_for(Color::White))?;
+    Ok((outcome, record))
+}
+
+fn play_moves(
+    black: &mut UsiEngine,
+    white: &mut UsiEngine,
+    opening: &str,
+    opts: &Opts,
+) -> Result<(Outcome, CsaRecord)> {
+    let mut game = Game::from_opening(opening)?;
+    let mut record = CsaRecord::new(&black.name, &white.name);
+    record.event = Some("arena".to_string());
+    for mv in game.moves() {
+        record.push(mv.to_csa(), None);
+    }
+
+    black.is_ready()?;
+    white.is_ready()?;
+    black.new_game()?;
+    white.new_game()?;
+
+    loop {
+        let next_turn = game.next_turn();
+        if game.legal_moves().is_empty() {
+            record.result = Some("%TSUMI".to_string());
+            return Ok((Outcome::Win(opponent(next_turn)), record));
+        }
+        if game.moves().len() >= opts.max_moves {
+            record.result = Some("%MAX_MOVES".to_string());
+            return Ok((Outcome::Draw, record));
+        }
+
+        let engine = if next_turn == Color::Black {
+            &mut *black
+        } else {
+            &mut *white
+        };
+        let start = Instant::now();
+        let bestmove = engine.go(&game.position_command(), opts.byoyomi)?;
+        let seconds = start.elapsed().as_secs();
+        match bestmove.as_str() {
+            "resign" => {
+                record.result = Some("%TORYO".to_string());
+                return Ok((Outcome::Win(opponent(next_turn)), record));
+            }
+            "win" => {
+                if game.can_declare_win() {
+                    record.result = Some("%KACHI".to_string());
+                    return Ok((Outcome::Win(next_turn), record));
+                }
+                log::warn!("{}: declared a win without the conditions", engine.name);
+                record.result = Some(illegal_action(next_turn));
+                return Ok((Outcome::Win(opponent(next_turn)), record));
+            }
+            usi => match game.play_usi(usi) {
+                Ok(mv) => record.push(mv.to_csa(), Some(seconds)),
+                Err(e) => {
+                    log::warn!("{}: {}", engine.name, e);
+                    record.result = Some("%ILLEGAL_MOVE".to_string());
+                    return Ok((Outcome::Win(opponent(next_turn)), record));
+                }
+            },
+        }
+
+        match game.sennichite() {
+            Some(Sennichite::Draw) => {
+                record.result = Some("%SENNICHITE".to_string());
+                return Ok((Outcome::Draw, record));
+            }
+            Some(Sennichite::PerpetualCheck(checker)) => {
+                record.result = Some(illegal_action(checker));
+                return Ok((Outcome::Win(opponent(checker)), record));
+            }
+            None => {}
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    env::set_var("RUST_LOG", "info");
+    env_logger::init();
+    let opts: Opts = Opts::parse();
+    if opts.games == 0 {
+        bail!("--games must be at least 1");
+    }
+
+    let openings = match opts.openings.as_ref() {
+        Some(path) => read_to_string(path)?
+            .split("\n")
+            .map(|line| line.trim().trim_start_matches("position ").to_string())
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>(),
+        None => vec!["startpos".to_string()],
+    };
+    create_dir_all(&opts.out_dir)?;
+
+    let mut engine1 = UsiEngine::spawn(&opts.engine1)?;
+    let mut engine2 = UsiEngine::spawn(&opts.engine2)?;
+    log::info!("{} vs {}", engine1.name, engine2.name);
+
+    let mut result = MatchResult::default();
+    for i in 0..opts.games {
+        let opening = &openings[(i / 2) % openings.len()];
+        let engine1_is_black = i % 2 == 0;
+        let (outcome, record) = if engine1_is_black {
+            play_game(&mut engine1, &mut engine2, opening, &opts)?
+        } else {
+            play_game(&mut engine2, &mut engine1, opening, &opts)?
+        };
+
+        let engine1_color = if engine1_is_black {
+            Color::Black
+        } else {
+            Color::White
+        };
+        match outcome {
+            Outcome::Win(color) if color == engine1_color => result.wins += 1,
+            Outcome::Win(_) => result.losses += 1,
+            Outcome::Draw => result.draws += 1,
+        }
+
+        let path = PathBuf::from(&opts.out_dir).join(format!("{:05}.csa", i));
+        write(&path, record.to_string())?;
+        log::info!(
+            "game={} {:?} moves={} {} W={} D={} L={}",
+            i,
+            outcome,
+            record.moves.len(),
+            record.result.as_deref().unwrap_or(""),
+            result.wins,
+            result.draws,
+            result.losses
+        );
+    }
+
+    let (lower, upper) = result.elo_interval();
+    println!(
+        "{} vs {}: W={} D={} L={} score={:.3} elo={:.1} [{:.1}, {:.1}]",
+        engine1.name,
+        engine2.name,
+        result.wins,
+        result.draws,
+        result.losses,
+        result.score(),
+        result.elo(),
+        lower,
+        upper
+    );
+    Ok(())
+}
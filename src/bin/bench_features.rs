@@ -0,0 +1,133 @@
+//! Compares building input batches with `PositionBatches` against the nested `Vec` expansion
+//! `ToFlatVec` used to do, which is kept here as the baseline.
+use anyhow::Result;
+use clap::Clap;
+use rand::prelude::*;
+use std::env;
+use std::time::Instant;
+use super_duper_dragon::data_loader::{load_bin_file, PositionBatches};
+use super_duper_dragon::features::{FeatureSet, InputFeatures};
+use super_duper_dragon::game::Game;
+use super_duper_dragon::model::Position;
+use tch::Tensor;
+
+#[derive(Clap)]
+#[clap(version = "1.0", author = "kenkoooo <kenkou.n@gmail.com>")]
+struct Opts {
+    /// Positions written by `read_kifu`. Positions of random games are used if not given.
+    #[clap(short, long)]
+    data: Option<String>,
+    /// Number of positions of random games.
+    #[clap(long, default_value = "100000")]
+    positions: usize,
+    #[clap(short, long, default_value = "1024")]
+    batchsize: usize,
+    /// Every implementation builds all batches this many times and the fastest is reported.
+    #[clap(long, default_value = "5")]
+    repeat: usize,
+    #[clap(long, default_value = "717")]
+    seed: u64,
+    /// Feature set of `data`, which must have no history, and of the positions of random games.
+    #[clap(long, default_value = "v1")]
+    feature_set: FeatureSet,
+}
+
+fn nested_to_flat_vec(planes: &[u128]) -> Vec<f32> {
+    let mut features = vec![vec![vec![0.0f32; 9]; 9]; planes.len()];
+    for (channel, &board) in planes.iter().enumerate() {
+        for i in 0..9 {
+            for j in 0..9 {
+                let pos = i * 9 + j;
+                if board & (1 << pos) != 0 {
+                    features[channel][i][j] = 1.0;
+                }
+            }
+        }
+    }
+    features
+        .into_iter()
+        .flat_map(|board| board.into_iter().flat_map(|row| row))
+        .collect()
+}
+
+fn nested_batches(positions: &[Position], batchsize: usize) -> impl Iterator<Item = Tensor> + '_ {
+    positions.chunks_exact(batchsize).map(|batch| {
+        let mut data = vec![];
+        for position in batch {
+            data.extend(nested_to_flat_vec(&position.features));
+        }
+        Tensor::of_slice(&data)
+    })
+}
+
+fn random_positions<R: Rng>(
+    n: usize,
+    input_features: InputFeatures,
+    rng: &mut R,
+) -> Result<Vec<Position>> {
+    let mut positions = vec![];
+    let mut game = Game::new();
+    while positions.len() < n {
+        let moves = game.legal_moves();
+        if moves.is_empty() || game.moves().len() >= 256 {
+            game = Game::new();
+            continue;
+        }
+        let features =
+            input_features.encode(game.board(), game.next_turn(), &game.feature_context());
+        positions.push(Position {
+            move_number: (game.moves().len() + 1) as u16,
+            ..Position::new(features, 0)
+        });
+        let mv = moves.choose(rng).unwrap();
+        game.play_usi(&mv.to_usi())?;
+    }
+    Ok(positions)
+}
+
+/// Positions per second of the fastest of `repeat` runs of `build`, which returns the number of
+/// positions built.
+fn measure<F: FnMut() -> usize>(repeat: usize, mut build: F) -> f64 {
+    (0..repeat)
+        .map(|_| {
+            let start = Instant::now();
+            let positions = build();
+            positions as f64 / start.elapsed().as_secs_f64()
+        })
+        .fold(0.0, f64::max)
+}
+
+fn main() -> Result<()> {
+    env::set_var("RUST_LOG", "info");
+    env_logger::init();
+    let opts: Opts = Opts::parse();
+
+    let input_features = InputFeatures::from(opts.feature_set);
+    let positions = match opts.data.as_ref() {
+        Some(data) => load_bin_file(data)?,
+        None => {
+            let mut rng = StdRng::seed_from_u64(opts.seed);
+            random_positions(opts.positions, input_features, &mut rng)?
+        }
+    };
+    log::info!("positions = {}", positions.len());
+    let batchsize = opts.batchsize;
+
+    let nested = measure(opts.repeat, || {
+        nested_batches(&positions, batchsize)
+            .map(|x| x.size()[0] as usize)
+            .sum::<usize>()
+            / (input_features.bit_planes() * 9 * 9)
+    });
+    let batched = measure(opts.repeat, || {
+        PositionBatches::new(&positions, input_features, batchsize)
+            .map(|(_, t)| t.size()[0] as usize)
+            .sum()
+    });
+
+    println!("{:<16} {:>14}", "", "positions/sec");
+    println!("{:<16} {:>14.0}", "nested Vec", nested);
+    println!("{:<16} {:>14.0}", "PositionBatches", batched);
+    println!("speedup {:.2}x", batched / nested);
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use anyhow::Result;
+use clap::Clap;
+use std::env;
+use std::time::Duration;
+use super_duper_dragon::csa_client::{run, CsaClientConfig, GameEnd};
+use super_duper_dragon::network::config::ModelConfig;
+use super_duper_dragon::network::policy::PolicyNetwork;
+use super_duper_dragon::player::PolicyPlayer;
+use super_duper_dragon::sampling::MoveSampler;
+use tch::nn::VarStore;
+use tch::Device;
+
+#[derive(Clap)]
+#[clap(version = "1.0", author = "kenkoooo <kenkou.n@gmail.com>")]
+struct Opts {
+    #[clap(short, long)]
+    model_filepath: String,
+    #[clap(long, default_value = "wdoor.c.u-tokyo.ac.jp")]
+    host: String,
+    #[clap(long, default_value = "4081")]
+    port: u16,
+    #[clap(long)]
+    name: String,
+    /// Floodgate uses `floodgate-600-10F,<password>` to choose the game.
+    #[clap(long)]
+    password: String,
+    #[clap(short, long, default_value = "1")]
+    games: usize,
+    #[clap(long, default_value = "5")]
+    reconnect_attempts: usize,
+    /// Seconds to wait before reconnecting.
+    #[clap(long, default_value = "30")]
+    reconnect_wait: u64,
+    #[clap(long, default_value = "717")]
+    seed: u64,
+}
+
+fn main() -> Result<()> {
+    env::set_var("RUST_LOG", "info");
+    env_logger::init();
+    let opts: Opts = Opts::parse();
+
+    let mut vs = VarStore::new(Device::Cuda(0));
+    let config = ModelConfig::load_for(&opts.model_filepath)?;
+    let model = PolicyNetwork::new(&vs.root(), &config);
+    vs.load(&opts.model_filepath)?;
+    let mut player = PolicyPlayer::new(model, vs, MoveSampler::default(), opts.seed);
+
+    let config = CsaClientConfig {
+        host: opts.host,
+        port: opts.port,
+        name: opts.name,
+        password: opts.password,
+        games: opts.games,
+        reconnect_attempts: opts.reconnect_attempts,
+        reconnect_wait: Duration::from_secs(opts.reconnect_wait),
+    };
+    let results = run(&config, &mut player)?;
+    let count = |end| results.iter().filter(|result| result.end == end).count();
+    println!(
+        "W={} D={} L={} CHUDAN={}",
+        count(GameEnd::Win),
+        count(GameEnd::Draw),
+        count(GameEnd::Lose),
+        count(GameEnd::Chudan)
+    );
+    Ok(())
+}
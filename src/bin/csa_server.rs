@@ -0,0 +1,52 @@
+use anyhow::Result;
+use clap::Clap;
+use std::env;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use super_duper_dragon::csa_server::{serve, CsaServerConfig};
+
+#[derive(Clap)]
+#[clap(version = "1.0", author = "kenkoooo <kenkou.n@gmail.com>")]
+struct Opts {
+    #[clap(long, default_value = "4081")]
+    port: u16,
+    /// Total time of each player in seconds.
+    #[clap(long, default_value = "600")]
+    total_time: u64,
+    #[clap(long, default_value = "10")]
+    byoyomi: u64,
+    #[clap(long, default_value = "256")]
+    max_moves: usize,
+    #[clap(short, long)]
+    out_dir: Option<PathBuf>,
+    #[clap(long)]
+    standings: Option<PathBuf>,
+    /// Number of games to play before exiting. Runs forever if omitted.
+    #[clap(short, long)]
+    games: Option<usize>,
+    #[clap(long, default_value = "1500")]
+    initial_rate: f64,
+}
+
+fn main() -> Result<()> {
+    env::set_var("RUST_LOG", "info");
+    env_logger::init();
+    let opts: Opts = Opts::parse();
+
+    let listener = TcpListener::bind(("0.0.0.0", opts.port))?;
+    log::info!("Listening on port {}", opts.port);
+    let config = CsaServerConfig {
+        total_time: opts.total_time,
+        byoyomi: opts.byoyomi,
+        max_moves: opts.max_moves,
+        out_dir: opts.out_dir,
+        standings_path: opts.standings,
+        games: opts.games,
+        initial_rate: opts.initial_rate,
+    };
+    let results = serve(listener, config)?;
+    for result in results.iter() {
+        println!("{} {} {:?}", result.game_id, result.reason, result.winner);
+    }
+    Ok(())
+}
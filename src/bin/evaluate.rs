@@ -0,0 +1,48 @@
+use anyhow::Result;
+use clap::Clap;
+use std::env;
+use super_duper_dragon::data_loader::{load_bin_file, load_legal_moves};
+use super_duper_dragon::evaluation::evaluate;
+use super_duper_dragon::network::config::ModelConfig;
+use super_duper_dragon::network::policy::PolicyNetwork;
+use tch::nn::VarStore;
+use tch::Device;
+
+#[derive(Clap)]
+#[clap(version = "1.0", author = "kenkoooo <kenkou.n@gmail.com>")]
+struct Opts {
+    #[clap(short, long)]
+    model_filepath: String,
+    /// Positions written by `read_kifu`. The legal accuracy is reported if the legal moves were
+    /// written next to them.
+    #[clap(short, long)]
+    data: String,
+    #[clap(short, long, default_value = "1024")]
+    batchsize: usize,
+}
+
+fn main() -> Result<()> {
+    env::set_var("RUST_LOG", "info");
+    env_logger::init();
+    let opts: Opts = Opts::parse();
+
+    let positions = load_bin_file(&opts.data)?;
+    log::info!("data = {}", positions.len());
+    let legal_moves = load_legal_moves(&opts.data, &positions)?;
+
+    let mut vs = VarStore::new(Device::Cuda(0));
+    let config = ModelConfig::load_for(&opts.model_filepath)?;
+    let model = PolicyNetwork::new(&vs.root(), &config);
+    vs.load(&opts.model_filepath)?;
+
+    let metrics = evaluate(
+        &positions,
+        legal_moves.as_deref(),
+        config.input_features(),
+        opts.batchsize,
+        &model,
+        vs.device(),
+    );
+    print!("{}", metrics);
+    Ok(())
+}
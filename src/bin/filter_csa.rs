@@ -4,6 +4,7 @@ use rand::prelude::{SliceRandom, StdRng};
 use rand::SeedableRng;
 use std::fs::{read_dir, read_to_string};
 use std::path::Path;
+use super_duper_dragon::util::rating::parse_ratings;
 
 #[derive(Clap)]
 #[clap(version = "1.0", author = "kenkoooo <kenkou.n@gmail.com>")]
@@ -22,25 +23,13 @@ struct KifuInfo<P> {
 }
 
 fn load_kifu_info<P: AsRef<Path>>(kifu_path: P) -> Option<KifuInfo<P>> {
-    let rate_pattern = regex::Regex::new("^'(black|white)_rate:.*:(.*)").ok()?;
     let content = read_to_string(&kifu_path).ok()?;
+    let ratings = parse_ratings(&content);
 
-    let mut black_rate = None;
-    let mut white_rate = None;
     let mut is_resign = false;
     let mut moves = 0;
 
     for line in content.split("\n") {
-        if let Some(caps) = rate_pattern.captures(line) {
-            let side = &caps[1];
-            let rate = caps[2].parse::<f64>().ok()?;
-            if side == "black" {
-                black_rate = Some(rate);
-            } else {
-                white_rate = Some(rate);
-            }
-        }
-
         if line.len() > 0 && (&line[..1] == "+" || &line[..1] == "-") {
             moves += 1
         }
@@ -49,8 +38,8 @@ fn load_kifu_info<P: AsRef<Path>>(kifu_path: P) -> Option<KifuInfo<P>> {
         }
     }
 
-    let black_rate = black_rate?;
-    let white_rate = white_rate?;
+    let black_rate = ratings.black?;
+    let white_rate = ratings.white?;
 
     if !is_resign || moves <= 50 || min(black_rate, white_rate) < 3000.0 {
         None
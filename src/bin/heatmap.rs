@@ -0,0 +1,67 @@
+use anyhow::Result;
+use clap::Clap;
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use super_duper_dragon::game::Game;
+use super_duper_dragon::heatmap::Heatmap;
+use super_duper_dragon::inference::{policy_distribution, predict};
+use super_duper_dragon::network::config::ModelConfig;
+use super_duper_dragon::network::policy::PolicyNetwork;
+use tch::nn::VarStore;
+use tch::Device;
+
+#[derive(Clap)]
+#[clap(version = "1.0", author = "kenkoooo <kenkou.n@gmail.com>")]
+struct Opts {
+    #[clap(short, long)]
+    model_filepath: String,
+    /// USI position such as `startpos moves 7g7f 3c3d`.
+    #[clap(short, long, default_value = "startpos")]
+    position: String,
+    /// Writes an SVG instead of printing an ANSI heatmap.
+    #[clap(long)]
+    svg: Option<String>,
+    /// Number of moves drawn as arrows.
+    #[clap(long, default_value = "5")]
+    top: usize,
+}
+
+fn main() -> Result<()> {
+    env::set_var("RUST_LOG", "info");
+    env_logger::init();
+    let opts: Opts = Opts::parse();
+
+    let mut vs = VarStore::new(Device::Cuda(0));
+    let config = ModelConfig::load_for(&opts.model_filepath)?;
+    let model = PolicyNetwork::new(&vs.root(), &config);
+    vs.load(&opts.model_filepath)?;
+
+    let game = Game::from_opening(&opts.position)?;
+    let (board, next_turn) = (game.board(), game.next_turn());
+    let context = game.feature_context();
+    let probabilities = policy_distribution(
+        &model,
+        vs.device(),
+        config.input_features(),
+        board,
+        next_turn,
+        &context,
+    );
+    let mut top_moves = predict(
+        &model,
+        vs.device(),
+        config.input_features(),
+        board,
+        next_turn,
+        &context,
+    );
+    top_moves.truncate(opts.top);
+    let heatmap = Heatmap::new(&probabilities, next_turn, top_moves);
+
+    match opts.svg.as_ref() {
+        Some(svg) => write!(File::create(svg)?, "{}", heatmap.to_svg(board))?,
+        None => print!("{}", heatmap.to_ansi(board)),
+    }
+    Ok(())
+}
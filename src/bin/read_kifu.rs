@@ -1,14 +1,18 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use clap::Clap;
 use shogiutil::{parse_csa_string, Board, Color};
 use std::env;
 use std::fs::{read_to_string, File};
 use std::io::Write;
 use std::path::{Path, PathBuf};
-use super_duper_dragon::model::Position;
+use super_duper_dragon::data_loader::legal_moves_path_for;
+use super_duper_dragon::features::{
+    ContextTracker, FeatureSet, History, InputFeatures, MAX_HISTORY,
+};
+use super_duper_dragon::model::{set_auxiliary_targets, Position};
 use super_duper_dragon::progressbar::ToProgressBar;
-use super_duper_dragon::util::board_packer::BoardPacker;
-use super_duper_dragon::util::make_output_label::make_output_label;
+use super_duper_dragon::util::make_output_label::{legal_move_labels, make_output_label};
+use super_duper_dragon::util::rating::parse_ratings;
 
 #[derive(Clap)]
 #[clap(version = "1.0", author = "kenkoooo <kenkou.n@gmail.com>")]
@@ -17,20 +21,41 @@ struct Opts {
     train: String,
     #[clap(long)]
     test: String,
+    /// v1 or v2. `train_policy` must be run with the same feature set and history.
+    #[clap(long, default_value = "v1")]
+    feature_set: FeatureSet,
+    /// Number of past positions stacked on the planes of the feature set.
+    #[clap(long, default_value = "0")]
+    history_positions: usize,
+    /// Number of past moves stacked as their from and to squares.
+    #[clap(long, default_value = "0")]
+    history_moves: usize,
 }
 
-fn read_single_kifu<P: AsRef<Path>>(filepath: P) -> Result<Vec<Position>> {
+fn read_single_kifu<P: AsRef<Path>>(
+    filepath: P,
+    game_id: u32,
+    input_features: InputFeatures,
+) -> Result<(Vec<Position>, Vec<Vec<i16>>)> {
     let content = read_to_string(filepath)?;
     let kifu = parse_csa_string(&content)?;
+    let ratings = parse_ratings(&content);
+    let black_rate = ratings.black.map(|rate| rate as f32);
+    let white_rate = ratings.white.map(|rate| rate as f32);
     let winner = kifu.winner.expect("No winner");
     let mut data = vec![];
+    let mut legal_moves = vec![];
     let mut board = Board::default();
-    for mv in kifu.moves {
-        let features = if mv.color == Color::Black {
-            board.encode()
+    let mut contexts = ContextTracker::default();
+    for (i, mv) in kifu.moves.into_iter().enumerate() {
+        let context = contexts.context(&board, mv.color);
+        let features = input_features.encode(&board, mv.color, &context);
+        legal_moves.push(if mv.color == Color::Black {
+            legal_move_labels(&board)
         } else {
-            board.rotate180().encode()
-        };
+            legal_move_labels(&board.rotate180())
+        });
+        contexts.push(board.clone(), &mv);
         let result = board.push_move(mv.clone())?;
         let move_label = if mv.color == Color::Black {
             make_output_label(&mv.from, &mv.to, mv.piece, result.promoted)
@@ -44,30 +69,61 @@ fn read_single_kifu<P: AsRef<Path>>(filepath: P) -> Result<Vec<Position>> {
         };
 
         let is_winner_turn = mv.color == winner;
+        let (rate, opponent_rate) = if mv.color == Color::Black {
+            (black_rate, white_rate)
+        } else {
+            (white_rate, black_rate)
+        };
         data.push(Position {
             is_winner_turn,
             move_label,
-            features: features.to_vec(),
+            features,
+            rate,
+            opponent_rate,
+            move_number: (i + 1) as u16,
+            game_id,
+            piece: mv.piece.to_usize() as u8,
+            opponent_move_label: None,
+            game_length: 0,
+            policy_target: vec![],
         });
     }
-    Ok(data)
+    set_auxiliary_targets(&mut data);
+    Ok((data, legal_moves))
 }
 
-fn read_and_write<P: AsRef<Path>>(kifu_list_filepath: P, bin_filepath: P) -> Result<()> {
+fn read_and_write<P: AsRef<Path>>(
+    kifu_list_filepath: P,
+    bin_filepath: P,
+    input_features: InputFeatures,
+    write_legal_moves: bool,
+) -> Result<()> {
     let mut train_data = vec![];
+    let mut all_legal_moves = vec![];
     let kifu_list = read_to_string(kifu_list_filepath)?;
     let kifu_list = kifu_list.split("\n").collect::<Vec<_>>();
-    for filepath in kifu_list.iter().progress(|state| log::info!("{}", state)) {
+    for (game_id, filepath) in kifu_list
+        .iter()
+        .enumerate()
+        .progress(|state| log::info!("{}", state))
+    {
         if filepath.is_empty() {
             continue;
         }
-        let data = read_single_kifu(filepath)?;
+        let (data, legal_moves) = read_single_kifu(filepath, game_id as u32, input_features)?;
         train_data.extend(data);
+        if write_legal_moves {
+            all_legal_moves.extend(legal_moves);
+        }
     }
 
-    let mut file = File::create(bin_filepath)?;
+    let mut file = File::create(&bin_filepath)?;
     let bin = bincode::serialize(&train_data)?;
     file.write_all(&bin)?;
+    if write_legal_moves {
+        let mut file = File::create(legal_moves_path_for(&bin_filepath))?;
+        file.write_all(&bincode::serialize(&all_legal_moves)?)?;
+    }
     Ok(())
 }
 
@@ -76,12 +132,24 @@ fn main() -> Result<()> {
     env_logger::init();
     let opts: Opts = Opts::parse();
 
+    let input_features = InputFeatures {
+        feature_set: opts.feature_set,
+        history: History {
+            positions: opts.history_positions,
+            moves: opts.history_moves,
+        },
+    };
+    if opts.history_positions > MAX_HISTORY || opts.history_moves > MAX_HISTORY {
+        bail!("At most {} past positions and moves are kept", MAX_HISTORY);
+    }
+
     let train_list = PathBuf::from(opts.train);
     let train_save = train_list.with_extension("bin");
-    read_and_write(train_list, train_save)?;
+    read_and_write(train_list, train_save, input_features, false)?;
 
     let test_list = PathBuf::from(opts.test);
     let test_save = test_list.with_extension("bin");
-    read_and_write(test_list, test_save)?;
+    // Only the test data needs the legal moves, for the legal accuracy of the evaluation.
+    read_and_write(test_list, test_save, input_features, true)?;
     Ok(())
 }
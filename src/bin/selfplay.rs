@@ -0,0 +1,219 @@
+use anyhow::{bail, Result};
+use clap::Clap;
+use rand::prelude::*;
+use shogiutil::Color;
+use std::env;
+use std::fs::{create_dir_all, write, File};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use std::thread;
+use super_duper_dragon::csa::{illegal_action, CsaRecord};
+use super_duper_dragon::game::{opponent, Game, Sennichite};
+use super_duper_dragon::inference_queue::{BatchConfig, InferenceQueue};
+use super_duper_dragon::model::{set_auxiliary_targets, Position};
+use super_duper_dragon::network::config::ModelConfig;
+use super_duper_dragon::network::policy::PolicyNetwork;
+use super_duper_dragon::progressbar::ToProgressBar;
+use super_duper_dragon::sampling::sample_with_temperature;
+use tch::nn::VarStore;
+use tch::Device;
+
+#[derive(Clap)]
+#[clap(version = "1.0", author = "kenkoooo <kenkou.n@gmail.com>")]
+struct Opts {
+    #[clap(short, long)]
+    model_filepath: String,
+    #[clap(short, long, default_value = "100")]
+    games: usize,
+    /// Positions are written in the same format as `read_kifu` with the feature set of the model,
+    /// to be used by `train_policy`, along with the policy of the model as their target. Positions
+    /// of drawn games are kept.
+    #[clap(short, long)]
+    out: String,
+    /// Softmax temperature of the policy. Zero always plays the most probable move.
+    #[clap(short, long, default_value = "1.0")]
+    temperature: f64,
+    /// Games reaching this number of moves are drawn.
+    #[clap(long, default_value = "256")]
+    max_moves: usize,
+    /// The n-th game samples with seed `seed + n`.
+    #[clap(long, default_value = "717")]
+    seed: u64,
+    /// Number of games played at the same time, whose positions are evaluated in one batch.
+    #[clap(long, default_value = "1")]
+    threads: usize,
+    /// Also write every game as CSA to the given directory.
+    #[clap(long)]
+    csa_dir: Option<String>,
+}
+
+#[derive(Clone)]
+struct SelfPlayer {
+    queue: InferenceQueue,
+    temperature: f64,
+    max_moves: usize,
+}
+
+impl SelfPlayer {
+    /// Plays a game and returns its positions with the winner, `None` if the game is drawn.
+    fn play<R: Rng>(
+        &self,
+        game_id: u32,
+        rng: &mut R,
+    ) -> Result<(Vec<Position>, Option<Color>, CsaRecord)> {
+        let mut game = Game::new();
+        let mut record = CsaRecord::new("selfplay", "selfplay");
+        let mut positions = vec![];
+        let mut colors = vec![];
+
+        let winner = loop {
+            let next_turn = game.next_turn();
+            if game.moves().len() >= self.max_moves {
+                record.result = Some("%MAX_MOVES".to_string());
+                break None;
+            }
+            match game.sennichite() {
+                Some(Sennichite::Draw) => {
+                    record.result = Some("%SENNICHITE".to_string());
+                    break None;
+                }
+                Some(Sennichite::PerpetualCheck(checker)) => {
+                    record.result = Some(illegal_action(checker));
+                    break Some(opponent(checker));
+                }
+                None => {}
+            }
+
+            let context = game.feature_context();
+            let moves = self.queue.predict(game.board(), next_turn, &context)?;
+            if moves.is_empty() {
+                record.result = Some("%TSUMI".to_string());
+                break Some(opponent(next_turn));
+            }
+            let logits = moves.iter().map(|mv| mv.logit).collect::<Vec<_>>();
+            let chosen = &moves[sample_with_temperature(&logits, self.temperature, rng)];
+
+            // The policy restricted to the legal moves.
+            let legal_probability = moves.iter().map(|mv| mv.probability).sum::<f64>();
+            let policy_target = moves
+                .iter()
+                .map(|mv| (mv.label, (mv.probability / legal_probability) as f32))
+                .collect();
+            let features = self
+                .queue
+                .input_features()
+                .encode(game.board(), next_turn, &context);
+            positions.push(Position {
+                features,
+                is_winner_turn: false,
+                move_label: chosen.label,
+                rate: None,
+                opponent_rate: None,
+                move_number: (game.moves().len() + 1) as u16,
+                game_id,
+                piece: chosen.mv.mv.piece.to_usize() as u8,
+                opponent_move_label: None,
+                game_length: 0,
+                policy_target,
+            });
+            colors.push(next_turn);
+
+            game.play_usi(&chosen.mv.to_usi())?;
+            record.push(chosen.mv.to_csa(), None);
+        };
+
+        // Neither player of a drawn game is the winner.
+        for (position, &color) in positions.iter_mut().zip(colors.iter()) {
+            position.is_winner_turn = Some(color) == winner;
+        }
+        set_auxiliary_targets(&mut positions);
+        Ok((positions, winner, record))
+    }
+}
+
+fn main() -> Result<()> {
+    env::set_var("RUST_LOG", "info");
+    env_logger::init();
+    let opts: Opts = Opts::parse();
+    if opts.threads == 0 {
+        bail!("--threads must be at least 1");
+    }
+
+    let mut vs = VarStore::new(Device::Cuda(0));
+    let config = ModelConfig::load_for(&opts.model_filepath)?;
+    let model = PolicyNetwork::new(&vs.root(), &config);
+    vs.load(&opts.model_filepath)?;
+    if let Some(dir) = opts.csa_dir.as_ref() {
+        create_dir_all(dir)?;
+    }
+
+    let queue = InferenceQueue::spawn(
+        model,
+        vs,
+        BatchConfig {
+            max_batch_size: opts.threads,
+            ..Default::default()
+        },
+    );
+    let player = SelfPlayer {
+        queue: queue.clone(),
+        temperature: opts.temperature,
+        max_moves: opts.max_moves,
+    };
+
+    let next_game = Arc::new(AtomicUsize::new(0));
+    let (sender, receiver) = channel();
+    for _ in 0..opts.threads {
+        let player = player.clone();
+        let next_game = next_game.clone();
+        let sender = sender.clone();
+        let (games, seed) = (opts.games, opts.seed);
+        thread::spawn(move || loop {
+            let game_id = next_game.fetch_add(1, Ordering::SeqCst);
+            if game_id >= games {
+                return;
+            }
+            let mut rng = StdRng::seed_from_u64(seed.wrapping_add(game_id as u64));
+            let result = player.play(game_id as u32, &mut rng);
+            if sender.send((game_id, result)).is_err() {
+                return;
+            }
+        });
+    }
+    drop(sender);
+
+    // Zipped with the range only to give the progress bar the number of remaining games.
+    let mut games = (0..opts.games)
+        .zip(receiver.iter())
+        .map(|(_, game)| game)
+        .progress(|state| log::info!("{}", state))
+        .collect::<Vec<_>>();
+    games.sort_by_key(|&(game_id, _)| game_id);
+    let mut data = vec![];
+    let mut draws = 0;
+    for (game_id, result) in games {
+        let (positions, winner, record) = result?;
+        if winner.is_none() {
+            draws += 1;
+        }
+        data.extend(positions);
+        if let Some(dir) = opts.csa_dir.as_ref() {
+            let path = PathBuf::from(dir).join(format!("{:05}.csa", game_id));
+            write(path, record.to_string())?;
+        }
+    }
+    log::info!(
+        "positions={} draws={} average_batch_size={:.1}",
+        data.len(),
+        draws,
+        queue.average_batch_size()
+    );
+
+    let mut file = File::create(&opts.out)?;
+    let bin = bincode::serialize(&data)?;
+    file.write_all(&bin)?;
+    Ok(())
+}
@@ -0,0 +1,53 @@
+use anyhow::Result;
+use clap::Clap;
+use std::env;
+use std::net::TcpListener;
+use std::time::Duration;
+use super_duper_dragon::inference_queue::{BatchConfig, InferenceQueue};
+use super_duper_dragon::network::config::ModelConfig;
+use super_duper_dragon::network::policy::PolicyNetwork;
+use super_duper_dragon::policy_server::serve;
+use tch::nn::VarStore;
+use tch::Device;
+
+#[derive(Clap)]
+#[clap(version = "1.0", author = "kenkoooo <kenkou.n@gmail.com>")]
+struct Opts {
+    #[clap(short, long)]
+    model_filepath: String,
+    #[clap(long, default_value = "127.0.0.1")]
+    host: String,
+    #[clap(long, default_value = "8080")]
+    port: u16,
+    #[clap(long, default_value = "64")]
+    max_batch_size: usize,
+    /// Milliseconds to wait for more requests to fill a batch.
+    #[clap(long, default_value = "2")]
+    max_wait: u64,
+    /// Number of connections served concurrently.
+    #[clap(long, default_value = "16")]
+    workers: usize,
+}
+
+fn main() -> Result<()> {
+    env::set_var("RUST_LOG", "info");
+    env_logger::init();
+    let opts: Opts = Opts::parse();
+
+    let mut vs = VarStore::new(Device::Cuda(0));
+    let config = ModelConfig::load_for(&opts.model_filepath)?;
+    let model = PolicyNetwork::new(&vs.root(), &config);
+    vs.load(&opts.model_filepath)?;
+    let queue = InferenceQueue::spawn(
+        model,
+        vs,
+        BatchConfig {
+            max_batch_size: opts.max_batch_size,
+            max_wait: Duration::from_millis(opts.max_wait),
+        },
+    );
+
+    let listener = TcpListener::bind((opts.host.as_str(), opts.port))?;
+    log::info!("Listening on {}:{}", opts.host, opts.port);
+    serve(listener, queue, opts.workers)
+}
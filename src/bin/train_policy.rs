@@ -1,19 +1,33 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use clap::Clap;
 use rand::prelude::*;
 use std::env;
-use std::fs::File;
-use std::io::Read;
-use super_duper_dragon::constants::INPUT_CHANNELS;
-use super_duper_dragon::data_loader::DataLoader;
+use std::io::Write;
+use std::path::Path;
+use std::time::Instant;
+use super_duper_dragon::auxiliary::AuxiliaryLosses;
+use super_duper_dragon::checkpoint::{CheckPointPaths, TrainingState};
+use super_duper_dragon::data_loader::{
+    load_bin_file, load_legal_moves, policy_targets, write_batch_features, PositionBatches,
+};
+use super_duper_dragon::distillation::Distillation;
+use super_duper_dragon::evaluation::{evaluate, TOP_K};
+use super_duper_dragon::features::{FeatureSet, History, InputFeatures};
+use super_duper_dragon::lr_scheduler::{LrSchedule, LrScheduleKind, LrScheduler};
+use super_duper_dragon::metrics_sink::{MetricsRecord, MetricsSink};
+use super_duper_dragon::mixed_precision::{MixedPrecision, Precision};
 use super_duper_dragon::model::Position;
+use super_duper_dragon::network::config::{AuxiliaryHeads, ModelConfig};
 use super_duper_dragon::network::policy::PolicyNetwork;
+use super_duper_dragon::optimizer::{Optimizer, OptimizerKind, OptimizerOptions};
 use super_duper_dragon::progressbar::{ProgressBar, ToProgressBar};
-use super_duper_dragon::util::board_packer::ToFlatVec;
-use super_duper_dragon::util::{Accuracy, CheckPoint};
-use tch::kind::Kind::{Double, Int64};
-use tch::nn::{Module, OptimizerConfig, Sgd, VarStore};
-use tch::{no_grad, Device};
+use super_duper_dragon::tensorboard::SummaryWriter;
+use super_duper_dragon::util::make_output_label::describe_label;
+use super_duper_dragon::util::rating::RatingWeight;
+use super_duper_dragon::util::{weighted_cross_entropy, weighted_nll_loss, CheckPoint};
+use tch::kind::Kind::{Double, Float, Int64};
+use tch::nn::{Module, VarStore};
+use tch::{no_grad, Device, Tensor};
 
 #[derive(Clap)]
 #[clap(version = "1.0", author = "kenkoooo <kenkou.n@gmail.com>")]
@@ -32,114 +46,687 @@ struct Opts {
     epoch: usize,
     #[clap(short, long, default_value = "0.01")]
     learning_rate: f64,
-}
 
-fn load_bin_file(filepath: &str) -> Result<Vec<Position>> {
-    log::info!("Loading {}", filepath);
-    let mut f = File::open(filepath)?;
-    let mut buf = vec![];
-    f.read_to_end(&mut buf)?;
-    let kifu: Vec<Position> = bincode::deserialize(&buf)?;
-    Ok(kifu)
-}
+    /// sgd, adam or adamw
+    #[clap(long, default_value = "sgd")]
+    optimizer: OptimizerKind,
+    #[clap(long, default_value = "0")]
+    momentum: f64,
+    #[clap(long)]
+    nesterov: bool,
+    #[clap(long, default_value = "0")]
+    weight_decay: f64,
 
-fn validate(
-    test_kifu: &[Position],
-    batchsize: usize,
-    model: &PolicyNetwork,
-    device: Device,
-) -> f64 {
-    let mut accuracy = 0.0;
-    let mut iter = 0.0;
-    no_grad(|| {
-        let test_loader = DataLoader::new(test_kifu, position_to_features, batchsize);
-        for (x, t) in test_loader.progress(|state| log::info!("validation {}", state)) {
-            let x = x
-                .view((batchsize as i64, INPUT_CHANNELS as i64, 9, 9))
-                .to_device(device);
-            let t = t.to_device(device);
-            let y = model.forward(&x);
-            accuracy += y.accuracy(&t);
-            iter += 1.0;
-        }
-    });
-    accuracy / iter
+    /// constant, step, cosine or plateau
+    #[clap(long, default_value = "constant")]
+    lr_schedule: LrScheduleKind,
+    /// Linearly increase the learning rate from 0 over the first iterations.
+    #[clap(long, default_value = "0")]
+    warmup_iters: usize,
+    /// Decay interval of the step schedule.
+    #[clap(long, default_value = "5")]
+    lr_step_epochs: usize,
+    /// Decay factor of the step and plateau schedules.
+    #[clap(long, default_value = "0.1")]
+    lr_gamma: f64,
+    /// Epochs without validation improvement before the plateau schedule decays.
+    #[clap(long, default_value = "2")]
+    lr_patience: usize,
+    #[clap(long, default_value = "0")]
+    min_lr: f64,
+
+    /// Continue from the training state saved next to `save_file_path`.
+    #[clap(long)]
+    resume: bool,
+    /// Also save a checkpoint every given number of iterations, at the end of the optimizer step
+    /// of the iteration or of the first one after it. 0 disables it.
+    #[clap(long)]
+    checkpoint_interval: Option<usize>,
+    #[clap(long, default_value = "717")]
+    seed: u64,
+
+    /// Number of per-epoch checkpoints to keep as `<save_file_path>.epoch<N>`.
+    #[clap(long, default_value = "3")]
+    keep_checkpoints: usize,
+    /// Stop when the validation accuracy has not improved for the given number of epochs.
+    #[clap(long)]
+    early_stopping_patience: Option<usize>,
+
+    /// Append one row per eval interval and per epoch to a CSV file.
+    #[clap(long)]
+    metrics_csv: Option<String>,
+    /// Append the same rows as JSON Lines.
+    #[clap(long)]
+    metrics_jsonl: Option<String>,
+    /// Write TensorBoard event files to the given directory.
+    #[clap(long)]
+    tensorboard_dir: Option<String>,
+
+    /// Weight each move by `2^((rate - base) / scale)` of the player who made it.
+    /// Moves are weighted uniformly if not given.
+    #[clap(long)]
+    rating_weight_scale: Option<f64>,
+    #[clap(long, default_value = "3000")]
+    rating_weight_base: f64,
+
+    /// fp32, fp16 or bf16. fp16 and bf16 run the forward and backward passes in lower precision
+    /// while the weights are updated in float32.
+    #[clap(long, default_value = "fp32")]
+    precision: Precision,
+    /// Initial loss scale of fp16, which is adjusted while training.
+    #[clap(long, default_value = "65536")]
+    initial_loss_scale: f64,
+    /// Sum the gradients of the given number of batches into every optimizer step, for an
+    /// effective batch size of `batchsize * accumulation_steps`.
+    #[clap(long, default_value = "1")]
+    accumulation_steps: usize,
+
+    /// v1 or v2. The data must be written by `read_kifu` with the same feature set, and the
+    /// feature set is saved in the config next to `save_file_path`.
+    #[clap(long, default_value = "v1")]
+    feature_set: FeatureSet,
+    /// Number of past positions stacked on the input, as given to `read_kifu`.
+    #[clap(long, default_value = "0")]
+    history_positions: usize,
+    /// Number of past moves stacked on the input, as given to `read_kifu`.
+    #[clap(long, default_value = "0")]
+    history_moves: usize,
+
+    /// Weight of the loss of a head predicting the reply of the opponent. The head is only
+    /// trained if the weight is positive, and is not used by the players.
+    #[clap(long, default_value = "0")]
+    opponent_move_loss_weight: f64,
+    /// Weight of the loss of a head predicting the number of moves left in the game.
+    #[clap(long, default_value = "0")]
+    game_length_loss_weight: f64,
+
+    /// Filters of the 3x3 convolutions of the network.
+    #[clap(long, default_value = "192")]
+    channels: usize,
+    /// Number of the 3x3 convolutions of the network.
+    #[clap(long, default_value = "12")]
+    layers: usize,
+
+    /// Checkpoint of a network, built with the config saved next to it, whose policy the trained
+    /// network learns along with the labels. It must take the same input features.
+    #[clap(long)]
+    teacher: Option<String>,
+    /// Temperature softening the policies of the teacher and the trained network.
+    #[clap(long, default_value = "2")]
+    distillation_temperature: f64,
+    /// Weight of the loss to the policy of the teacher. The loss of the labels is weighted by
+    /// `1 - distillation_weight`.
+    #[clap(long, default_value = "0.5")]
+    distillation_weight: f64,
 }
 
 fn main() -> Result<()> {
     env::set_var("RUST_LOG", "info");
     env_logger::init();
     let opts: Opts = Opts::parse();
+    if opts.channels == 0 {
+        return Err(anyhow!("--channels must be at least 1"));
+    }
+    if opts.layers == 0 {
+        return Err(anyhow!("--layers must be at least 1"));
+    }
 
-    let mut rng = StdRng::seed_from_u64(717);
     let batchsize = opts.batchsize;
 
     let train_kifu = load_bin_file(&opts.train)?;
     log::info!("train_data = {}", train_kifu.len());
 
-    let mut test_kifu = load_bin_file(&opts.test)?;
+    let test_kifu = load_bin_file(&opts.test)?;
     log::info!("test_data = {}", test_kifu.len());
+    let test_legal_moves = load_legal_moves(&opts.test, &test_kifu)?;
+
+    let config = ModelConfig {
+        feature_set: opts.feature_set,
+        history: History {
+            positions: opts.history_positions,
+            moves: opts.history_moves,
+        },
+        auxiliary: AuxiliaryHeads {
+            opponent_move: opts.opponent_move_loss_weight,
+            game_length: opts.game_length_loss_weight,
+        },
+        channels: opts.channels,
+        layers: opts.layers,
+    };
+    let input_features = config.input_features();
+    for position in train_kifu.first().iter().chain(test_kifu.first().iter()) {
+        if position.features.len() != input_features.bit_planes() {
+            return Err(anyhow!(
+                "The data has {} planes but {:?} has {}",
+                position.features.len(),
+                input_features,
+                input_features.bit_planes()
+            ));
+        }
+    }
+    if Path::new(&opts.save_file_path).exists() {
+        let saved = ModelConfig::load_for(&opts.save_file_path)?;
+        if !saved.same_architecture(&config) {
+            return Err(anyhow!(
+                "{} was trained with {:?}, not {:?}",
+                opts.save_file_path,
+                saved,
+                config
+            ));
+        }
+    }
 
     let mut vs = VarStore::new(Device::Cuda(0));
-    let model = PolicyNetwork::new(&vs.root());
-    vs.load_if_exists(&opts.save_file_path)?;
+    let model = PolicyNetwork::new(&vs.root(), &config);
+    let teacher = match opts.teacher.as_ref() {
+        Some(path) => Some(load_teacher(path, input_features, vs.device())?),
+        None => None,
+    };
+    let distillation = Distillation {
+        temperature: opts.distillation_temperature,
+        weight: opts.distillation_weight,
+    };
+
+    let rating_weight = opts.rating_weight_scale.map(|scale| RatingWeight {
+        base: opts.rating_weight_base,
+        scale,
+    });
+
+    let mut optimizer = Optimizer::build(
+        &vs,
+        &OptimizerOptions {
+            kind: opts.optimizer,
+            learning_rate: opts.learning_rate,
+            momentum: opts.momentum,
+            nesterov: opts.nesterov,
+            weight_decay: opts.weight_decay,
+        },
+    )?;
+    let schedule = match opts.lr_schedule {
+        LrScheduleKind::Constant => LrSchedule::Constant,
+        LrScheduleKind::Step => {
+            if opts.lr_step_epochs == 0 {
+                return Err(anyhow!("--lr-step-epochs must be at least 1"));
+            }
+            LrSchedule::Step {
+                step_epochs: opts.lr_step_epochs,
+                gamma: opts.lr_gamma,
+            }
+        }
+        LrScheduleKind::Cosine => LrSchedule::Cosine {
+            min_lr: opts.min_lr,
+        },
+        LrScheduleKind::Plateau => LrSchedule::Plateau {
+            patience: opts.lr_patience,
+            gamma: opts.lr_gamma,
+            min_lr: opts.min_lr,
+        },
+    };
+    let scheduler = LrScheduler::new(
+        opts.learning_rate,
+        schedule,
+        opts.warmup_iters,
+        opts.epoch,
+        train_kifu.len() / batchsize,
+    );
+
+    let checkpoint = CheckPointPaths::new(&opts.save_file_path);
+    let resumed = if opts.resume {
+        checkpoint.load(&mut vs, &mut optimizer)?
+    } else {
+        None
+    };
+    let mut state = match resumed {
+        Some(state) => {
+            log::info!(
+                "Resumed at epoch={} iter_epoch={}",
+                state.epoch,
+                state.iter_epoch
+            );
+            state
+        }
+        None => {
+            vs.load_if_exists(&opts.save_file_path)?;
+            TrainingState::new(opts.seed, scheduler)
+        }
+    };
 
-    let mut optimizer = Sgd::default().build(&vs, opts.learning_rate)?;
-    for epoch in 0..opts.epoch {
+    let mut mixed_precision = match opts.precision {
+        Precision::Fp32 => None,
+        precision => Some(MixedPrecision::new(
+            &vs,
+            &config,
+            precision,
+            opts.initial_loss_scale,
+        )?),
+    };
+    if let (Some(mixed_precision), Some(scaler)) =
+        (mixed_precision.as_mut(), state.loss_scaler.clone())
+    {
+        mixed_precision.restore_loss_scaler(scaler);
+    }
+    let accumulation_steps = opts.accumulation_steps.max(1);
+    let iters_per_epoch = train_kifu.len() / batchsize;
+    let mut checkpoint_iter = state.global_iter;
+    log::info!(
+        "precision={} effective batchsize={}",
+        opts.precision.name(),
+        batchsize * accumulation_steps
+    );
+
+    let mut metrics_sink =
+        MetricsSink::new(opts.metrics_csv.as_ref(), opts.metrics_jsonl.as_ref())?;
+    let mut summary_writer = match opts.tensorboard_dir.as_ref() {
+        Some(dir) => Some(SummaryWriter::create(dir)?),
+        None => None,
+    };
+
+    for epoch in state.epoch..opts.epoch {
+        if let Some(patience) = opts.early_stopping_patience {
+            if state.epochs_without_improvement >= patience {
+                log::info!(
+                    "Stopping early: no improvement for {} epochs (best accuracy={:?})",
+                    state.epochs_without_improvement,
+                    state.best_accuracy
+                );
+                break;
+            }
+        }
         log::info!("Start epoch {}", epoch);
 
         let mut sum_loss = 0.0;
+        let mut sum_opponent_move_loss = 0.0;
+        let mut sum_game_length_loss = 0.0;
+        let mut sum_distillation_loss = 0.0;
         let mut iter = 0.0;
-        let mut sum_loss_epoch = 0.0;
-        let mut iter_epoch = 0.0;
+        let mut lr = state.scheduler.lr(epoch, state.iter_epoch);
+        let epoch_start = Instant::now();
+        let epoch_start_iter = state.iter_epoch;
+        let mut interval_start = Instant::now();
 
-        let train_loader = DataLoader::new(&train_kifu, position_to_features, batchsize);
-        for (x, t) in train_loader.progress(|state| log::info!("{}", state)) {
+        let train_loader = PositionBatches::new(&train_kifu, input_features, batchsize)
+            .skip_batches(state.iter_epoch)
+            .zip(train_kifu.chunks_exact(batchsize).skip(state.iter_epoch));
+        for ((x, t), positions) in train_loader.progress(|state| log::info!("{}", state)) {
             let x = x
-                .view((batchsize as i64, INPUT_CHANNELS as i64, 9, 9))
+                .view((batchsize as i64, config.input_channels() as i64, 9, 9))
                 .to_device(vs.device());
             let t = t.totype(Int64).to_device(vs.device());
 
-            optimizer.zero_grad();
-            let y = model.forward(&x);
-            let loss = y.log_softmax(-1, Double).nll_loss(&t);
-            optimizer.backward_step(&loss);
+            // The last batches of an epoch make a step even if there are fewer of them.
+            let first_batch = state.iter_epoch % accumulation_steps == 0;
+            let last_batch = (state.iter_epoch + 1) % accumulation_steps == 0
+                || state.iter_epoch + 1 == iters_per_epoch;
+            lr = state.scheduler.lr(epoch, state.iter_epoch);
+            optimizer.set_lr(lr);
+            if first_batch {
+                match mixed_precision.as_mut() {
+                    Some(mixed_precision) => mixed_precision.start_step(&vs)?,
+                    None => optimizer.zero_grad(),
+                }
+            }
+            let heads = match mixed_precision.as_ref() {
+                Some(mixed_precision) => mixed_precision.forward_heads(&x),
+                None => model.forward_heads(&x),
+            };
+            let y = &heads.policy;
+            let w = rating_weight.as_ref().map(|rating_weight| {
+                let w = positions
+                    .iter()
+                    .map(|position| rating_weight.weight(position.rate))
+                    .collect::<Vec<_>>();
+                Tensor::of_slice(&w).to_device(vs.device())
+            });
+            // Positions of self-play are trained on the policy recorded with them.
+            let loss = match (policy_targets(positions), w) {
+                (Some(target), w) => {
+                    let w = w
+                        .unwrap_or_else(|| Tensor::ones(&[batchsize as i64], (Float, vs.device())));
+                    weighted_cross_entropy(y, &target.to_device(vs.device()), &w)
+                }
+                (None, Some(w)) => weighted_nll_loss(y, &t, &w),
+                (None, None) => y.log_softmax(-1, Double).nll_loss(&t),
+            };
+            let soft_loss = teacher.as_ref().map(|(_, teacher)| {
+                let teacher_y = no_grad(|| teacher.forward(&x));
+                distillation.soft_loss(y, &teacher_y)
+            });
+            let policy_loss = match soft_loss.as_ref() {
+                Some(soft_loss) => distillation.loss(&loss, soft_loss),
+                None => loss.shallow_clone(),
+            };
+            let auxiliary = AuxiliaryLosses::new(&heads, positions, vs.device());
+            let total_loss = match auxiliary.weighted_sum(&config.auxiliary) {
+                Some(auxiliary_loss) => policy_loss + auxiliary_loss,
+                None => policy_loss,
+            };
+            let accumulated_loss = &total_loss / accumulation_steps as f64;
+            match mixed_precision.as_ref() {
+                Some(mixed_precision) => mixed_precision.backward(&accumulated_loss),
+                None => accumulated_loss.backward(),
+            }
+            if last_batch {
+                match mixed_precision.as_mut() {
+                    Some(mixed_precision) => {
+                        if !mixed_precision.step(&mut optimizer) {
+                            log::warn!(
+                                "Skipped a step with overflowing gradients, loss scale={}",
+                                mixed_precision.loss_scale()
+                            );
+                        }
+                        state.loss_scaler = mixed_precision.loss_scaler().cloned();
+                    }
+                    None => optimizer.step(),
+                }
+            }
 
             sum_loss += loss.double_value(&[]);
+            if let Some(loss) = auxiliary.opponent_move.as_ref() {
+                sum_opponent_move_loss += loss.double_value(&[]);
+            }
+            if let Some(loss) = auxiliary.game_length.as_ref() {
+                sum_game_length_loss += loss.double_value(&[]);
+            }
+            if let Some(loss) = soft_loss.as_ref() {
+                sum_distillation_loss += loss.double_value(&[]);
+            }
             iter += 1.0;
-            sum_loss_epoch += loss.double_value(&[]);
-            iter_epoch += 1.0;
+            state.sum_loss_epoch += loss.double_value(&[]);
+            state.iter_epoch += 1;
+            state.global_iter += 1;
 
             if iter as usize == opts.eval_interval {
-                test_kifu.shuffle(&mut rng);
+                let mut rng = StdRng::seed_from_u64(state.seed ^ state.global_iter as u64);
+                let indices = rand::seq::index::sample(&mut rng, test_kifu.len(), batchsize);
+                let sample = indices
+                    .iter()
+                    .map(|i| test_kifu[i].clone())
+                    .collect::<Vec<_>>();
+                let sample_legal_moves = test_legal_moves.as_ref().map(|legal_moves| {
+                    indices
+                        .iter()
+                        .map(|i| legal_moves[i].clone())
+                        .collect::<Vec<_>>()
+                });
 
-                let accuracy = validate(&test_kifu[0..batchsize], batchsize, &model, vs.device());
+                let samples_per_sec =
+                    iter * batchsize as f64 / interval_start.elapsed().as_secs_f64();
+                let metrics = evaluate(
+                    &sample,
+                    sample_legal_moves.as_deref(),
+                    input_features,
+                    batchsize,
+                    &model,
+                    vs.device(),
+                );
+                metrics_sink.write(
+                    &MetricsRecord::new(
+                        "eval",
+                        epoch,
+                        state.global_iter,
+                        sum_loss / iter,
+                        &metrics,
+                        lr,
+                        samples_per_sec,
+                    )
+                    .with_precision(
+                        opts.precision,
+                        accumulation_steps,
+                        loss_scale(&mixed_precision),
+                        skipped_steps(&mixed_precision),
+                    )
+                    .with_auxiliary_losses(
+                        Some(sum_opponent_move_loss / iter)
+                            .filter(|_| config.auxiliary.opponent_move > 0.0),
+                        Some(sum_game_length_loss / iter)
+                            .filter(|_| config.auxiliary.game_length > 0.0),
+                    ),
+                )?;
+                if let Some(writer) = summary_writer.as_mut() {
+                    let step = state.global_iter;
+                    writer.add_scalar("train/loss", sum_loss / iter, step)?;
+                    writer.add_scalar("val/loss", metrics.loss(), step)?;
+                    writer.add_scalar("val/accuracy", metrics.accuracy(), step)?;
+                    writer.add_scalar("train/learning_rate", lr, step)?;
+                    writer.add_scalar("train/samples_per_sec", samples_per_sec, step)?;
+                    if mixed_precision.is_some() {
+                        writer.add_scalar(
+                            "train/loss_scale",
+                            loss_scale(&mixed_precision),
+                            step,
+                        )?;
+                    }
+                    if config.auxiliary.opponent_move > 0.0 {
+                        let loss = sum_opponent_move_loss / iter;
+                        writer.add_scalar("train/opponent_move_loss", loss, step)?;
+                    }
+                    if config.auxiliary.game_length > 0.0 {
+                        let loss = sum_game_length_loss / iter;
+                        writer.add_scalar("train/game_length_loss", loss, step)?;
+                    }
+                    if teacher.is_some() {
+                        let loss = sum_distillation_loss / iter;
+                        writer.add_scalar("train/distillation_loss", loss, step)?;
+                    }
+                    write_histograms(writer, &vs, step)?;
+                    let n = std::cmp::min(sample.len(), 4);
+                    let predictions = describe_predictions(&sample[..n], &model, vs.device());
+                    writer.add_text("val/predictions", &predictions, step)?;
+                    writer.flush()?;
+                }
                 log::info!(
-                    "iter_epoch={} loss={} accuracy={}",
-                    iter_epoch,
+                    "iter_epoch={} loss={} val_loss={} accuracy={} lr={}",
+                    state.iter_epoch,
                     sum_loss / iter,
-                    accuracy
+                    metrics.loss(),
+                    metrics.accuracy(),
+                    lr
                 );
+                if config.auxiliary.any() {
+                    log::info!(
+                        "opponent_move_loss={} game_length_loss={}",
+                        sum_opponent_move_loss / iter,
+                        sum_game_length_loss / iter
+                    );
+                }
+                if teacher.is_some() {
+                    log::info!("distillation_loss={}", sum_distillation_loss / iter);
+                }
                 sum_loss = 0.0;
+                sum_opponent_move_loss = 0.0;
+                sum_game_length_loss = 0.0;
+                sum_distillation_loss = 0.0;
                 iter = 0.0;
+                interval_start = Instant::now();
+            }
+
+            // Only between optimizer steps, so that a resumed run does not lose the gradients
+            // accumulated so far.
+            if let Some(interval) = opts.checkpoint_interval.filter(|&interval| interval > 0) {
+                if last_batch && state.global_iter - checkpoint_iter >= interval {
+                    log::info!("saving checkpoint at iter_epoch={} ...", state.iter_epoch);
+                    checkpoint.save(&vs, &config, &optimizer, &state)?;
+                    checkpoint_iter = state.global_iter;
+                }
             }
         }
 
-        let accuracy = validate(&test_kifu, batchsize, &model, vs.device());
+        let samples_per_sec = ((state.iter_epoch - epoch_start_iter) * batchsize) as f64
+            / epoch_start.elapsed().as_secs_f64();
+        let metrics = evaluate(
+            &test_kifu,
+            test_legal_moves.as_deref(),
+            input_features,
+            batchsize,
+            &model,
+            vs.device(),
+        );
+        let accuracy = metrics.accuracy();
+        metrics_sink.write(
+            &MetricsRecord::new(
+                "epoch",
+                epoch,
+                state.global_iter,
+                state.sum_loss_epoch / state.iter_epoch as f64,
+                &metrics,
+                lr,
+                samples_per_sec,
+            )
+            .with_precision(
+                opts.precision,
+                accumulation_steps,
+                loss_scale(&mixed_precision),
+                skipped_steps(&mixed_precision),
+            ),
+        )?;
+        if let Some(writer) = summary_writer.as_mut() {
+            let step = state.global_iter;
+            writer.add_scalar(
+                "epoch/train_loss",
+                state.sum_loss_epoch / state.iter_epoch as f64,
+                step,
+            )?;
+            writer.add_scalar("epoch/val_loss", metrics.loss(), step)?;
+            for &k in TOP_K.iter() {
+                if let Some(accuracy) = metrics.top_k_accuracy(k) {
+                    writer.add_scalar(&format!("epoch/val_top{}", k), accuracy, step)?;
+                }
+            }
+            if metrics.legal.total > 0 {
+                writer.add_scalar("epoch/val_legal", metrics.legal.accuracy(), step)?;
+            }
+            writer.flush()?;
+        }
         log::info!(
-            "epoch={} loss={} accuracy={}",
+            "epoch={} loss={} val_loss={} accuracy={}",
             epoch,
-            sum_loss_epoch / iter_epoch,
+            state.sum_loss_epoch / state.iter_epoch as f64,
+            metrics.loss(),
             accuracy
         );
+        log::info!("validation metrics:\n{}", metrics);
+        state.scheduler.on_validation(accuracy);
+        let improved = state.update_best(accuracy);
+
+        state.epoch = epoch + 1;
+        state.iter_epoch = 0;
+        state.sum_loss_epoch = 0.0;
         log::info!("saving ...");
-        vs.save(&opts.save_file_path)?;
+        checkpoint.save(&vs, &config, &optimizer, &state)?;
+        if improved {
+            log::info!("saving best model (accuracy={}) ...", accuracy);
+            checkpoint
+                .with_suffix("best")
+                .save(&vs, &config, &optimizer, &state)?;
+        }
+        if opts.keep_checkpoints > 0 {
+            let suffix = format!("epoch{}", epoch);
+            checkpoint
+                .with_suffix(&suffix)
+                .save(&vs, &config, &optimizer, &state)?;
+            if epoch >= opts.keep_checkpoints {
+                let suffix = format!("epoch{}", epoch - opts.keep_checkpoints);
+                checkpoint.with_suffix(&suffix).remove()?;
+            }
+        }
     }
 
     log::info!("Done");
     Ok(())
 }
 
-fn position_to_features(position: &Position) -> (Vec<f32>, i16) {
-    (position.features.to_flat_vec(), position.move_label)
+/// Loads the network at `path` with the config saved next to it, along with the `VarStore`
+/// holding its weights.
+fn load_teacher(
+    path: &str,
+    input_features: InputFeatures,
+    device: Device,
+) -> Result<(VarStore, PolicyNetwork)> {
+    let config = ModelConfig::load_for(path)?;
+    if config.input_features() != input_features {
+        return Err(anyhow!(
+            "The teacher {} takes {:?}, not {:?}",
+            path,
+            config.input_features(),
+            input_features
+        ));
+    }
+    let mut vs = VarStore::new(device);
+    let model = PolicyNetwork::new(&vs.root(), &config);
+    vs.load(path)?;
+    log::info!(
+        "teacher: {} channels x {} layers",
+        config.channels,
+        config.layers
+    );
+    Ok((vs, model))
+}
+
+fn loss_scale(mixed_precision: &Option<MixedPrecision>) -> f64 {
+    mixed_precision
+        .as_ref()
+        .map(|mixed_precision| mixed_precision.loss_scale())
+        .unwrap_or(1.0)
+}
+
+fn skipped_steps(mixed_precision: &Option<MixedPrecision>) -> usize {
+    mixed_precision
+        .as_ref()
+        .map(|mixed_precision| mixed_precision.skipped_steps())
+        .unwrap_or(0)
+}
+
+/// Histograms of every variable of the network and of its gradient.
+fn write_histograms<W: Write>(
+    writer: &mut SummaryWriter<W>,
+    vs: &VarStore,
+    step: usize,
+) -> Result<()> {
+    let mut variables = vs.variables().into_iter().collect::<Vec<_>>();
+    variables.sort_by(|a, b| a.0.cmp(&b.0));
+    for (name, var) in variables {
+        let values = var.detach().view(-1).to_device(Device::Cpu).totype(Double);
+        let values = Vec::<f64>::from(&values);
+        writer.add_histogram(&format!("weights/{}", name), &values, step)?;
+        let grad = var.grad();
+        if grad.defined() {
+            let values = grad.view(-1).to_device(Device::Cpu).totype(Double);
+            let values = Vec::<f64>::from(&values);
+            writer.add_histogram(&format!("gradients/{}", name), &values, step)?;
+        }
+    }
+    Ok(())
+}
+
+/// Markdown table of the top 3 predictions for each position.
+fn describe_predictions(positions: &[Position], model: &PolicyNetwork, device: Device) -> String {
+    let input_features = model.config().input_features();
+    let mut features = vec![0.0; positions.len() * input_features.input_channels() * 9 * 9];
+    write_batch_features(input_features, positions, &mut features);
+    let x = Tensor::of_slice(&features)
+        .view((
+            positions.len() as i64,
+            input_features.input_channels() as i64,
+            9,
+            9,
+        ))
+        .to_device(device);
+    let (probability, labels) = no_grad(|| {
+        model
+            .forward(&x)
+            .softmax(-1, Double)
+            .topk(3, -1, true, true)
+    });
+
+    let mut text = String::from("| move | top1 | top2 | top3 |\n|---|---|---|---|\n");
+    for (i, position) in positions.iter().enumerate() {
+        text += &format!("| {} |", describe_label(position.move_label));
+        for k in 0..3 {
+            let label = labels.int64_value(&[i as i64, k]) as i16;
+            let p = probability.double_value(&[i as i64, k]);
+            text += &format!(" {} ({:.3}) |", describe_label(label), p);
+        }
+        text += "\n";
+    }
+    text
 }
@@ -0,0 +1,211 @@
+use crate::lr_scheduler::LrScheduler;
+use crate::mixed_precision::LossScaler;
+use crate::network::config::ModelConfig;
+use crate::optimizer::Optimizer;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs::{remove_file, rename, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use tch::nn::VarStore;
+
+/// Everything besides the weights and the optimizer buffers needed to continue a training run.
+#[derive(Serialize, Deserialize)]
+pub struct TrainingState {
+    /// Epoch to continue from.
+    pub epoch: usize,
+    /// Batches of `epoch` already trained on.
+    pub iter_epoch: usize,
+    pub global_iter: usize,
+    pub sum_loss_epoch: f64,
+    /// Random numbers are drawn from generators seeded with `seed` and `global_iter`, so that a
+    /// resumed run draws the same numbers as an uninterrupted one.
+    pub seed: u64,
+    pub scheduler: LrScheduler,
+    pub best_accuracy: Option<f64>,
+    pub epochs_without_improvement: usize,
+    /// Loss scale of fp16 as of the last optimizer step, `None` for other precisions.
+    pub loss_scaler: Option<LossScaler>,
+}
+
+impl TrainingState {
+    pub fn new(seed: u64, scheduler: LrScheduler) -> Self {
+        Self {
+            epoch: 0,
+            iter_epoch: 0,
+            global_iter: 0,
+            sum_loss_epoch: 0.0,
+            seed,
+            scheduler,
+            best_accuracy: None,
+            epochs_without_improvement: 0,
+            loss_scaler: None,
+        }
+    }
+
+    /// Records the validation accuracy of a finished epoch and returns whether it is the best so far.
+    pub fn update_best(&mut self, accuracy: f64) -> bool {
+        let improved = self
+            .best_accuracy
+            .map(|best| accuracy > best)
+            .unwrap_or(true);
+        if improved {
+            self.best_accuracy = Some(accuracy);
+            self.epochs_without_improvement = 0;
+        } else {
+            self.epochs_without_improvement += 1;
+        }
+        improved
+    }
+}
+
+/// A checkpoint is stored as four files. The weights keep the plain `VarStore` format and the
+/// `ModelConfig` is saved next to them, so that `policy_player` can load them directly.
+pub struct CheckPointPaths {
+    pub weights: PathBuf,
+    pub config: PathBuf,
+    pub optimizer: PathBuf,
+    pub state: PathBuf,
+}
+
+impl CheckPointPaths {
+    pub fn new<P: AsRef<Path>>(weights: P) -> Self {
+        let weights = weights.as_ref().to_path_buf();
+        let config = ModelConfig::path_for(&weights);
+        let optimizer = append_extension(&weights, "optimizer");
+        let state = append_extension(&weights, "state");
+        Self {
+            weights,
+            config,
+            optimizer,
+            state,
+        }
+    }
+
+    /// Paths of another checkpoint next to this one, e.g. `check_point.bin.best`.
+    pub fn with_suffix(&self, suffix: &str) -> Self {
+        Self::new(append_extension(&self.weights, suffix))
+    }
+
+    pub fn remove(&self) -> Result<()> {
+        for path in [&self.weights, &self.config, &self.optimizer, &self.state].iter() {
+            if path.exists() {
+                remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn save(
+        &self,
+        vs: &VarStore,
+        config: &ModelConfig,
+        optimizer: &Optimizer,
+        state: &TrainingState,
+    ) -> Result<()> {
+        let weights = append_extension(&self.weights, "tmp");
+        vs.save(&weights)?;
+        config.save_for(&self.weights)?;
+        let optimizer_path = append_extension(&self.optimizer, "tmp");
+        optimizer.save(&optimizer_path)?;
+        let state_path = append_extension(&self.state, "tmp");
+        File::create(&state_path)?.write_all(&bincode::serialize(state)?)?;
+
+        // Rename after every file is written so that an interrupted save keeps the old checkpoint.
+        rename(weights, &self.weights)?;
+        rename(optimizer_path, &self.optimizer)?;
+        rename(state_path, &self.state)?;
+        Ok(())
+    }
+
+    /// Returns `None` if there is no training state to resume from.
+    pub fn load(
+        &self,
+        vs: &mut VarStore,
+        optimizer: &mut Optimizer,
+    ) -> Result<Option<TrainingState>> {
+        if !self.state.exists() {
+            return Ok(None);
+        }
+        log::info!("Resuming from {}", self.state.display());
+        let mut buf = vec![];
+        File::open(&self.state)?.read_to_end(&mut buf)?;
+        let state: TrainingState = bincode::deserialize(&buf)?;
+        vs.load(&self.weights)?;
+        optimizer.load(&self.optimizer)?;
+        Ok(Some(state))
+    }
+}
+
+fn append_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".");
+    path.push(extension);
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lr_scheduler::LrSchedule;
+
+    #[test]
+    fn test_checkpoint_paths() {
+        let paths = CheckPointPaths::new("./train_policy_check_point.bin");
+        assert_eq!(
+            paths.weights,
+            PathBuf::from("./train_policy_check_point.bin")
+        );
+        assert_eq!(
+            paths.config,
+            PathBuf::from("./train_policy_check_point.bin.config.json")
+        );
+        assert_eq!(
+            paths.optimizer,
+            PathBuf::from("./train_policy_check_point.bin.optimizer")
+        );
+        assert_eq!(
+            paths.state,
+            PathBuf::from("./train_policy_check_point.bin.state")
+        );
+
+        let best = paths.with_suffix("best");
+        assert_eq!(
+            best.weights,
+            PathBuf::from("./train_policy_check_point.bin.best")
+        );
+        assert_eq!(
+            best.state,
+            PathBuf::from("./train_policy_check_point.bin.best.state")
+        );
+    }
+
+    #[test]
+    fn test_update_best() {
+        let scheduler = LrScheduler::new(0.1, LrSchedule::Constant, 0, 10, 100);
+        let mut state = TrainingState::new(717, scheduler);
+        assert!(state.update_best(0.3));
+        assert!(!state.update_best(0.3));
+        assert!(!state.update_best(0.2));
+        assert_eq!(state.epochs_without_improvement, 2);
+        assert!(state.update_best(0.4));
+        assert_eq!(state.epochs_without_improvement, 0);
+        assert_eq!(state.best_accuracy, Some(0.4));
+    }
+
+    #[test]
+    fn test_loss_scaler_state() {
+        let scheduler = LrScheduler::new(0.1, LrSchedule::Constant, 0, 10, 100);
+        let mut state = TrainingState::new(717, scheduler);
+        let mut scaler = LossScaler::new(65536.0);
+        scaler.update(false);
+        scaler.update(true);
+        state.loss_scaler = Some(scaler);
+
+        let state: TrainingState =
+            bincode::deserialize(&bincode::serialize(&state).unwrap()).unwrap();
+        let scaler = state.loss_scaler.unwrap();
+        assert_eq!(scaler.scale(), 32768.0);
+        assert_eq!(scaler.skipped_steps(), 1);
+    }
+}
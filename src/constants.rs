@@ -1,16 +1,5 @@
 use crate::model::MoveDirection;
 
-/// Pieces = 14
-/// Pieces in hand
-///     - Pawn: 18
-///     - Lance: 4
-///     - Knight: 4
-///     - Silver: 4
-///     - Gold: 4
-///     - Bishop: 2
-///     - Rook: 2
-pub const INPUT_CHANNELS: usize = 104;
-
 pub const MOVE_DIRECTIONS: [MoveDirection; 20] = [
     MoveDirection::Up,
     MoveDirection::Down,
@@ -36,3 +25,11 @@ pub const MOVE_DIRECTIONS: [MoveDirection; 20] = [
 
 // directions + drops
 pub const MOVE_DIRECTION_LABEL_NUM: i64 = MOVE_DIRECTIONS.len() as i64 + 7;
+
+/// CSA names indexed by `Piece::to_usize()`.
+pub const PIECE_NAMES: [&str; 15] = [
+    "", "FU", "KY", "KE", "GI", "KI", "KA", "HI", "OU", "TO", "NY", "NK", "NG", "UM", "RY",
+];
+
+/// USI names of pieces in hand indexed by `Piece::to_usize()`.
+pub const HAND_PIECE_USI_NAMES: [&str; 8] = ["", "P", "L", "N", "S", "G", "B", "R"];
@@ -0,0 +1,155 @@
+use crate::constants::PIECE_NAMES;
+use shogiutil::{Color, Move};
+use std::fmt::{self, Display, Formatter};
+
+/// CSA notation of a move, e.g. `+7776FU`, `+8822UM` or `-0055FU`.
+pub fn format_csa_move(mv: &Move, promoted: bool) -> String {
+    let sign = if mv.color == Color::Black { '+' } else { '-' };
+    let from = match mv.from.as_ref() {
+        Some(from) => format!("{}{}", from.file, from.rank),
+        None => "00".to_string(),
+    };
+    let piece = mv.piece.to_usize();
+    let piece = if promoted { promote(piece) } else { piece };
+    format!(
+        "{}{}{}{}{}",
+        sign, from, mv.to.file, mv.to.rank, PIECE_NAMES[piece]
+    )
+}
+
+/// Special move recording a foul of `color` such as perpetual check, e.g. `%+ILLEGAL_ACTION`.
+pub fn illegal_action(color: Color) -> String {
+    let sign = if color == Color::Black { '+' } else { '-' };
+    format!("%{}ILLEGAL_ACTION", sign)
+}
+
+/// Index of the promoted piece in `PIECE_NAMES`.
+fn promote(piece: usize) -> usize {
+    match piece {
+        1..=4 => piece + 8,
+        6 | 7 => piece + 7,
+        _ => piece,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CsaMove {
+    pub mv: String,
+    /// Thinking time in seconds.
+    pub seconds: Option<u64>,
+    /// Comment written after the move, without `'`.
+    pub comment: Option<String>,
+}
+
+/// Game record in CSA format V2.2 starting from the initial position.
+#[derive(Debug, Clone, Default)]
+pub struct CsaRecord {
+    pub black_name: String,
+    pub white_name: String,
+    pub event: Option<String>,
+    /// Comment lines written after the header such as `black_rate:name:3000.0`, without `'`.
+    pub header_comments: Vec<String>,
+    pub moves: Vec<CsaMove>,
+    /// Special move ending the game such as `%TORYO`.
+    pub result: Option<String>,
+}
+
+impl CsaRecord {
+    pub fn new(black_name: &str, white_name: &str) -> Self {
+        Self {
+            black_name: black_name.to_string(),
+            white_name: white_name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn push(&mut self, mv: String, seconds: Option<u64>) {
+        self.moves.push(CsaMove {
+            mv,
+            seconds,
+            comment: None,
+        });
+    }
+
+    pub fn push_with_comment(&mut self, mv: String, seconds: Option<u64>, comment: String) {
+        self.moves.push(CsaMove {
+            mv,
+            seconds,
+            comment: Some(comment),
+        });
+    }
+}
+
+impl Display for CsaRecord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "V2.2")?;
+        writeln!(f, "N+{}", self.black_name)?;
+        writeln!(f, "N-{}", self.white_name)?;
+        if let Some(event) = self.event.as_ref() {
+            writeln!(f, "$EVENT:{}", event)?;
+        }
+        for comment in self.header_comments.iter() {
+            writeln!(f, "'{}", comment)?;
+        }
+        writeln!(f, "PI")?;
+        writeln!(f, "+")?;
+        for mv in self.moves.iter() {
+            writeln!(f, "{}", mv.mv)?;
+            if let Some(seconds) = mv.seconds {
+                writeln!(f, "T{}", seconds)?;
+            }
+            if let Some(comment) = mv.comment.as_ref() {
+                writeln!(f, "'{}", comment)?;
+            }
+        }
+        if let Some(result) = self.result.as_ref() {
+            writeln!(f, "{}", result)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shogiutil::{Piece, Square};
+
+    #[test]
+    fn test_format_csa_move() {
+        let mv = Move {
+            from: Some(Square { file: 7, rank: 7 }),
+            to: Square { file: 7, rank: 6 },
+            piece: Piece::Pawn,
+            color: Color::Black,
+        };
+        assert_eq!(format_csa_move(&mv, false), "+7776FU");
+
+        let mv = Move {
+            from: Some(Square { file: 8, rank: 8 }),
+            to: Square { file: 2, rank: 2 },
+            piece: Piece::Bishop,
+            color: Color::Black,
+        };
+        assert_eq!(format_csa_move(&mv, true), "+8822UM");
+
+        let mv = Move {
+            from: None,
+            to: Square { file: 5, rank: 5 },
+            piece: Piece::Pawn,
+            color: Color::White,
+        };
+        assert_eq!(format_csa_move(&mv, false), "-0055FU");
+    }
+
+    #[test]
+    fn test_csa_record() {
+        let mut record = CsaRecord::new("engine1", "engine2");
+        record.push("+7776FU".to_string(), Some(1));
+        record.push_with_comment("-3334FU".to_string(), None, "p=0.5".to_string());
+        record.result = Some("%TORYO".to_string());
+        assert_eq!(
+            record.to_string(),
+            "V2.2\nN+engine1\nN-engine2\nPI\n+\n+7776FU\nT1\n-3334FU\n'p=0.5\n%TORYO\n"
+        );
+    }
+}
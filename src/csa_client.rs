@@ -0,0 +1,387 @@
+//! Client of the CSA server protocol used by Floodgate and most computer shogi tournaments.
+//!
+//! A `UsiPlayer` is driven by the client as if by a USI GUI: it receives `position` and `go`
+//! with the time left for every own move, and its answer is sent to the server in CSA notation.
+//! No move resigns with `%TORYO` and `UsiPlayer::declare_win` declares a win with `%KACHI`.
+use crate::game::Game;
+use crate::usi::{GoTime, UsiPlayer};
+use anyhow::{anyhow, bail, Result};
+use shogiutil::{Color, UsiRequest};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::thread::sleep;
+use std::time::Duration;
+
+const INITIAL_POSITION: [&str; 9] = [
+    "P1-KY-KE-GI-KI-OU-KI-GI-KE-KY",
+    "P2 * -HI *  *  *  *  * -KA *",
+    "P3-FU-FU-FU-FU-FU-FU-FU-FU-FU",
+    "P4 *  *  *  *  *  *  *  *  *",
+    "P5 *  *  *  *  *  *  *  *  *",
+    "P6 *  *  *  *  *  *  *  *  *",
+    "P7+FU+FU+FU+FU+FU+FU+FU+FU+FU",
+    "P8 * +KA *  *  *  *  * +HI *",
+    "P9+KY+KE+GI+KI+OU+KI+GI+KE+KY",
+];
+
+#[derive(Debug, Clone)]
+pub struct CsaClientConfig {
+    pub host: String,
+    pub port: u16,
+    pub name: String,
+    pub password: String,
+    /// Number of games to play before logging out.
+    pub games: usize,
+    /// Number of reconnections tried in a row before giving up.
+    pub reconnect_attempts: usize,
+    pub reconnect_wait: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameSummary {
+    pub game_id: String,
+    pub black_name: String,
+    pub white_name: String,
+    pub my_color: Color,
+    /// Moves already played, in CSA notation, when a game is resumed.
+    pub moves: Vec<String>,
+    pub total_time: Option<u64>,
+    pub byoyomi: Option<u64>,
+    pub max_moves: Option<usize>,
+}
+
+fn parse_color(s: &str) -> Result<Color> {
+    match s {
+        "+" => Ok(Color::Black),
+        "-" => Ok(Color::White),
+        _ => Err(anyhow!("Invalid turn: {}", s)),
+    }
+}
+
+/// Parses the lines from `BEGIN Game_Summary` to `END Game_Summary`. Only games starting from
+/// the initial position are supported.
+pub fn parse_game_summary(lines: &[String]) -> Result<GameSummary> {
+    let mut game_id = None;
+    let mut black_name = String::new();
+    let mut white_name = String::new();
+    let mut my_color = None;
+    let mut total_time = None;
+    let mut byoyomi = None;
+    let mut max_moves = None;
+    let mut moves = vec![];
+    let mut in_position = false;
+
+    for line in lines.iter().map(|line| line.trim()) {
+        match line {
+            "BEGIN Position" => in_position = true,
+            "END Position" => in_position = false,
+            _ if in_position => {
+                if line.starts_with('P') {
+                    let row = line.get(1..2).and_then(|row| row.parse::<usize>().ok());
+                    if let Some(row) = row.filter(|&row| row >= 1) {
+                        if line != INITIAL_POSITION[row - 1] {
+                            bail!("Unsupported initial position: {}", line);
+                        }
+                    } else if line != "PI" && line != "P+" && line != "P-" {
+                        bail!("Unsupported initial position: {}", line);
+                    }
+                } else if line.len() >= 7 && (line.starts_with('+') || line.starts_with('-')) {
+                    moves.push(line.split(',').next().unwrap().to_string());
+                }
+            }
+            _ => {
+                let mut key_value = line.splitn(2, ':');
+                let (key, value) = match (key_value.next(), key_value.next()) {
+                    (Some(key), Some(value)) => (key, value),
+                    _ => continue,
+                };
+                match key {
+                    "Game_ID" => game_id = Some(value.to_string()),
+                    "Name+" => black_name = value.to_string(),
+                    "Name-" => white_name = value.to_string(),
+                    "Your_Turn" => my_color = Some(parse_color(value)?),
+                    "Total_Time" => total_time = value.parse().ok(),
+                    "Byoyomi" => byoyomi = value.parse().ok(),
+                    "Max_Moves" => max_moves = value.parse().ok(),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    Ok(GameSummary {
+        game_id: game_id.ok_or_else(|| anyhow!("No Game_ID in game summary"))?,
+        black_name,
+        white_name,
+        my_color: my_color.ok_or_else(|| anyhow!("No Your_Turn in game summary"))?,
+        moves,
+        total_time,
+        byoyomi,
+        max_moves,
+    })
+}
+
+/// Time left to both players as given to `go`, with `used_time` in seconds by Black and White.
+fn go_time(summary: &GameSummary, used_time: &[u64; 2]) -> GoTime {
+    let time_left = |used: u64| summary.total_time.unwrap_or(0).saturating_sub(used) * 1000;
+    GoTime {
+        btime: time_left(used_time[0]),
+        wtime: time_left(used_time[1]),
+        byoyomi: summary.byoyomi.unwrap_or(0) * 1000,
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum GameEnd {
+    Win,
+    Lose,
+    Draw,
+    /// The game was stopped by the server without a result.
+    Chudan,
+}
+
+#[derive(Debug, Clone)]
+pub struct GameResult {
+    pub game_id: String,
+    pub end: GameEnd,
+    /// Reason given by the server, e.g. `#RESIGN` or `#TIME_UP`.
+    pub reason: Option<String>,
+    pub moves: usize,
+}
+
+pub struct CsaClient {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+impl CsaClient {
+    /// Connects to the server and logs in.
+    pub fn connect(config: &CsaClientConfig) -> Result<Self> {
+        let stream = TcpStream::connect((config.host.as_str(), config.port))?;
+        let reader = BufReader::new(stream.try_clone()?);
+        let mut client = Self { stream, reader };
+
+        client.send(&format!("LOGIN {} {}", config.name, config.password))?;
+        let response = client.read_line()?;
+        if response != format!("LOGIN:{} OK", config.name) {
+            bail!("Login failed: {}", response);
+        }
+        Ok(client)
+    }
+
+    fn send(&mut self, line: &str) -> Result<()> {
+        log::info!("> {}", line);
+        writeln!(self.stream, "{}", line)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    /// Reads the next line, skipping keep-alive empty lines.
+    fn read_line(&mut self) -> Result<String> {
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                bail!("Connection closed by the server");
+            }
+            let line = line.trim();
+            if !line.is_empty() {
+                log::info!("< {}", line);
+                return Ok(line.to_string());
+            }
+        }
+    }
+
+    pub fn wait_game_summary(&mut self) -> Result<GameSummary> {
+        while self.read_line()? != "BEGIN Game_Summary" {}
+        let mut lines = vec![];
+        loop {
+            let line = self.read_line()?;
+            if line == "END Game_Summary" {
+                break;
+            }
+            lines.push(line);
+        }
+        parse_game_summary(&lines)
+    }
+
+    pub fn play_game<P: UsiPlayer>(
+        &mut self,
+        player: &mut P,
+        summary: &GameSummary,
+    ) -> Result<GameResult> {
+        player.play(UsiRequest::Usi);
+        player.play(UsiRequest::IsReady);
+        player.play(UsiRequest::NewGame);
+
+        self.send(&format!("AGREE {}", summary.game_id))?;
+        let response = self.read_line()?;
+        if !response.starts_with("START:") {
+            bail!("Game was not started: {}", response);
+        }
+
+        let mut game = Game::new();
+        for mv in summary.moves.iter() {
+            game.play_csa(mv)?;
+        }
+
+        let mut used_time = [0; 2];
+        let mut reason = None;
+        let mut waiting_echo = false;
+        loop {
+            if game.next_turn() == summary.my_color && !waiting_echo {
+                player.position_command(&game.position_command());
+                player.play(game.position_request()?);
+                player.go_time(go_time(summary, &used_time));
+                if player.declare_win() {
+                    self.send("%KACHI")?;
+                } else {
+                    let responses = player.play(UsiRequest::Go);
+                    match game.find_response_move(&responses) {
+                        Some(mv) => self.send(&mv.to_csa())?,
+                        None => self.send("%TORYO")?,
+                    }
+                }
+                waiting_echo = true;
+            }
+
+            let line = self.read_line()?;
+            let end = match line.as_str() {
+                "#WIN" => Some(GameEnd::Win),
+                "#LOSE" => Some(GameEnd::Lose),
+                "#DRAW" => Some(GameEnd::Draw),
+                "#CHUDAN" | "#CENSORED" => Some(GameEnd::Chudan),
+                _ => None,
+            };
+            if let Some(end) = end {
+                return Ok(GameResult {
+                    game_id: summary.game_id.clone(),
+                    end,
+                    reason,
+                    moves: game.moves().len(),
+                });
+            }
+
+            if line.starts_with('#') {
+                reason = Some(line);
+            } else if line.starts_with('%') {
+                // Special moves are echoed before the result.
+            } else if line.starts_with('+') || line.starts_with('-') {
+                let mut fields = line.split(',');
+                let mv = fields.next().unwrap();
+                let seconds = fields
+                    .next()
+                    .and_then(|time| time.strip_prefix('T'))
+                    .and_then(|time| time.parse::<u64>().ok())
+                    .unwrap_or(0);
+                let color = game.next_turn();
+                game.play_csa(mv)?;
+                used_time[(color == Color::White) as usize] += seconds;
+                if color == summary.my_color {
+                    waiting_echo = false;
+                }
+            }
+        }
+    }
+
+    pub fn logout(&mut self) -> Result<()> {
+        self.send("LOGOUT")?;
+        Ok(())
+    }
+}
+
+/// Plays `config.games` games, logging in again after connection errors.
+pub fn run<P: UsiPlayer>(config: &CsaClientConfig, player: &mut P) -> Result<Vec<GameResult>> {
+    let mut results = vec![];
+    let mut failures = 0;
+    while results.len() < config.games {
+        let result = CsaClient::connect(config).and_then(|mut client| {
+            while results.len() < config.games {
+                let summary = client.wait_game_summary()?;
+                let result = client.play_game(player, &summary)?;
+                log::info!("{:?}", result);
+                results.push(result);
+                failures = 0;
+            }
+            client.logout()
+        });
+
+        if let Err(e) = result {
+            failures += 1;
+            if failures > config.reconnect_attempts {
+                return Err(e);
+            }
+            log::warn!(
+                "{}; reconnecting in {:?} ({}/{})",
+                e,
+                config.reconnect_wait,
+                failures,
+                config.reconnect_attempts
+            );
+            sleep(config.reconnect_wait);
+        }
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_game_summary() {
+        let summary = "Protocol_Version:1.2
+Protocol_Mode:Server
+Format:Shogi 1.0
+Game_ID:20201016-test-1
+Name+:policy_player
+Name-:opponent
+Your_Turn:-
+To_Move:+
+Max_Moves:256
+BEGIN Time
+Time_Unit:1sec
+Total_Time:600
+Byoyomi:10
+END Time
+BEGIN Position
+P1-KY-KE-GI-KI-OU-KI-GI-KE-KY
+P2 * -HI *  *  *  *  * -KA * 
+P3-FU-FU-FU-FU-FU-FU-FU-FU-FU
+P4 *  *  *  *  *  *  *  *  * 
+P5 *  *  *  *  *  *  *  *  * 
+P6 *  *  *  *  *  *  *  *  * 
+P7+FU+FU+FU+FU+FU+FU+FU+FU+FU
+P8 * +KA *  *  *  *  * +HI * 
+P9+KY+KE+GI+KI+OU+KI+GI+KE+KY
+P+
+P-
++
++7776FU,T12
+END Position";
+        let lines = summary
+            .split('\n')
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>();
+        let summary = parse_game_summary(&lines).unwrap();
+        assert_eq!(summary.game_id, "20201016-test-1");
+        assert_eq!(summary.black_name, "policy_player");
+        assert_eq!(summary.white_name, "opponent");
+        assert_eq!(summary.my_color, Color::White);
+        assert_eq!(summary.moves, vec!["+7776FU".to_string()]);
+        assert_eq!(summary.total_time, Some(600));
+        assert_eq!(summary.byoyomi, Some(10));
+        assert_eq!(summary.max_moves, Some(256));
+        assert_eq!(
+            go_time(&summary, &[12, 700]),
+            GoTime {
+                btime: 588_000,
+                wtime: 0,
+                byoyomi: 10_000
+            }
+        );
+
+        let mut lines = lines;
+        lines[15] = "P1-KY-KE-GI-KI-OU-KI-GI-KE *".to_string();
+        assert!(parse_game_summary(&lines).is_err());
+    }
+}
@@ -0,0 +1,574 @@
+//! Minimal CSA protocol server to run local tournaments between `csa_client`s and other CSA
+//! engines.
+//!
+//! Players are paired in login order, and go back to the waiting queue after each game until they
+//! disconnect. A player who logged out while waiting is noticed when the next game is offered.
+use crate::csa::{illegal_action, CsaRecord};
+use crate::game::{opponent, Game, Sennichite};
+use anyhow::{anyhow, bail, Result};
+use shogiutil::Color;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::{self, Display, Formatter};
+use std::fs::{create_dir_all, File};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(30);
+const AGREE_TIMEOUT: Duration = Duration::from_secs(60);
+/// Allowance for network latency on top of the remaining time.
+const TIME_MARGIN: Duration = Duration::from_secs(1);
+const K_FACTOR: f64 = 16.0;
+
+#[derive(Debug, Clone)]
+pub struct CsaServerConfig {
+    /// Total time of each player in seconds.
+    pub total_time: u64,
+    pub byoyomi: u64,
+    pub max_moves: usize,
+    /// Directory to write the CSA record of every game.
+    pub out_dir: Option<PathBuf>,
+    /// File rewritten with the standings table after every game.
+    pub standings_path: Option<PathBuf>,
+    /// Number of games to play before `serve` returns. The server runs forever if `None`.
+    pub games: Option<usize>,
+    /// Rate of players in their first game.
+    pub initial_rate: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Standing {
+    pub wins: usize,
+    pub losses: usize,
+    pub draws: usize,
+    pub rate: f64,
+}
+
+/// Results of every player, with Elo ratings updated after each game.
+#[derive(Debug, Clone)]
+pub struct Standings {
+    players: BTreeMap<String, Standing>,
+    initial_rate: f64,
+}
+
+impl Standings {
+    pub fn new(initial_rate: f64) -> Self {
+        Self {
+            players: BTreeMap::new(),
+            initial_rate,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Standing> {
+        self.players.get(name)
+    }
+
+    pub fn rate(&self, name: &str) -> f64 {
+        self.get(name)
+            .map(|standing| standing.rate)
+            .unwrap_or(self.initial_rate)
+    }
+
+    /// Records a game. `winner` is `None` for draws.
+    pub fn record(&mut self, black: &str, white: &str, winner: Option<Color>) {
+        let black_rate = self.rate(black);
+        let white_rate = self.rate(white);
+        let expected = 1.0 / (1.0 + 10f64.powf((white_rate - black_rate) / 400.0));
+        let score = match winner {
+            Some(Color::Black) => 1.0,
+            Some(Color::White) => 0.0,
+            None => 0.5,
+        };
+        let delta = K_FACTOR * (score - expected);
+
+        for (name, rate, score) in vec![
+            (black, black_rate + delta, score),
+            (white, white_rate - delta, 1.0 - score),
+        ] {
+            let standing = self.players.entry(name.to_string()).or_insert(Standing {
+                wins: 0,
+                losses: 0,
+                draws: 0,
+                rate,
+            });
+            standing.rate = rate;
+            if score == 1.0 {
+                standing.wins += 1;
+            } else if score == 0.0 {
+                standing.losses += 1;
+            } else {
+                standing.draws += 1;
+            }
+        }
+    }
+}
+
+impl Display for Standings {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut players = self.players.iter().collect::<Vec<_>>();
+        players.sort_by(|(_, a), (_, b)| b.rate.partial_cmp(&a.rate).unwrap());
+        writeln!(
+            f,
+            "{:>4} {:<24} {:>7} {:>5} {:>5} {:>5}",
+            "rank", "name", "rate", "win", "draw", "lose"
+        )?;
+        for (i, (name, standing)) in players.into_iter().enumerate() {
+            writeln!(
+                f,
+                "{:>4} {:<24} {:>7.1} {:>5} {:>5} {:>5}",
+                i + 1,
+                name,
+                standing.rate,
+                standing.wins,
+                standing.draws,
+                standing.losses
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerGameResult {
+    pub game_id: String,
+    pub black_name: String,
+    pub white_name: String,
+    /// `None` for draws and games stopped at the move limit.
+    pub winner: Option<Color>,
+    /// Reason sent to the players, e.g. `#RESIGN` or `#TIME_UP`.
+    pub reason: String,
+    pub record: CsaRecord,
+}
+
+struct Connection {
+    name: String,
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+    alive: bool,
+}
+
+impl Connection {
+    fn send(&mut self, line: &str) {
+        log::info!("{} > {}", self.name, line);
+        let result = writeln!(self.stream, "{}", line).and_then(|_| self.stream.flush());
+        if let Err(e) = result {
+            log::warn!("{}: {}", self.name, e);
+            self.alive = false;
+        }
+    }
+
+    /// Reads the next non-empty line. Returns `None` when the deadline has passed.
+    fn read_line(&mut self, deadline: Instant) -> Result<Option<String>> {
+        let line = read_line(&mut self.reader, deadline);
+        if line.is_err() {
+            self.alive = false;
+        }
+        if let Ok(Some(line)) = line.as_ref() {
+            log::info!("{} < {}", self.name, line);
+        }
+        line
+    }
+}
+
+fn read_line(reader: &mut BufReader<TcpStream>, deadline: Instant) -> Result<Option<String>> {
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(None);
+        }
+        reader.get_ref().set_read_timeout(Some(deadline - now))?;
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) => bail!("Connection closed"),
+            Ok(_) => {
+                let line = line.trim();
+                if !line.is_empty() {
+                    return Ok(Some(line.to_string()));
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Reads `LOGIN <name> <password>`. Passwords are not checked.
+fn login(stream: TcpStream, logged_in: &Mutex<BTreeSet<String>>) -> Result<Connection> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let line = read_line(&mut reader, Instant::now() + LOGIN_TIMEOUT)?
+        .ok_or_else(|| anyhow!("Login timed out"))?;
+    let tokens = line.split_whitespace().collect::<Vec<_>>();
+    let name = match tokens.as_slice() {
+        ["LOGIN", name, _password] => name.to_string(),
+        _ => bail!("Invalid login: {}", line),
+    };
+
+    let mut connection = Connection {
+        name: name.clone(),
+        stream,
+        reader,
+        alive: true,
+    };
+    if !logged_in.lock().unwrap().insert(name.clone()) {
+        connection.send("LOGIN:incorrect");
+        bail!("{} is already logged in", name);
+    }
+    connection.send(&format!("LOGIN:{} OK", name));
+    Ok(connection)
+}
+
+struct ServerState {
+    waiting: Mutex<VecDeque<Connection>>,
+    arrived: Condvar,
+    logged_in: Mutex<BTreeSet<String>>,
+}
+
+impl ServerState {
+    fn wait_pair(&self) -> (Connection, Connection) {
+        let mut waiting = self.waiting.lock().unwrap();
+        while waiting.len() < 2 {
+            waiting = self.arrived.wait(waiting).unwrap();
+        }
+        let first = waiting.pop_front().unwrap();
+        let second = waiting.pop_front().unwrap();
+        (first, second)
+    }
+
+    /// Puts the player back to the waiting queue, or forgets it if the connection was lost.
+    fn release(&self, connection: Connection) {
+        if connection.alive {
+            self.waiting.lock().unwrap().push_back(connection);
+            self.arrived.notify_one();
+        } else {
+            log::info!("{} left", connection.name);
+            self.logged_in.lock().unwrap().remove(&connection.name);
+        }
+    }
+}
+
+fn game_summary(config: &CsaServerConfig, game_id: &str, names: &[String], color: Color) -> String {
+    format!(
+        "BEGIN Game_Summary
+Protocol_Version:1.2
+Protocol_Mode:Server
+Format:Shogi 1.0
+Declaration:Jishogi 1.1
+Game_ID:{}
+Name+:{}
+Name-:{}
+Your_Turn:{}
+Rematch_On_Draw:NO
+To_Move:+
+Max_Moves:{}
+BEGIN Time
+Time_Unit:1sec
+Total_Time:{}
+Byoyomi:{}
+Least_Time_Per_Move:1
+END Time
+BEGIN Position
+PI
++
+END Position
+END Game_Summary",
+        game_id,
+        names[0],
+        names[1],
+        if color == Color::Black { '+' } else { '-' },
+        config.max_moves,
+        config.total_time,
+        config.byoyomi
+    )
+}
+
+/// Offers the game to both players. Returns false if either of them did not agree.
+fn agree(players: &mut [Connection; 2], config: &CsaServerConfig, game_id: &str) -> bool {
+    let names = [players[0].name.clone(), players[1].name.clone()];
+    for (player, color) in players.iter_mut().zip(&[Color::Black, Color::White]) {
+        player.send(&game_summary(config, game_id, &names, *color));
+    }
+
+    let deadline = Instant::now() + AGREE_TIMEOUT;
+    let mut rejected_by = None;
+    for player in players.iter_mut() {
+        let line = player.read_line(deadline).ok().flatten();
+        match line
+            .as_deref()
+            .and_then(|line| line.split_whitespace().next())
+        {
+            Some("AGREE") => {}
+            Some("LOGOUT") => {
+                player.send("LOGOUT:completed");
+                player.alive = false;
+                rejected_by = Some(player.name.clone());
+                break;
+            }
+            _ => {
+                rejected_by = Some(player.name.clone());
+                break;
+            }
+        }
+    }
+
+    match rejected_by {
+        Some(name) => {
+            for player in players.iter_mut() {
+                player.send(&format!("REJECT:{} by {}", game_id, name));
+            }
+            false
+        }
+        None => {
+            for player in players.iter_mut() {
+                player.send(&format!("START:{}", game_id));
+            }
+            true
+        }
+    }
+}
+
+/// Plays a game between players who agreed to it. `players[0]` is Black.
+fn play(
+    players: &mut [Connection; 2],
+    config: &CsaServerConfig,
+    record: &mut CsaRecord,
+) -> (Option<Color>, String) {
+    let broadcast = |players: &mut [Connection; 2], line: &str| {
+        for player in players.iter_mut() {
+            player.send(line);
+        }
+    };
+
+    let mut game = Game::new();
+    let mut remaining = [config.total_time; 2];
+    loop {
+        if game.moves().len() >= config.max_moves {
+            record.result = Some("%HIKIWAKE".to_string());
+            return (None, "#MAX_MOVES".to_string());
+        }
+
+        let turn = game.next_turn();
+        let index = (turn == Color::White) as usize;
+        let limit = remaining[index] + config.byoyomi;
+        let start = Instant::now();
+        let line = players[index].read_line(start + Duration::from_secs(limit) + TIME_MARGIN);
+        let seconds = start.elapsed().as_secs().max(1);
+
+        let line = match line {
+            Ok(Some(line)) if seconds <= limit => line,
+            Ok(_) => {
+                record.result = Some("%TIME_UP".to_string());
+                return (Some(opponent(turn)), "#TIME_UP".to_string());
+            }
+            Err(_) => {
+                record.result = Some("%CHUDAN".to_string());
+                return (Some(opponent(turn)), "#ABNORMAL".to_string());
+            }
+        };
+        remaining[index] = remaining[index].saturating_sub(seconds);
+
+        match line.as_str() {
+            "%TORYO" => {
+                broadcast(players, &format!("%TORYO,T{}", seconds));
+                record.result = Some("%TORYO".to_string());
+                return (Some(opponent(turn)), "#RESIGN".to_string());
+            }
+            "%KACHI" => {
+                record.result = Some("%KACHI".to_string());
+                return if game.can_declare_win() {
+                    broadcast(players, "%KACHI");
+                    (Some(turn), "#JISHOGI".to_string())
+                } else {
+                    (Some(opponent(turn)), "#ILLEGAL_MOVE".to_string())
+                };
+            }
+            _ => {}
+        }
+
+        let mv = line.split(',').next().unwrap();
+        if game.play_csa(mv).is_err() {
+            record.result = Some("%ILLEGAL_MOVE".to_string());
+            return (Some(opponent(turn)), "#ILLEGAL_MOVE".to_string());
+        }
+        broadcast(players, &format!("{},T{}", mv, seconds));
+        record.push(mv.to_string(), Some(seconds));
+
+        match game.sennichite() {
+            Some(Sennichite::Draw) => {
+                record.result = Some("%SENNICHITE".to_string());
+                return (None, "#SENNICHITE".to_string());
+            }
+            Some(Sennichite::PerpetualCheck(checker)) => {
+                record.result = Some(illegal_action(checker));
+                return (Some(opponent(checker)), "#OUTE_SENNICHITE".to_string());
+            }
+            None => {}
+        }
+    }
+}
+
+fn run_game(
+    players: &mut [Connection; 2],
+    config: &CsaServerConfig,
+    game_id: &str,
+    standings: &Mutex<Standings>,
+) -> Result<Option<ServerGameResult>> {
+    if !agree(players, config, game_id) {
+        return Ok(None);
+    }
+
+    let black_name = players[0].name.clone();
+    let white_name = players[1].name.clone();
+    let mut record = CsaRecord::new(&black_name, &white_name);
+    record.event = Some(game_id.to_string());
+    {
+        let standings = standings.lock().unwrap();
+        record.header_comments = vec![
+            format!(
+                "black_rate:{}:{:.1}",
+                black_name,
+                standings.rate(&black_name)
+            ),
+            format!(
+                "white_rate:{}:{:.1}",
+                white_name,
+                standings.rate(&white_name)
+            ),
+        ];
+    }
+
+    let (winner, reason) = play(players, config, &mut record);
+    for (player, color) in players.iter_mut().zip(&[Color::Black, Color::White]) {
+        player.send(&reason);
+        player.send(match winner {
+            Some(winner) if winner == *color => "#WIN",
+            Some(_) => "#LOSE",
+            None if reason == "#MAX_MOVES" => "#CENSORED",
+            None => "#DRAW",
+        });
+    }
+
+    let mut standings = standings.lock().unwrap();
+    standings.record(&black_name, &white_name, winner);
+    if let Some(out_dir) = config.out_dir.as_ref() {
+        create_dir_all(out_dir)?;
+        let mut file = File::create(out_dir.join(format!("{}.csa", game_id)))?;
+        write!(file, "{}", record)?;
+    }
+    if let Some(standings_path) = config.standings_path.as_ref() {
+        let mut file = File::create(standings_path)?;
+        write!(file, "{}", standings)?;
+    }
+    log::info!("{} {} {:?}\n{}", game_id, reason, winner, standings);
+
+    Ok(Some(ServerGameResult {
+        game_id: game_id.to_string(),
+        black_name,
+        white_name,
+        winner,
+        reason,
+        record,
+    }))
+}
+
+/// Accepts players on `listener` and pairs them until `config.games` games are over.
+pub fn serve(listener: TcpListener, config: CsaServerConfig) -> Result<Vec<ServerGameResult>> {
+    let state = Arc::new(ServerState {
+        waiting: Mutex::new(VecDeque::new()),
+        arrived: Condvar::new(),
+        logged_in: Mutex::new(BTreeSet::new()),
+    });
+    let standings = Arc::new(Mutex::new(Standings::new(config.initial_rate)));
+
+    {
+        let state = state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        log::warn!("{}", e);
+                        continue;
+                    }
+                };
+                let state = state.clone();
+                thread::spawn(move || match login(stream, &state.logged_in) {
+                    Ok(connection) => {
+                        log::info!("{} logged in", connection.name);
+                        state.release(connection);
+                    }
+                    Err(e) => log::warn!("{}", e),
+                });
+            }
+        });
+    }
+
+    let mut handles = vec![];
+    let mut game_number = 0;
+    while config.games.map_or(true, |games| game_number < games) {
+        let (first, second) = state.wait_pair();
+        game_number += 1;
+        // Alternate colors between games.
+        let mut players = if game_number % 2 == 1 {
+            [first, second]
+        } else {
+            [second, first]
+        };
+        let game_id = format!(
+            "local+{}+{}+{}",
+            game_number, players[0].name, players[1].name
+        );
+
+        let state = state.clone();
+        let standings = standings.clone();
+        let config = config.clone();
+        handles.push(thread::spawn(move || {
+            let result = run_game(&mut players, &config, &game_id, &standings);
+            let [black, white] = players;
+            state.release(black);
+            state.release(white);
+            result
+        }));
+    }
+
+    let mut results = vec![];
+    for handle in handles {
+        let result = handle
+            .join()
+            .map_err(|_| anyhow!("Game thread panicked"))??;
+        results.extend(result);
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_standings() {
+        let mut standings = Standings::new(1500.0);
+        standings.record("a", "b", Some(Color::Black));
+        assert_eq!(standings.rate("a"), 1508.0);
+        assert_eq!(standings.rate("b"), 1492.0);
+        assert_eq!(standings.rate("c"), 1500.0);
+
+        standings.record("b", "a", None);
+        let a = standings.get("a").unwrap();
+        let b = standings.get("b").unwrap();
+        assert_eq!((a.wins, a.draws, a.losses), (1, 1, 0));
+        assert_eq!((b.wins, b.draws, b.losses), (0, 1, 1));
+        assert!(a.rate < 1508.0 && b.rate > 1492.0);
+        assert!((a.rate + b.rate - 3000.0).abs() < 1e-9);
+
+        let table = standings.to_string();
+        let lines = table.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].contains(" a "));
+        assert!(lines[2].contains(" b "));
+    }
+}
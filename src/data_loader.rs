@@ -1,6 +1,62 @@
+use crate::constants::MOVE_DIRECTION_LABEL_NUM;
+use crate::features::InputFeatures;
+use crate::model::Position;
+use crate::util::board_packer::ToFlatVec;
+use anyhow::{bail, Result};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use tch::kind::Element;
 use tch::Tensor;
 
+pub fn load_bin_file(filepath: &str) -> Result<Vec<Position>> {
+    log::info!("Loading {}", filepath);
+    let mut f = File::open(filepath)?;
+    let mut buf = vec![];
+    f.read_to_end(&mut buf)?;
+    let kifu: Vec<Position> = bincode::deserialize(&buf)?;
+    Ok(kifu)
+}
+
+/// Path of the labels of the legal moves of the positions in `bin_filepath`, e.g.
+/// `test.bin.legal`. They are kept out of `Position` since only evaluation uses them.
+pub fn legal_moves_path_for<P: AsRef<Path>>(bin_filepath: P) -> PathBuf {
+    let mut path = bin_filepath.as_ref().as_os_str().to_owned();
+    path.push(".legal");
+    PathBuf::from(path)
+}
+
+/// Labels of the legal moves of every one of `positions` loaded from `bin_filepath`, in the same
+/// orientation as `Position::move_label`, or `None` if they were not written.
+pub fn load_legal_moves(
+    bin_filepath: &str,
+    positions: &[Position],
+) -> Result<Option<Vec<Vec<i16>>>> {
+    let path = legal_moves_path_for(bin_filepath);
+    if !path.exists() {
+        return Ok(None);
+    }
+    log::info!("Loading {}", path.display());
+    let mut f = File::open(path)?;
+    let mut buf = vec![];
+    f.read_to_end(&mut buf)?;
+    let legal_moves: Vec<Vec<i16>> = bincode::deserialize(&buf)?;
+    if legal_moves.len() != positions.len() {
+        bail!(
+            "{} has the legal moves of {} positions, not {}",
+            bin_filepath,
+            legal_moves.len(),
+            positions.len()
+        );
+    }
+    Ok(Some(legal_moves))
+}
+
+/// Inputs of `FeatureSet::V1` without history, which are the stored planes.
+pub fn position_to_features(position: &Position) -> (Vec<f32>, i16) {
+    (position.features.to_flat_vec(), position.move_label)
+}
+
 pub struct DataLoader<'a, T, F> {
     data: &'a [T],
     loader: F,
@@ -17,6 +73,12 @@ impl<'a, T, F> DataLoader<'a, T, F> {
             cur_position: 0,
         }
     }
+
+    /// Skips the first `batches` batches without loading them.
+    pub fn skip_batches(mut self, batches: usize) -> Self {
+        self.cur_position += batches;
+        self
+    }
 }
 
 impl<'a, T, F, Feature, Label> Iterator for DataLoader<'a, T, F>
@@ -46,7 +108,143 @@ where
 
     fn size_hint(&self) -> (usize, Option<usize>) {
         let size = self.data.len() / self.batchsize;
-        let remain = size - self.cur_position;
+        let remain = size.saturating_sub(self.cur_position);
+        (remain, Some(remain))
+    }
+}
+
+/// Writes the inputs of `positions`, encoded with `input_features`, to `out` of
+/// `positions.len() * input_features.input_channels() * 81` values.
+pub fn write_batch_features(
+    input_features: InputFeatures,
+    positions: &[Position],
+    out: &mut [f32],
+) {
+    let size = input_features.input_channels() * 9 * 9;
+    assert_eq!(out.len(), positions.len() * size);
+    for (position, out) in positions.iter().zip(out.chunks_exact_mut(size)) {
+        input_features.write_features(&position.features, position.move_number, out);
+    }
+}
+
+/// Policy targets of `positions` of shape `[N, labels]`: the distribution of `policy_target` for
+/// positions which have one and the played move for the others. `None` if no position has one,
+/// in which case the move labels alone are the targets.
+pub fn policy_targets(positions: &[Position]) -> Option<Tensor> {
+    if positions
+        .iter()
+        .all(|position| position.policy_target.is_empty())
+    {
+        return None;
+    }
+    let labels = 9 * 9 * MOVE_DIRECTION_LABEL_NUM as usize;
+    let mut targets = vec![0f32; positions.len() * labels];
+    for (position, target) in positions.iter().zip(targets.chunks_exact_mut(labels)) {
+        if position.policy_target.is_empty() {
+            target[position.move_label as usize] = 1.0;
+        }
+        for &(label, probability) in position.policy_target.iter() {
+            target[label as usize] = probability;
+        }
+    }
+    Some(Tensor::of_slice(&targets).view((positions.len() as i64, labels as i64)))
+}
+
+/// Batches of inputs and move labels of `positions`, built in one buffer reused by every batch
+/// instead of a `Vec` per position.
+pub struct PositionBatches<'a> {
+    positions: &'a [Position],
+    input_features: InputFeatures,
+    batchsize: usize,
+    cur_position: usize,
+    features: Vec<f32>,
+    labels: Vec<i16>,
+}
+
+impl<'a> PositionBatches<'a> {
+    pub fn new(positions: &'a [Position], input_features: InputFeatures, batchsize: usize) -> Self {
+        Self {
+            positions,
+            input_features,
+            batchsize,
+            cur_position: 0,
+            features: vec![0.0; batchsize * input_features.input_channels() * 9 * 9],
+            labels: Vec::with_capacity(batchsize),
+        }
+    }
+
+    /// Skips the first `batches` batches without loading them.
+    pub fn skip_batches(mut self, batches: usize) -> Self {
+        self.cur_position += batches;
+        self
+    }
+}
+
+impl<'a> Iterator for PositionBatches<'a> {
+    type Item = (Tensor, Tensor);
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.cur_position * self.batchsize;
+        if start + self.batchsize > self.positions.len() {
+            return None;
+        }
+        let batch = &self.positions[start..start + self.batchsize];
+        write_batch_features(self.input_features, batch, &mut self.features);
+        self.labels.clear();
+        self.labels
+            .extend(batch.iter().map(|position| position.move_label));
+
+        self.cur_position += 1;
+        Some((
+            Tensor::of_slice(&self.features),
+            Tensor::of_slice(&self.labels),
+        ))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remain = (self.positions.len() / self.batchsize).saturating_sub(self.cur_position);
         (remain, Some(remain))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::FeatureSet;
+    use crate::util::board_packer::BoardPacker;
+    use shogiutil::Board;
+
+    #[test]
+    fn test_position_batches() {
+        let positions = (0..5)
+            .map(|i| Position::new(Board::default().encode().to_vec(), i))
+            .collect::<Vec<_>>();
+        let batches =
+            PositionBatches::new(&positions, FeatureSet::V1.into(), 2).collect::<Vec<_>>();
+        let expected = DataLoader::new(&positions, position_to_features, 2).collect::<Vec<_>>();
+        assert_eq!(batches.len(), 2);
+        for ((x, t), (expected_x, expected_t)) in batches.iter().zip(expected.iter()) {
+            assert_eq!(Vec::<f32>::from(x), Vec::<f32>::from(expected_x));
+            assert_eq!(Vec::<i16>::from(t), Vec::<i16>::from(expected_t));
+        }
+
+        let skipped = PositionBatches::new(&positions, FeatureSet::V1.into(), 2).skip_batches(3);
+        assert_eq!(skipped.size_hint(), (0, Some(0)));
+        let skipped = DataLoader::new(&positions, position_to_features, 2).skip_batches(3);
+        assert_eq!(skipped.size_hint(), (0, Some(0)));
+    }
+
+    #[test]
+    fn test_policy_targets() {
+        let mut positions = (0..2).map(|i| Position::new(vec![], i)).collect::<Vec<_>>();
+        assert!(policy_targets(&positions).is_none());
+
+        positions[1].policy_target = vec![(3, 0.25), (5, 0.75)];
+        let targets = policy_targets(&positions).unwrap();
+        assert_eq!(targets.size(), [2, 2187]);
+        let targets = Vec::<f32>::from(&targets.view(-1));
+        assert_eq!(targets[0], 1.0);
+        assert_eq!(targets[2187 + 3], 0.25);
+        assert_eq!(targets[2187 + 5], 0.75);
+        assert_eq!(targets.iter().sum::<f32>(), 2.0);
+    }
+}
@@ -0,0 +1,72 @@
+//! Knowledge distillation, which trains a student `PolicyNetwork` to match the policy of a
+//! teacher, typically larger, network along with the labels.
+use tch::kind::Kind::Double;
+use tch::Tensor;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Distillation {
+    /// Temperature dividing the logits of both networks before the softmax.
+    pub temperature: f64,
+    /// Weight of the distillation loss. The loss of the labels is weighted by `1 - weight`.
+    pub weight: f64,
+}
+
+impl Distillation {
+    /// KL divergence from the softened policy of the teacher to that of the student, averaged over
+    /// the batch and multiplied by `temperature^2` so that its gradients keep their scale.
+    pub fn soft_loss(&self, student: &Tensor, teacher: &Tensor) -> Tensor {
+        let batchsize = student.size()[0].max(1) as f64;
+        let teacher = teacher / self.temperature;
+        let p = teacher.softmax(-1, Double);
+        let log_p = teacher.log_softmax(-1, Double);
+        let log_q = (student / self.temperature).log_softmax(-1, Double);
+        (p * (log_p - log_q)).sum(Double) / batchsize * (self.temperature * self.temperature)
+    }
+
+    /// `hard_loss` of the labels mixed with `soft_loss`.
+    pub fn loss(&self, hard_loss: &Tensor, soft_loss: &Tensor) -> Tensor {
+        hard_loss * (1.0 - self.weight) + soft_loss * self.weight
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tch::kind::Kind::Float;
+    use tch::Device;
+
+    #[test]
+    fn test_distillation() {
+        let distillation = Distillation {
+            temperature: 2.0,
+            weight: 0.25,
+        };
+        let teacher = Tensor::of_slice(&[1.0f32, 2.0, 3.0, 0.0, 0.0, 4.0]).view((2, 3));
+        let soft_loss = f64::from(&distillation.soft_loss(&teacher, &teacher));
+        assert!(soft_loss.abs() < 1e-9);
+
+        // Logits shifted by a constant give the same policy.
+        let student = &teacher + 5.0;
+        assert!(f64::from(&distillation.soft_loss(&student, &teacher)).abs() < 1e-9);
+
+        let student = Tensor::zeros(&[2, 3], (Float, Device::Cpu));
+        let soft_loss = distillation.soft_loss(&student, &teacher);
+        let expected = [[0.5, 1.0, 1.5], [0.0, 0.0, 2.0]]
+            .iter()
+            .map(|logits| {
+                let z = logits.iter().map(|l: &f64| l.exp()).sum::<f64>();
+                logits
+                    .iter()
+                    .map(|l| l.exp() / z * (l.exp() / z * 3.0).ln())
+                    .sum::<f64>()
+            })
+            .sum::<f64>()
+            / 2.0
+            * 4.0;
+        assert!((f64::from(&soft_loss) - expected).abs() < 1e-6);
+
+        let hard_loss = Tensor::from(2.0f64);
+        let loss = f64::from(&distillation.loss(&hard_loss, &soft_loss));
+        assert!((loss - (0.75 * 2.0 + 0.25 * expected)).abs() < 1e-6);
+    }
+}
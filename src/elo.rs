@@ -0,0 +1,77 @@
+/// Win/draw/loss counts of one player against another.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct MatchResult {
+    pub wins: usize,
+    pub draws: usize,
+    pub losses: usize,
+}
+
+impl MatchResult {
+    pub fn games(&self) -> usize {
+        self.wins + self.draws + self.losses
+    }
+
+    pub fn score(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games() as f64
+    }
+
+    /// Elo difference implied by the score.
+    pub fn elo(&self) -> f64 {
+        score_to_elo(self.score())
+    }
+
+    /// 95% confidence interval of the Elo difference, from the normal approximation of the
+    /// mean game score.
+    pub fn elo_interval(&self) -> (f64, f64) {
+        let n = self.games() as f64;
+        let score = self.score();
+        let variance = (self.wins as f64 * (1.0 - score).powi(2)
+            + self.draws as f64 * (0.5 - score).powi(2)
+            + self.losses as f64 * score.powi(2))
+            / n;
+        let margin = 1.96 * (variance / n).sqrt();
+        (score_to_elo(score - margin), score_to_elo(score + margin))
+    }
+}
+
+fn score_to_elo(score: f64) -> f64 {
+    if score <= 0.0 {
+        f64::NEG_INFINITY
+    } else if score >= 1.0 {
+        f64::INFINITY
+    } else {
+        -400.0 * (1.0 / score - 1.0).log10()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_elo() {
+        let even = MatchResult {
+            wins: 10,
+            draws: 0,
+            losses: 10,
+        };
+        assert!(even.elo().abs() < 1e-9);
+        let (lower, upper) = even.elo_interval();
+        assert!(lower < 0.0 && upper > 0.0);
+        assert!((lower + upper).abs() < 1e-9);
+
+        let strong = MatchResult {
+            wins: 75,
+            draws: 0,
+            losses: 25,
+        };
+        assert!((strong.elo() - 190.848).abs() < 1e-3);
+
+        let perfect = MatchResult {
+            wins: 10,
+            draws: 0,
+            losses: 0,
+        };
+        assert_eq!(perfect.elo(), f64::INFINITY);
+    }
+}
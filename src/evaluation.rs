@@ -0,0 +1,330 @@
+use crate::constants::{MOVE_DIRECTIONS, PIECE_NAMES};
+use crate::data_loader::PositionBatches;
+use crate::features::InputFeatures;
+use crate::model::Position;
+use crate::progressbar::ToProgressBar;
+use std::cmp::Ordering;
+use std::fmt::{self, Display, Formatter};
+use tch::kind::Kind::Double;
+use tch::nn::Module;
+use tch::{no_grad, Device, Tensor};
+
+pub const TOP_K: [usize; 4] = [1, 3, 5, 10];
+
+/// Games are split into phases at these move numbers.
+pub const PHASE_BOUNDARIES: [u16; 4] = [30, 60, 90, 120];
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Counter {
+    pub correct: usize,
+    pub total: usize,
+}
+
+impl Counter {
+    fn add(&mut self, correct: bool) {
+        if correct {
+            self.correct += 1;
+        }
+        self.total += 1;
+    }
+
+    pub fn accuracy(&self) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            self.correct as f64 / self.total as f64
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Metrics {
+    pub sum_loss: f64,
+    pub top_k: [Counter; 4],
+    /// Top-1 accuracy when the prediction is restricted to legal moves, over the positions whose
+    /// legal moves are known.
+    pub legal: Counter,
+    pub by_phase: [Counter; 5],
+    /// Top-1 accuracy of board moves and of drops.
+    pub by_move_kind: [Counter; 2],
+    pub by_piece: [Counter; 15],
+}
+
+impl Metrics {
+    /// Adds a position given the log probabilities of all labels, and the labels of its legal
+    /// moves if they are known.
+    pub fn add(
+        &mut self,
+        log_probability: &[f64],
+        position: &Position,
+        legal_move_labels: Option<&[i16]>,
+    ) {
+        let label = position.move_label as usize;
+        let target = log_probability[label];
+        self.sum_loss -= target;
+
+        let rank = log_probability.iter().filter(|&&p| p > target).count();
+        for (counter, &k) in self.top_k.iter_mut().zip(TOP_K.iter()) {
+            counter.add(rank < k);
+        }
+
+        if let Some(legal_move_labels) = legal_move_labels {
+            let legal_best = legal_move_labels
+                .iter()
+                .max_by(|&&a, &&b| {
+                    log_probability[a as usize]
+                        .partial_cmp(&log_probability[b as usize])
+                        .unwrap_or(Ordering::Equal)
+                })
+                .cloned();
+            self.legal.add(legal_best == Some(position.move_label));
+        }
+
+        let correct = rank == 0;
+        self.by_phase[phase(position.move_number)].add(correct);
+        self.by_move_kind[is_drop(position.move_label) as usize].add(correct);
+        self.by_piece[position.piece as usize].add(correct);
+    }
+
+    pub fn total(&self) -> usize {
+        self.top_k[0].total
+    }
+
+    /// Mean negative log likelihood, 0 if there are no positions.
+    pub fn loss(&self) -> f64 {
+        if self.total() == 0 {
+            0.0
+        } else {
+            self.sum_loss / self.total() as f64
+        }
+    }
+
+    /// Top-1 accuracy.
+    pub fn accuracy(&self) -> f64 {
+        self.top_k[0].accuracy()
+    }
+
+    pub fn top_k_accuracy(&self, k: usize) -> Option<f64> {
+        let i = TOP_K.iter().position(|&top_k| top_k == k)?;
+        Some(self.top_k[i].accuracy())
+    }
+}
+
+impl Display for Metrics {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "positions={} loss={:.5}", self.total(), self.loss())?;
+        for (counter, k) in self.top_k.iter().zip(TOP_K.iter()) {
+            writeln!(f, "top{:<2} accuracy={:.5}", k, counter.accuracy())?;
+        }
+        if self.legal.total > 0 {
+            writeln!(f, "legal accuracy={:.5}", self.legal.accuracy())?;
+        }
+
+        let mut from = 1;
+        for (i, counter) in self.by_phase.iter().enumerate() {
+            let range = match PHASE_BOUNDARIES.get(i) {
+                Some(&to) => format!("{}-{}", from, to),
+                None => format!("{}-", from),
+            };
+            writeln!(
+                f,
+                "moves {:<8} accuracy={:.5} ({})",
+                range,
+                counter.accuracy(),
+                counter.total
+            )?;
+            from = PHASE_BOUNDARIES.get(i).map(|&to| to + 1).unwrap_or(from);
+        }
+
+        for (name, counter) in ["board", "drop"].iter().zip(self.by_move_kind.iter()) {
+            writeln!(
+                f,
+                "{:<5} accuracy={:.5} ({})",
+                name,
+                counter.accuracy(),
+                counter.total
+            )?;
+        }
+        for (name, counter) in PIECE_NAMES.iter().zip(self.by_piece.iter()).skip(1) {
+            if counter.total > 0 {
+                writeln!(
+                    f,
+                    "{} accuracy={:.5} ({})",
+                    name,
+                    counter.accuracy(),
+                    counter.total
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn phase(move_number: u16) -> usize {
+    PHASE_BOUNDARIES
+        .iter()
+        .filter(|&&boundary| move_number > boundary)
+        .count()
+}
+
+fn is_drop(move_label: i16) -> bool {
+    move_label as usize / (9 * 9) >= MOVE_DIRECTIONS.len()
+}
+
+/// Metrics of `model` over `positions`, a `PolicyNetwork` or any module with the same outputs,
+/// whose inputs are of `input_features`. `legal_moves` are the labels of the legal moves of each
+/// position, as loaded by `load_legal_moves`. The positions after the last full batch are
+/// evaluated as one smaller batch.
+pub fn evaluate<M: Module + ?Sized>(
+    positions: &[Position],
+    legal_moves: Option<&[Vec<i16>]>,
+    input_features: InputFeatures,
+    batchsize: usize,
+    model: &M,
+    device: Device,
+) -> Metrics {
+    let mut metrics = Metrics::default();
+    let full_batches = positions.len() / batchsize * batchsize;
+    let rest = &positions[full_batches..];
+    no_grad(|| {
+        let loader = PositionBatches::new(positions, input_features, batchsize)
+            .zip(positions.chunks_exact(batchsize))
+            .enumerate();
+        for (i, ((x, _), batch)) in loader.progress(|state| log::info!("validation {}", state)) {
+            let legal_moves = legal_moves
+                .map(|legal_moves| &legal_moves[i * batchsize..i * batchsize + batch.len()]);
+            add_batch(
+                &mut metrics,
+                model,
+                &x,
+                batch,
+                legal_moves,
+                input_features,
+                device,
+            );
+        }
+        if rest.is_empty() {
+            return;
+        }
+        if let Some((x, _)) = PositionBatches::new(rest, input_features, rest.len()).next() {
+            let legal_moves = legal_moves.map(|legal_moves| &legal_moves[full_batches..]);
+            add_batch(
+                &mut metrics,
+                model,
+                &x,
+                rest,
+                legal_moves,
+                input_features,
+                device,
+            );
+        }
+    });
+    metrics
+}
+
+/// Adds the predictions of `model` for the inputs `x` of `batch` to `metrics`.
+fn add_batch<M: Module + ?Sized>(
+    metrics: &mut Metrics,
+    model: &M,
+    x: &Tensor,
+    batch: &[Position],
+    legal_moves: Option<&[Vec<i16>]>,
+    input_features: InputFeatures,
+    device: Device,
+) {
+    let x = x
+        .view((
+            batch.len() as i64,
+            input_features.input_channels() as i64,
+            9,
+            9,
+        ))
+        .to_device(device);
+    let y = model.forward(&x);
+    let log_probability = y.log_softmax(-1, Double);
+    let labels = log_probability.size()[1] as usize;
+    let log_probability = Vec::<f64>::from(&log_probability.view(-1).to_device(Device::Cpu));
+    for (j, (position, log_probability)) in
+        batch.iter().zip(log_probability.chunks(labels)).enumerate()
+    {
+        let legal_move_labels = legal_moves.map(|legal_moves| legal_moves[j].as_slice());
+        metrics.add(log_probability, position, legal_move_labels);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::config::ModelConfig;
+    use crate::network::policy::PolicyNetwork;
+    use crate::util::board_packer::BoardPacker;
+    use shogiutil::Board;
+    use tch::nn::VarStore;
+
+    fn position(move_label: i16, move_number: u16) -> Position {
+        Position {
+            move_number,
+            ..Position::new(vec![], move_label)
+        }
+    }
+
+    #[test]
+    fn test_metrics() {
+        let log_probability = [-0.5, -1.0, -2.0, -3.0, -4.0];
+        let mut metrics = Metrics::default();
+        metrics.add(&log_probability, &position(0, 1), Some(&[0, 1][..]));
+        metrics.add(&log_probability, &position(2, 31), Some(&[2, 3][..]));
+        metrics.add(&log_probability, &position(4, 200), Some(&[0, 4][..]));
+        metrics.add(&log_probability, &position(1, 2), None);
+
+        assert_eq!(metrics.total(), 4);
+        assert!((metrics.loss() - 7.5 / 4.0).abs() < 1e-9);
+        assert_eq!(metrics.top_k[0].correct, 1);
+        assert_eq!(metrics.top_k[1].correct, 3);
+        assert_eq!(metrics.top_k[2].correct, 4);
+        assert_eq!(metrics.legal.correct, 2);
+        assert_eq!(metrics.legal.total, 3);
+        assert_eq!(metrics.by_phase[0].correct, 1);
+        assert_eq!(metrics.by_phase[1].total, 1);
+        assert_eq!(metrics.by_phase[4].total, 1);
+        assert_eq!(metrics.by_move_kind[0].total, 4);
+    }
+
+    #[test]
+    fn test_empty_metrics() {
+        let metrics = Metrics::default();
+        assert_eq!(metrics.loss(), 0.0);
+        assert_eq!(metrics.accuracy(), 0.0);
+    }
+
+    #[test]
+    fn test_evaluate_last_batch() {
+        let vs = VarStore::new(Device::Cpu);
+        let config = ModelConfig {
+            channels: 8,
+            layers: 1,
+            ..ModelConfig::default()
+        };
+        let model = PolicyNetwork::new(&vs.root(), &config);
+        let positions = (0..5)
+            .map(|i| Position::new(Board::default().encode().to_vec(), i))
+            .collect::<Vec<_>>();
+        let legal_moves = vec![vec![0, 1, 2, 3, 4]; positions.len()];
+        let metrics = evaluate(
+            &positions,
+            Some(&legal_moves[..]),
+            config.input_features(),
+            2,
+            &model,
+            Device::Cpu,
+        );
+        assert_eq!(metrics.total(), 5);
+        assert_eq!(metrics.legal.total, 5);
+    }
+
+    #[test]
+    fn test_is_drop() {
+        assert!(!is_drop(9 * 9 * 19 + 80));
+        assert!(is_drop(9 * 9 * 20));
+    }
+}
@@ -0,0 +1,630 @@
+//! Versioned sets of input features. A model is trained on one feature set, which is stored in its
+//! `ModelConfig`, and has `input_channels()` input planes.
+//!
+//! Features are stored in `Position::features` as the bit planes returned by `encode`, and
+//! expanded to floats by `write_features`. Every plane is seen from the player to move, with the
+//! board rotated by 180 degrees when White is to move.
+//!
+//! - `V1`: pieces of both sides and unary counts of pieces in hand, the planes of
+//!   `BoardPacker::encode` (104 planes).
+//! - `V2`: `V1`, the squares attacked by the player to move and by the opponent, a full plane if
+//!   the player to move is in check, the pinned pieces of both sides, three full planes if the
+//!   position has appeared at least once, twice and three times before, and a plane of the move
+//!   number divided by `MAX_MOVE_NUMBER` (113 planes).
+//!
+//! `InputFeatures` may stack `History` planes of the positions and moves before the current one
+//! on the bit planes of the feature set, before its move number plane.
+use crate::game::{legal_moves, opponent};
+use crate::util::board_packer::{BoardPacker, ToFlatVec, BOARD_PLANES};
+use anyhow::{anyhow, bail, Error, Result};
+use serde::{Deserialize, Serialize};
+use shogiutil::{Bitboard, Board, Color, Move, Square, UsiRequest};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeatureSet {
+    V1,
+    V2,
+}
+
+impl Default for FeatureSet {
+    fn default() -> Self {
+        FeatureSet::V1
+    }
+}
+
+impl FromStr for FeatureSet {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "v1" => Ok(FeatureSet::V1),
+            "v2" => Ok(FeatureSet::V2),
+            _ => Err(anyhow!("Unknown feature set: {}", s)),
+        }
+    }
+}
+
+const V1_PLANES: usize = BOARD_PLANES;
+const REPETITION_PLANES: usize = 3;
+const V2_PLANES: usize = V1_PLANES + 2 + 1 + 2 + REPETITION_PLANES;
+
+/// Move numbers are divided by this and clipped to 1 in the move number plane.
+pub const MAX_MOVE_NUMBER: u16 = 256;
+
+/// Number of past positions kept in a `FeatureContext`, the most `History` can stack.
+pub const MAX_HISTORY: usize = 8;
+
+/// A position before the current one and the move played in it. Squares are the indices
+/// `i * 9 + j` of `Square::to_pos()` as seen from Black.
+#[derive(Clone)]
+pub struct PastPosition {
+    pub board: Board,
+    /// `None` for drops.
+    pub from: Option<usize>,
+    pub to: usize,
+}
+
+impl PastPosition {
+    /// `mv` is played in `board`, with squares as seen from Black.
+    pub fn new(board: Board, mv: &Move) -> PastPosition {
+        PastPosition {
+            board,
+            from: mv.from.as_ref().map(square_index),
+            to: square_index(&mv.to),
+        }
+    }
+}
+
+fn square_index(square: &Square) -> usize {
+    let (i, j) = square.to_pos();
+    i as usize * 9 + j as usize
+}
+
+/// What the features depend on besides the board.
+#[derive(Clone)]
+pub struct FeatureContext {
+    /// 1-origin number of the move about to be played.
+    pub move_number: u16,
+    /// Number of times the position has appeared before.
+    pub repetitions: u8,
+    /// Up to `MAX_HISTORY` past positions, the latest first.
+    pub history: Vec<PastPosition>,
+}
+
+impl Default for FeatureContext {
+    fn default() -> Self {
+        Self {
+            move_number: 1,
+            repetitions: 0,
+            history: vec![],
+        }
+    }
+}
+
+impl FeatureContext {
+    /// Context of the position of a USI command such as `position startpos moves 7g7f`. Positions
+    /// before an SFEN are unknown, so only repetitions after it are counted. The moves are replayed
+    /// one by one from the SFEN of the previous position, so a long game is not parsed once per
+    /// move.
+    pub fn from_position_command(command: &str) -> Result<FeatureContext> {
+        let tokens = command.split_whitespace().collect::<Vec<_>>();
+        let (start, first_move_number, rest) = match tokens.get(1) {
+            Some(&"startpos") => (2, 1, &tokens[2..]),
+            Some(&"sfen") if tokens.len() >= 6 => {
+                let move_number = tokens[5].parse().unwrap_or(1);
+                (6, move_number, &tokens[6..])
+            }
+            _ => bail!("Invalid position: {}", command),
+        };
+        let moves = match rest.first() {
+            Some(&"moves") => &rest[1..],
+            _ => &rest[..0],
+        };
+
+        let (mut board, mut next_turn) = parse_position(&tokens[..start].join(" "))?;
+        let mut contexts = ContextTracker {
+            move_number: first_move_number,
+            ..ContextTracker::default()
+        };
+        for &usi in moves {
+            let mv = legal_moves(&board, next_turn)
+                .into_iter()
+                .find(|mv| mv.to_usi() == usi)
+                .ok_or_else(|| anyhow!("Illegal move {} in {}", usi, command))?;
+            contexts.context(&board, next_turn);
+            let previous = board.clone();
+            mv.push_to(&mut board)?;
+            contexts.push(previous, &mv.mv);
+            next_turn = opponent(next_turn);
+        }
+        Ok(contexts.context(&board, next_turn))
+    }
+}
+
+/// Contexts of the positions of a game replayed move by move.
+pub struct ContextTracker {
+    move_number: u16,
+    occurrences: BTreeMap<(Vec<u128>, bool), u8>,
+    history: Vec<PastPosition>,
+}
+
+impl Default for ContextTracker {
+    fn default() -> Self {
+        Self {
+            move_number: 1,
+            occurrences: BTreeMap::new(),
+            history: vec![],
+        }
+    }
+}
+
+impl ContextTracker {
+    /// Context of `board` with `next_turn` to move, which is counted as an occurrence of it.
+    pub fn context(&mut self, board: &Board, next_turn: Color) -> FeatureContext {
+        let occurrences = self
+            .occurrences
+            .entry((board.encode().to_vec(), next_turn == Color::Black))
+            .or_insert(0);
+        let repetitions = *occurrences;
+        *occurrences = occurrences.saturating_add(1);
+        FeatureContext {
+            move_number: self.move_number,
+            repetitions,
+            history: self.history.clone(),
+        }
+    }
+
+    /// Records `mv` played in `board`, with squares as seen from Black.
+    pub fn push(&mut self, board: Board, mv: &Move) {
+        self.history.insert(0, PastPosition::new(board, mv));
+        self.history.truncate(MAX_HISTORY);
+        self.move_number = self.move_number.saturating_add(1);
+    }
+}
+
+impl FeatureSet {
+    /// Number of bit planes returned by `encode`.
+    pub fn bit_planes(&self) -> usize {
+        match self {
+            FeatureSet::V1 => V1_PLANES,
+            FeatureSet::V2 => V2_PLANES,
+        }
+    }
+
+    /// Number of input planes of the network.
+    pub fn input_channels(&self) -> usize {
+        match self {
+            FeatureSet::V1 => V1_PLANES,
+            FeatureSet::V2 => V2_PLANES + 1,
+        }
+    }
+
+    /// Bit planes of `board` as seen from `next_turn`.
+    pub fn encode(&self, board: &Board, next_turn: Color, context: &FeatureContext) -> Vec<u128> {
+        let rotated;
+        let board = if next_turn == Color::Black {
+            board
+        } else {
+            rotated = board.rotate180();
+            &rotated
+        };
+        let mut planes = board.encode().to_vec();
+        if *self == FeatureSet::V1 {
+            return planes;
+        }
+
+        let pieces = piece_map(board);
+        let full = Bitboard::full().0;
+        let king = board.piece_bb[KING].0 & board.occupied[0].0;
+        let opponent_attacks = attacks(&pieces, 1);
+        planes.push(attacks(&pieces, 0));
+        planes.push(opponent_attacks);
+        planes.push(if opponent_attacks & king != 0 {
+            full
+        } else {
+            0
+        });
+        planes.push(pinned(&pieces, 0));
+        planes.push(pinned(&pieces, 1));
+        for i in 0..REPETITION_PLANES {
+            planes.push(if (context.repetitions as usize) > i {
+                full
+            } else {
+                0
+            });
+        }
+        planes
+    }
+
+    /// Writes the planes following the bit planes, `(input_channels() - bit_planes()) * 81`
+    /// values.
+    fn write_scalars(&self, move_number: u16, out: &mut [f32]) {
+        if *self == FeatureSet::V2 {
+            let value = move_number.min(MAX_MOVE_NUMBER) as f32 / MAX_MOVE_NUMBER as f32;
+            out.iter_mut().for_each(|x| *x = value);
+        }
+    }
+}
+
+/// Planes of the positions and moves before the current one. Missing ones, before the start of
+/// the game or the SFEN of the position, are zeros.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct History {
+    /// Number of past positions, the latest first, whose `BoardPacker::encode` planes are stacked.
+    pub positions: usize,
+    /// Number of past moves, the latest first, whose from and to squares are stacked as two
+    /// planes each. The from plane of a drop is empty.
+    pub moves: usize,
+}
+
+impl History {
+    pub fn planes(&self) -> usize {
+        self.positions * BOARD_PLANES + self.moves * 2
+    }
+
+    /// Appends the planes of `context` as seen from `next_turn` to `planes`.
+    fn encode(&self, next_turn: Color, context: &FeatureContext, planes: &mut Vec<u128>) {
+        let rotate = next_turn == Color::White;
+        for k in 0..self.positions {
+            match context.history.get(k) {
+                Some(past) if rotate => planes.extend_from_slice(&past.board.rotate180().encode()),
+                Some(past) => planes.extend_from_slice(&past.board.encode()),
+                None => planes.extend_from_slice(&[0; BOARD_PLANES]),
+            }
+        }
+        let bit = |square: usize| 1u128 << if rotate { 80 - square } else { square };
+        for k in 0..self.moves {
+            match context.history.get(k) {
+                Some(past) => {
+                    planes.push(past.from.map(bit).unwrap_or(0));
+                    planes.push(bit(past.to));
+                }
+                None => planes.extend_from_slice(&[0, 0]),
+            }
+        }
+    }
+}
+
+/// Inputs of a network: the planes of a feature set with the history planes stacked on them.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct InputFeatures {
+    pub feature_set: FeatureSet,
+    pub history: History,
+}
+
+impl From<FeatureSet> for InputFeatures {
+    fn from(feature_set: FeatureSet) -> Self {
+        InputFeatures {
+            feature_set,
+            history: History::default(),
+        }
+    }
+}
+
+impl InputFeatures {
+    /// Number of bit planes returned by `encode`.
+    pub fn bit_planes(&self) -> usize {
+        self.feature_set.bit_planes() + self.history.planes()
+    }
+
+    /// Number of input planes of the network.
+    pub fn input_channels(&self) -> usize {
+        self.feature_set.input_channels() + self.history.planes()
+    }
+
+    /// Bit planes of `board` and the history of `context` as seen from `next_turn`.
+    pub fn encode(&self, board: &Board, next_turn: Color, context: &FeatureContext) -> Vec<u128> {
+        let mut planes = self.feature_set.encode(board, next_turn, context);
+        self.history.encode(next_turn, context, &mut planes);
+        planes
+    }
+
+    /// Writes the `input_channels() * 81` inputs of the network for `planes` returned by `encode`.
+    pub fn write_features(&self, planes: &[u128], move_number: u16, out: &mut [f32]) {
+        assert_eq!(planes.len(), self.bit_planes());
+        let (bits, scalars) = out.split_at_mut(planes.len() * 81);
+        planes.write_flat(bits);
+        self.feature_set.write_scalars(move_number, scalars);
+    }
+
+    /// Inputs of the network for `board` with `next_turn` to move.
+    pub fn board_features(
+        &self,
+        board: &Board,
+        next_turn: Color,
+        context: &FeatureContext,
+    ) -> Vec<f32> {
+        let planes = self.encode(board, next_turn, context);
+        let mut features = vec![0.0; self.input_channels() * 81];
+        self.write_features(&planes, context.move_number, &mut features);
+        features
+    }
+}
+
+fn parse_position(command: &str) -> Result<(Board, Color)> {
+    match UsiRequest::parse(command)? {
+        UsiRequest::Position { board, next_turn } => Ok((board, next_turn)),
+        _ => bail!("Invalid position: {}", command),
+    }
+}
+
+/// Indices of pieces in `Board::piece_bb`, in the order of `PIECE_NAMES`.
+const KY: usize = 2;
+const KE: usize = 3;
+const GI: usize = 4;
+const KA: usize = 6;
+const HI: usize = 7;
+const KING: usize = 8;
+const UM: usize = 13;
+const RY: usize = 14;
+
+/// Directions as (rank, file index) steps for Black, whose forward is toward rank 1.
+const GOLD: [(i32, i32); 6] = [(-1, -1), (-1, 0), (-1, 1), (0, -1), (0, 1), (1, 0)];
+const SILVER: [(i32, i32); 5] = [(-1, -1), (-1, 0), (-1, 1), (1, -1), (1, 1)];
+const KNIGHT: [(i32, i32); 2] = [(-2, -1), (-2, 1)];
+const PAWN: [(i32, i32); 1] = [(-1, 0)];
+const ORTHOGONAL: [(i32, i32); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+const DIAGONAL: [(i32, i32); 4] = [(-1, -1), (-1, 1), (1, -1), (1, 1)];
+const ALL: [(i32, i32); 8] = [
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+    (0, -1),
+    (0, 1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+];
+
+const NONE: [(i32, i32); 0] = [];
+
+/// Squares a piece moves to by one step, and directions it slides in.
+fn movement(piece: usize) -> (&'static [(i32, i32)], &'static [(i32, i32)]) {
+    let (steps, slides): (&[(i32, i32)], &[(i32, i32)]) = match piece {
+        1 => (&PAWN, &NONE),
+        KY => (&NONE, &PAWN),
+        KE => (&KNIGHT, &NONE),
+        GI => (&SILVER, &NONE),
+        KA => (&NONE, &DIAGONAL),
+        HI => (&NONE, &ORTHOGONAL),
+        KING => (&ALL, &NONE),
+        UM => (&ORTHOGONAL, &DIAGONAL),
+        RY => (&DIAGONAL, &ORTHOGONAL),
+        // KI, TO, NY, NK and NG
+        _ => (&GOLD, &NONE),
+    };
+    (steps, slides)
+}
+
+/// Whether a piece of the opponent of `color` attacks its king. Pinned pieces, which have no
+/// legal move to the king, still give check.
+pub fn is_king_attacked(board: &Board, color: Color) -> bool {
+    let color_id = (color == Color::White) as usize;
+    let king = board.piece_bb[KING].0 & board.occupied[color_id].0;
+    attacks(&piece_map(board), 1 - color_id) & king != 0
+}
+
+/// Color index and piece of every square.
+fn piece_map(board: &Board) -> [Option<(usize, usize)>; 81] {
+    let mut pieces = [None; 81];
+    for piece in 1..15 {
+        for color in 0..2 {
+            let mut bits = board.piece_bb[piece].0 & board.occupied[color].0;
+            while bits != 0 {
+                let square = bits.trailing_zeros() as usize;
+                pieces[square] = Some((color, piece));
+                bits &= bits - 1;
+            }
+        }
+    }
+    pieces
+}
+
+fn square(i: i32, j: i32) -> Option<usize> {
+    if (0..9).contains(&i) && (0..9).contains(&j) {
+        Some((i * 9 + j) as usize)
+    } else {
+        None
+    }
+}
+
+/// Steps of `color` in board coordinates; White moves toward rank 9.
+fn oriented(color: usize, (di, dj): (i32, i32)) -> (i32, i32) {
+    if color == 0 {
+        (di, dj)
+    } else {
+        (-di, dj)
+    }
+}
+
+/// Squares attacked by the pieces of `color`, including squares of its own pieces.
+fn attacks(pieces: &[Option<(usize, usize)>; 81], color: usize) -> u128 {
+    let mut attacked = 0u128;
+    for (from, piece) in pieces.iter().enumerate() {
+        let piece = match piece {
+            Some((c, piece)) if *c == color => *piece,
+            _ => continue,
+        };
+        let (i, j) = ((from / 9) as i32, (from % 9) as i32);
+        let (steps, slides) = movement(piece);
+        for &step in steps {
+            let (di, dj) = oriented(color, step);
+            if let Some(to) = square(i + di, j + dj) {
+                attacked |= 1 << to;
+            }
+        }
+        for &slide in slides {
+            let (di, dj) = oriented(color, slide);
+            let mut k = 1;
+            while let Some(to) = square(i + di * k, j + dj * k) {
+                attacked |= 1 << to;
+                if pieces[to].is_some() {
+                    break;
+                }
+                k += 1;
+            }
+        }
+    }
+    attacked
+}
+
+/// Pieces of `color` which cannot leave the line between its king and a sliding piece of the
+/// opponent without exposing the king.
+fn pinned(pieces: &[Option<(usize, usize)>; 81], color: usize) -> u128 {
+    let king = match pieces.iter().position(|&p| p == Some((color, KING))) {
+        Some(king) => king,
+        None => return 0,
+    };
+    let (i, j) = ((king / 9) as i32, (king % 9) as i32);
+    let mut pinned = 0u128;
+    for &(di, dj) in ALL.iter() {
+        let mut blocker = None;
+        let mut k = 1;
+        while let Some(to) = square(i + di * k, j + dj * k) {
+            match (pieces[to], blocker) {
+                (None, _) => {}
+                (Some((c, _)), None) if c == color => blocker = Some(to),
+                (Some((c, piece)), Some(blocker)) if c != color => {
+                    let (_, slides) = movement(piece);
+                    let toward_king = slides.iter().any(|&slide| oriented(c, slide) == (-di, -dj));
+                    if toward_king {
+                        pinned |= 1 << blocker;
+                    }
+                    break;
+                }
+                _ => break,
+            }
+            k += 1;
+        }
+    }
+    pinned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Game;
+
+    const GOLD_ID: usize = 5;
+
+    fn bit(i: usize, j: usize) -> u128 {
+        1 << (i * 9 + j)
+    }
+
+    #[test]
+    fn test_input_channels() {
+        assert_eq!(FeatureSet::V1.input_channels(), 104);
+        assert_eq!(FeatureSet::V2.bit_planes(), 112);
+        assert_eq!(FeatureSet::V2.input_channels(), 113);
+        assert_eq!("v2".parse::<FeatureSet>().unwrap(), FeatureSet::V2);
+    }
+
+    #[test]
+    fn test_encode_v2() {
+        let game = Game::from_opening("startpos moves 7g7f 3c3d").unwrap();
+        let context = FeatureContext::default();
+        let planes = FeatureSet::V2.encode(game.board(), game.next_turn(), &context);
+        assert_eq!(planes.len(), FeatureSet::V2.bit_planes());
+        assert_eq!(
+            &planes[..V1_PLANES],
+            &game.board().encode()[..],
+            "V1 planes come first"
+        );
+
+        // The bishop on 8h sees the bishop on 2b.
+        assert_ne!(planes[V1_PLANES] & bit(1, 7), 0);
+        assert_ne!(planes[V1_PLANES + 1] & bit(7, 1), 0);
+        // Squares in front of the pawns of Black and White.
+        assert_ne!(planes[V1_PLANES] & bit(5, 4), 0);
+        assert_ne!(planes[V1_PLANES + 1] & bit(3, 4), 0);
+        assert_eq!(planes[V1_PLANES + 2], 0);
+        assert_eq!(planes[V1_PLANES + 3], 0);
+        assert_eq!(planes[V1_PLANES + 4], 0);
+        assert!(planes[V1_PLANES + 5..].iter().all(|&plane| plane == 0));
+
+        let mut features = vec![0.0; FeatureSet::V2.input_channels() * 81];
+        InputFeatures::from(FeatureSet::V2).write_features(&planes, 128, &mut features);
+        assert!(features[V2_PLANES * 81..].iter().all(|&x| x == 0.5));
+    }
+
+    #[test]
+    fn test_pinned() {
+        let mut pieces = [None; 81];
+        pieces[8 * 9 + 4] = Some((0, KING));
+        pieces[6 * 9 + 4] = Some((0, GOLD_ID));
+        pieces[2 * 9 + 4] = Some((1, KY));
+        pieces[6 * 9 + 2] = Some((0, GI));
+        pieces[4 * 9] = Some((1, KA));
+        assert_eq!(pinned(&pieces, 0), bit(6, 4) | bit(6, 2));
+
+        // A lance does not pin backwards.
+        let mut reversed = [None; 81];
+        reversed[4] = Some((0, KING));
+        reversed[9 + 4] = Some((0, GOLD_ID));
+        reversed[5 * 9 + 4] = Some((1, KY));
+        assert_eq!(pinned(&reversed, 0), 0);
+    }
+
+    #[test]
+    fn test_context_from_position_command() {
+        let context = FeatureContext::from_position_command(
+            "position startpos moves 5i5h 5a5b 5h5i 5b5a 5i5h",
+        )
+        .unwrap();
+        assert_eq!(context.move_number, 6);
+        assert_eq!(context.repetitions, 1);
+
+        let context = FeatureContext::from_position_command(
+            "position sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 31 moves 7g7f",
+        )
+        .unwrap();
+        assert_eq!(context.move_number, 32);
+        assert_eq!(context.repetitions, 0);
+        assert_eq!(context.history.len(), 1);
+
+        let context = FeatureContext::from_position_command(
+            "position startpos moves 7g7f 3c3d 8h2b+ 3a2b B*4e",
+        )
+        .unwrap();
+        assert_eq!(context.move_number, 6);
+        assert_eq!(context.history.len(), 5);
+        assert_eq!(context.history[0].from, None);
+        assert_eq!(context.history[0].to, 4 * 9 + 5);
+        assert!(FeatureContext::from_position_command("position startpos moves 7g7e").is_err());
+    }
+
+    #[test]
+    fn test_history() {
+        let input_features = InputFeatures {
+            feature_set: FeatureSet::V1,
+            history: History {
+                positions: 1,
+                moves: 2,
+            },
+        };
+        assert_eq!(input_features.input_channels(), 2 * BOARD_PLANES + 4);
+
+        let context =
+            FeatureContext::from_position_command("position startpos moves 7g7f").unwrap();
+        let game = Game::from_opening("startpos moves 7g7f").unwrap();
+        let planes = input_features.encode(game.board(), game.next_turn(), &context);
+        assert_eq!(planes.len(), input_features.bit_planes());
+        // The initial position is the same from both sides.
+        assert_eq!(
+            &planes[BOARD_PLANES..2 * BOARD_PLANES],
+            &Board::default().encode()[..]
+        );
+        // 7g7f as seen from White, and no move before it.
+        assert_eq!(planes[2 * BOARD_PLANES], 1 << (80 - 56));
+        assert_eq!(planes[2 * BOARD_PLANES + 1], 1 << (80 - 47));
+        assert_eq!(&planes[2 * BOARD_PLANES + 2..], &[0, 0]);
+
+        let context = game.feature_context();
+        assert_eq!(context.history.len(), 1);
+        assert_eq!(context.history[0].from, Some(56));
+        assert_eq!(context.history[0].to, 47);
+    }
+}
@@ -0,0 +1,399 @@
+use crate::csa::format_csa_move;
+use crate::features::{is_king_attacked, FeatureContext, PastPosition, MAX_HISTORY};
+use crate::usi::format_usi_move;
+use crate::util::board_packer::BoardPacker;
+use anyhow::{anyhow, bail, Result};
+use shogiutil::{Board, Color, Move, UsiRequest, UsiResponse};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone)]
+pub struct LegalMove {
+    pub mv: Move,
+    pub promoted: bool,
+}
+
+impl LegalMove {
+    pub fn to_usi(&self) -> String {
+        format_usi_move(&self.mv, self.promoted)
+    }
+
+    pub fn to_csa(&self) -> String {
+        format_csa_move(&self.mv, self.promoted)
+    }
+
+    /// Plays the move on `board`. `Board::push_move` takes the piece after the move, which is the
+    /// promoted one for a promotion.
+    pub fn push_to(&self, board: &mut Board) -> Result<()> {
+        let mut mv = self.mv.clone();
+        if self.promoted {
+            mv.piece = mv.piece.promote();
+        }
+        board.push_move(mv)?;
+        Ok(())
+    }
+}
+
+/// Legal moves of `next_turn`, with squares as seen from Black.
+pub fn legal_moves(board: &Board, next_turn: Color) -> Vec<LegalMove> {
+    if next_turn == Color::Black {
+        board
+            .generate_legal_moves()
+            .into_iter()
+            .map(|mv| LegalMove {
+                mv: mv.mv,
+                promoted: mv.promoted,
+            })
+            .collect()
+    } else {
+        board
+            .rotate180()
+            .generate_legal_moves()
+            .into_iter()
+            .map(|mv| LegalMove {
+                mv: Move {
+                    from: mv.mv.from.map(|f| f.rotate()),
+                    to: mv.mv.to.rotate(),
+                    piece: mv.mv.piece,
+                    color: next_turn,
+                },
+                promoted: mv.promoted,
+            })
+            .collect()
+    }
+}
+
+/// Whether `color` to move may declare a win by entering king under the 27-point rule of the CSA
+/// protocol: the king is in the enemy camp and not in check, and at least 10 other pieces are in
+/// the enemy camp, worth 28 points for Black or 27 for White counting pieces in hand. Major pieces
+/// count 5 points and the others 1.
+pub fn can_declare_win(board: &Board, color: Color) -> bool {
+    let color_id = (color == Color::White) as usize;
+    let camp: u128 = if color == Color::Black {
+        (1 << 27) - 1
+    } else {
+        ((1 << 81) - 1) ^ ((1 << 54) - 1)
+    };
+    let own = board.occupied[color_id].0;
+    if board.piece_bb[KING].0 & own & camp == 0 {
+        return false;
+    }
+
+    let mut pieces = 0;
+    let mut points = 0;
+    for piece in (1..15).filter(|&piece| piece != KING) {
+        let count = (board.piece_bb[piece].0 & own & camp).count_ones();
+        pieces += count;
+        points += count * piece_point(piece);
+    }
+    for piece in 1..KING {
+        points += board.pieces_in_hand[color_id][piece] as u32 * piece_point(piece);
+    }
+    let required = if color == Color::Black { 28 } else { 27 };
+    pieces >= 10 && points >= required && !is_in_check(board, color)
+}
+
+/// Whether the king of `color` is attacked by a piece of the opponent, see `is_king_attacked`.
+pub fn is_in_check(board: &Board, color: Color) -> bool {
+    is_king_attacked(board, color)
+}
+
+pub fn opponent(color: Color) -> Color {
+    if color == Color::Black {
+        Color::White
+    } else {
+        Color::Black
+    }
+}
+
+/// A game from the initial position. The moves are kept to send them to engines as
+/// `position_command()`.
+pub struct Game {
+    moves: Vec<LegalMove>,
+    board: Board,
+    next_turn: Color,
+    /// Boards before each of `moves`.
+    boards: Vec<Board>,
+    /// Numbers of moves played when each position appeared, keyed by the encoded board and the
+    /// side to move.
+    positions: BTreeMap<(Vec<u128>, bool), Vec<usize>>,
+    /// Whether each of `moves` gave check.
+    checks: Vec<bool>,
+}
+
+/// Result of a position appearing for the fourth time.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Sennichite {
+    Draw,
+    /// The given player checked with every move since the first appearance, and loses.
+    PerpetualCheck(Color),
+}
+
+impl Game {
+    pub fn new() -> Self {
+        let mut game = Self {
+            moves: vec![],
+            board: Board::default(),
+            next_turn: Color::Black,
+            boards: vec![],
+            positions: BTreeMap::new(),
+            checks: vec![],
+        };
+        game.count_position();
+        game
+    }
+
+    /// `opening` is a USI position such as `startpos moves 7g7f 3c3d`.
+    pub fn from_opening(opening: &str) -> Result<Self> {
+        let mut tokens = opening.split_whitespace();
+        if tokens.next() != Some("startpos") {
+            bail!("Openings must start from startpos: {}", opening);
+        }
+        let mut game = Game::new();
+        for usi in tokens.skip_while(|&token| token == "moves") {
+            game.play_usi(usi)?;
+        }
+        Ok(game)
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn next_turn(&self) -> Color {
+        self.next_turn
+    }
+
+    pub fn moves(&self) -> &[LegalMove] {
+        &self.moves
+    }
+
+    pub fn position_command(&self) -> String {
+        let mut command = "position startpos".to_string();
+        if !self.moves.is_empty() {
+            command += " moves";
+            for mv in self.moves.iter() {
+                command += " ";
+                command += &mv.to_usi();
+            }
+        }
+        command
+    }
+
+    pub fn legal_moves(&self) -> Vec<LegalMove> {
+        legal_moves(&self.board, self.next_turn)
+    }
+
+    /// Plays a move given in USI notation. Fails if the move is not legal.
+    pub fn play_usi(&mut self, usi: &str) -> Result<LegalMove> {
+        let mv = self
+            .legal_moves()
+            .into_iter()
+            .find(|mv| mv.to_usi() == usi)
+            .ok_or_else(|| anyhow!("Illegal move: {}", usi))?;
+        let previous = self.board.clone();
+        mv.push_to(&mut self.board)?;
+        self.boards.push(previous);
+        self.moves.push(mv.clone());
+        self.next_turn = opponent(self.next_turn);
+        self.checks.push(self.is_in_check());
+        self.count_position();
+        Ok(mv)
+    }
+
+    /// Plays a move given in CSA notation such as `+7776FU`. Fails if the move is not legal.
+    pub fn play_csa(&mut self, csa: &str) -> Result<LegalMove> {
+        let usi = self
+            .legal_moves()
+            .into_iter()
+            .find(|mv| mv.to_csa() == csa)
+            .map(|mv| mv.to_usi())
+            .ok_or_else(|| anyhow!("Illegal move: {}", csa))?;
+        self.play_usi(&usi)
+    }
+
+    /// The legal move a `UsiPlayer` answered with, if any.
+    pub fn find_response_move(&self, responses: &[UsiResponse]) -> Option<LegalMove> {
+        let legal_moves = self.legal_moves();
+        responses.iter().find_map(|response| match response {
+            UsiResponse::TravelMove { from, to, promoted } => legal_moves
+                .iter()
+                .find(|mv| {
+                    mv.mv.from.as_ref() == Some(from) && &mv.mv.to == to && mv.promoted == *promoted
+                })
+                .cloned(),
+            UsiResponse::DropMove { to, piece } => legal_moves
+                .iter()
+                .find(|mv| mv.mv.from.is_none() && &mv.mv.to == to && mv.mv.piece == *piece)
+                .cloned(),
+            _ => None,
+        })
+    }
+
+    /// Whether `board` with `next_turn` to move is the current position.
+    pub fn is_position(&self, board: &Board, next_turn: Color) -> bool {
+        self.position_key() == (board.encode().to_vec(), next_turn == Color::Black)
+    }
+
+    /// The legal move leading to `board` with `next_turn` to move, if any.
+    pub fn find_move_to(&self, board: &Board, next_turn: Color) -> Option<LegalMove> {
+        if next_turn != opponent(self.next_turn) {
+            return None;
+        }
+        let encoded = board.encode();
+        self.legal_moves().into_iter().find(|mv| {
+            let mut candidate = self.board.clone();
+            mv.push_to(&mut candidate).is_ok() && candidate.encode()[..] == encoded[..]
+        })
+    }
+
+    /// The `position` request for the current position.
+    pub fn position_request(&self) -> Result<UsiRequest> {
+        Ok(UsiRequest::parse(&self.position_command())?)
+    }
+
+    /// Whether the player to move may declare a win by entering king, see `can_declare_win`.
+    pub fn can_declare_win(&self) -> bool {
+        can_declare_win(&self.board, self.next_turn)
+    }
+
+    /// Whether the king of the player to move is attacked.
+    pub fn is_in_check(&self) -> bool {
+        is_in_check(&self.board, self.next_turn)
+    }
+
+    /// How many times the current position has appeared, including now.
+    pub fn repetition_count(&self) -> usize {
+        self.positions
+            .get(&self.position_key())
+            .map(|plies| plies.len())
+            .unwrap_or(0)
+    }
+
+    /// `Some` once the current position has appeared four times.
+    pub fn sennichite(&self) -> Option<Sennichite> {
+        let plies = self.positions.get(&self.position_key())?;
+        if plies.len() < 4 {
+            return None;
+        }
+        let (first, last) = (plies[0], self.moves.len());
+        // The last move was played by the opponent of the player to move.
+        for &(checker, parity) in [(opponent(self.next_turn), 1), (self.next_turn, 0)].iter() {
+            let always_checked = (first..last)
+                .filter(|&ply| (last - ply) % 2 == parity)
+                .all(|ply| self.checks[ply]);
+            if always_checked {
+                return Some(Sennichite::PerpetualCheck(checker));
+            }
+        }
+        Some(Sennichite::Draw)
+    }
+
+    /// Context of the current position for `InputFeatures::encode`.
+    pub fn feature_context(&self) -> FeatureContext {
+        FeatureContext {
+            move_number: (self.moves.len() + 1) as u16,
+            repetitions: (self.repetition_count() - 1).min(u8::MAX as usize) as u8,
+            history: self
+                .boards
+                .iter()
+                .zip(self.moves.iter())
+                .rev()
+                .take(MAX_HISTORY)
+                .map(|(board, mv)| PastPosition::new(board.clone(), &mv.mv))
+                .collect(),
+        }
+    }
+
+    fn position_key(&self) -> (Vec<u128>, bool) {
+        (self.board.encode().to_vec(), self.next_turn == Color::Black)
+    }
+
+    fn count_position(&mut self) {
+        let ply = self.moves.len();
+        self.positions
+            .entry(self.position_key())
+            .or_insert_with(Vec::new)
+            .push(ply);
+    }
+}
+
+/// Index of the king in `Board::piece_bb`.
+const KING: usize = 8;
+
+fn piece_point(piece: usize) -> u32 {
+    match piece {
+        6 | 7 | 13 | 14 => 5,
+        _ => 1,
+    }
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_play_usi() {
+        let opening = "startpos moves 7g7f 3c3d 8h2b+ 3a2b B*4e 8c8d";
+        let game = Game::from_opening(opening).unwrap();
+        match UsiRequest::parse(&format!("position {}", opening)).unwrap() {
+            UsiRequest::Position { board, next_turn } => {
+                assert_eq!(game.board().encode()[..], board.encode()[..]);
+                assert_eq!(game.next_turn(), next_turn);
+            }
+            _ => unreachable!(),
+        }
+        assert_eq!(game.moves()[2].to_csa(), "+8822UM");
+        assert!(Game::from_opening("startpos moves 7g7e").is_err());
+    }
+
+    #[test]
+    fn test_is_in_check() {
+        let game = Game::from_opening("startpos moves 7g7f 3c3d").unwrap();
+        assert!(!game.is_in_check());
+
+        // The gold on 5e checks the king on 4d although it is pinned by the lance on 5a.
+        let board = match UsiRequest::parse("position sfen 4l4/9/9/5k3/4G4/9/9/9/4K4 w - 1") {
+            Ok(UsiRequest::Position { board, .. }) => board,
+            _ => unreachable!(),
+        };
+        assert!(is_in_check(&board, Color::White));
+        assert!(!is_in_check(&board, Color::Black));
+        assert!(!legal_moves(&board, Color::Black)
+            .iter()
+            .any(|mv| mv.to_usi() == "5e4d"));
+    }
+
+    #[test]
+    fn test_sennichite() {
+        let mut game = Game::new();
+        for _ in 0..3 {
+            assert_eq!(game.sennichite(), None);
+            for usi in ["5i5h", "5a5b", "5h5i", "5b5a"].iter() {
+                game.play_usi(usi).unwrap();
+            }
+        }
+        assert_eq!(game.repetition_count(), 4);
+        assert_eq!(game.sennichite(), Some(Sennichite::Draw));
+
+        // As if every move of one side had checked, which `test_is_in_check` detects even for a
+        // pinned checker.
+        for (checker, parity) in [(Color::Black, 0), (Color::White, 1)].iter() {
+            for (ply, check) in game.checks.iter_mut().enumerate() {
+                *check = ply % 2 == *parity;
+            }
+            assert_eq!(
+                game.sennichite(),
+                Some(Sennichite::PerpetualCheck(*checker))
+            );
+        }
+        // A check missing from the cycle makes it a draw again.
+        game.checks[4] = false;
+        assert_eq!(game.sennichite(), Some(Sennichite::Draw));
+    }
+}
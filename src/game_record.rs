@@ -0,0 +1,249 @@
+//! Records the games an engine plays under a USI GUI, which only sends the positions of its own
+//! turns. The opponent's moves are recovered by finding the move between consecutive positions.
+use crate::csa::CsaRecord;
+use crate::game::{opponent, Game, LegalMove};
+use crate::kif::{format_kif_move, KifRecord};
+use crate::usi::GameOver;
+use anyhow::Result;
+use shogiutil::{Board, Color};
+use std::fs::{create_dir_all, File};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What the engine thought of its own move.
+#[derive(Debug, Clone)]
+pub struct MoveComment {
+    pub probability: f64,
+    /// The most probable moves other than the played one, with their probabilities.
+    pub alternatives: Vec<(LegalMove, f64)>,
+    pub seconds: f64,
+}
+
+impl MoveComment {
+    fn to_csa(&self) -> String {
+        let alternatives = self
+            .alternatives
+            .iter()
+            .map(|(mv, probability)| format!("{}:{:.4}", mv.to_csa(), probability))
+            .collect::<Vec<_>>();
+        format!(
+            "probability={:.4} time={:.2}s alternatives={}",
+            self.probability,
+            self.seconds,
+            alternatives.join(",")
+        )
+    }
+
+    fn to_kif(&self) -> String {
+        let alternatives = self
+            .alternatives
+            .iter()
+            .map(|(mv, probability)| {
+                format!(
+                    "{} {:.4}",
+                    format_kif_move(&mv.mv, mv.promoted, None),
+                    probability
+                )
+            })
+            .collect::<Vec<_>>();
+        format!(
+            "確率 {:.4} 思考 {:.2}秒 候補 {}",
+            self.probability,
+            self.seconds,
+            alternatives.join(" ")
+        )
+    }
+}
+
+struct RecordedMove {
+    mv: LegalMove,
+    /// Only known for own moves.
+    comment: Option<MoveComment>,
+}
+
+/// Writes every game as `<unix time>-<n>.csa` and `<unix time>-<n>.kifu` in a directory.
+pub struct GameRecorder {
+    dir: PathBuf,
+    name: String,
+    /// `None` after a position which does not follow from the recorded moves.
+    game: Option<Game>,
+    moves: Vec<RecordedMove>,
+    my_color: Option<Color>,
+    games: usize,
+}
+
+impl GameRecorder {
+    /// `name` is written as the engine's player name.
+    pub fn new<P: Into<PathBuf>>(dir: P, name: &str) -> Self {
+        Self {
+            dir: dir.into(),
+            name: name.to_string(),
+            game: Some(Game::new()),
+            moves: vec![],
+            my_color: None,
+            games: 0,
+        }
+    }
+
+    /// Follows a `position` command, recovering the opponent's move since the last one.
+    pub fn position(&mut self, board: &Board, next_turn: Color) {
+        let game = match self.game.as_mut() {
+            Some(game) => game,
+            None => return,
+        };
+        if game.is_position(board, next_turn) {
+            return;
+        }
+        match game.find_move_to(board, next_turn) {
+            Some(mv) => {
+                game.play_usi(&mv.to_usi()).unwrap();
+                self.moves.push(RecordedMove { mv, comment: None });
+            }
+            None => {
+                log::warn!("The position does not follow the recorded game; stop recording it");
+                self.game = None;
+            }
+        }
+    }
+
+    pub fn own_move(&mut self, mv: &LegalMove, comment: MoveComment) {
+        let game = match self.game.as_mut() {
+            Some(game) => game,
+            None => return,
+        };
+        game.play_usi(&mv.to_usi()).unwrap();
+        self.my_color = Some(mv.mv.color);
+        self.moves.push(RecordedMove {
+            mv: mv.clone(),
+            comment: Some(comment),
+        });
+    }
+
+    /// Writes the game if it has any move and starts a new one. `result` is `None` when the game
+    /// ended without `gameover`.
+    pub fn finish(&mut self, result: Option<GameOver>) -> Result<()> {
+        let moves = std::mem::replace(&mut self.moves, vec![]);
+        let recorded = self.game.replace(Game::new()).is_some();
+        let my_color = self.my_color.take();
+        if moves.is_empty() || !recorded {
+            return Ok(());
+        }
+
+        let (black_name, white_name) = match my_color {
+            Some(Color::White) => ("opponent", self.name.as_str()),
+            _ => (self.name.as_str(), "opponent"),
+        };
+        let mut csa = CsaRecord::new(black_name, white_name);
+        let mut kif = KifRecord::new(black_name, white_name);
+        let mut previous_to = None;
+        for mv in moves.iter() {
+            let seconds = mv.comment.as_ref().map(|comment| comment.seconds as u64);
+            match mv.comment.as_ref() {
+                Some(comment) => csa.push_with_comment(mv.mv.to_csa(), seconds, comment.to_csa()),
+                None => csa.push(mv.mv.to_csa(), seconds),
+            }
+            kif.push(
+                format_kif_move(&mv.mv.mv, mv.mv.promoted, previous_to),
+                seconds.unwrap_or(0),
+                mv.comment.as_ref().map(MoveComment::to_kif),
+            );
+            previous_to = Some(&mv.mv.mv.to);
+        }
+
+        // USI does not tell why the game ended.
+        kif.winner = match (result, my_color) {
+            (Some(GameOver::Win), Some(color)) => Some(color),
+            (Some(GameOver::Lose), Some(color)) => Some(opponent(color)),
+            _ => None,
+        };
+        kif.end = match result {
+            Some(GameOver::Draw) => Some("引き分け".to_string()),
+            Some(_) => None,
+            None => Some("中断".to_string()),
+        };
+        if let Some(result) = result {
+            csa.header_comments
+                .push(format!("result:{:?}", result).to_lowercase());
+        }
+
+        create_dir_all(&self.dir)?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let stem = format!("{}-{}", now, self.games);
+        self.games += 1;
+        write!(
+            File::create(self.dir.join(format!("{}.csa", stem)))?,
+            "{}",
+            csa
+        )?;
+        write!(
+            File::create(self.dir.join(format!("{}.kifu", stem)))?,
+            "{}",
+            kif
+        )?;
+        log::info!("Saved the game as {}", self.dir.join(stem).display());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shogiutil::UsiRequest;
+    use std::fs::{read_dir, read_to_string, remove_dir_all};
+
+    fn position(command: &str) -> (Board, Color) {
+        match UsiRequest::parse(command).unwrap() {
+            UsiRequest::Position { board, next_turn } => (board, next_turn),
+            _ => unreachable!(),
+        }
+    }
+
+    fn comment() -> MoveComment {
+        MoveComment {
+            probability: 0.5,
+            alternatives: vec![],
+            seconds: 1.5,
+        }
+    }
+
+    #[test]
+    fn test_game_recorder() {
+        let dir = std::env::temp_dir().join(format!(
+            "game_recorder_test_{}_{:016x}",
+            std::process::id(),
+            rand::random::<u64>()
+        ));
+        let mut recorder = GameRecorder::new(&dir, "engine");
+
+        let (board, next_turn) = position("position startpos");
+        recorder.position(&board, next_turn);
+        let game = Game::from_opening("startpos moves 7g7f").unwrap();
+        recorder.own_move(&game.moves()[0], comment());
+
+        let (board, next_turn) = position("position startpos moves 7g7f 3c3d");
+        recorder.position(&board, next_turn);
+        let game = Game::from_opening("startpos moves 7g7f 3c3d 2g2f").unwrap();
+        recorder.own_move(&game.moves()[2], comment());
+        recorder.finish(Some(GameOver::Win)).unwrap();
+
+        let files = read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        assert_eq!(files.len(), 2);
+        for file in files {
+            let record = read_to_string(&file).unwrap();
+            if file.extension().unwrap() == "csa" {
+                assert!(record.contains("N+engine\nN-opponent\n"));
+                assert!(record.contains("+7776FU\nT1\n'probability=0.5000"));
+                assert!(record.contains("-3334FU\n+2726FU"));
+            } else {
+                assert!(record.contains("   2 ３四歩(33)   ( 0:00/00:00:00)"));
+                assert!(record.contains("*確率 0.5000 思考 1.50秒"));
+                assert!(record.ends_with("まで3手で先手の勝ち\n"));
+            }
+        }
+        remove_dir_all(dir).unwrap();
+    }
+}
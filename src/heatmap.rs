@@ -0,0 +1,310 @@
+//! Heatmaps of the policy of a position, as ANSI text for terminals or as SVG.
+//!
+//! Labels are as seen from the player to move, so when White is to move the destination squares
+//! are rotated back to draw everything from Black's side like the board. Planes keep the view of
+//! the player to move: `Up` of White is toward rank 9.
+use crate::constants::{MOVE_DIRECTIONS, MOVE_DIRECTION_LABEL_NUM, PIECE_NAMES};
+use crate::inference::MoveProbability;
+use shogiutil::{Board, Color, Square};
+
+pub const PLANES: usize = MOVE_DIRECTION_LABEL_NUM as usize;
+
+/// Background colors of the ANSI heatmap from no mass to the most, in the 256-color palette.
+const ANSI_COLORS: [u8; 9] = [236, 52, 88, 124, 160, 196, 202, 208, 214];
+const CELL: usize = 48;
+const MARGIN: usize = 40;
+const BAR_HEIGHT: usize = 14;
+const BAR_WIDTH: f64 = 300.0;
+
+#[derive(Debug, Clone)]
+pub struct Heatmap {
+    /// Policy mass per destination square, indexed by `Square::to_pos()` as seen from Black.
+    pub squares: [[f64; 9]; 9],
+    /// Policy mass per `MoveDirection` plane, followed by the drop planes.
+    pub planes: [f64; PLANES],
+    /// Moves drawn as arrows, with squares as seen from Black.
+    pub top_moves: Vec<MoveProbability>,
+}
+
+impl Heatmap {
+    /// `probabilities` is the policy over all labels given by `policy_distribution`.
+    pub fn new(probabilities: &[f64], next_turn: Color, top_moves: Vec<MoveProbability>) -> Self {
+        let mut squares = [[0.0; 9]; 9];
+        let mut planes = [0.0; PLANES];
+        for (label, &probability) in probabilities.iter().enumerate() {
+            let (i, j) = (label % 81 / 9, label % 9);
+            let (i, j) = if next_turn == Color::Black {
+                (i, j)
+            } else {
+                (8 - i, 8 - j)
+            };
+            squares[i][j] += probability;
+            planes[label / 81] += probability;
+        }
+        Self {
+            squares,
+            planes,
+            top_moves,
+        }
+    }
+
+    fn max_square(&self) -> f64 {
+        self.squares
+            .iter()
+            .flat_map(|row| row.iter())
+            .cloned()
+            .fold(f64::MIN_POSITIVE, f64::max)
+    }
+
+    /// Board with colored squares, followed by the mass of each plane and the top moves.
+    pub fn to_ansi(&self, board: &Board) -> String {
+        let max = self.max_square();
+        let mut s = String::new();
+        for file in (1..=9).rev() {
+            s += &format!("{:>6}", file);
+        }
+        s += "\n";
+        for i in 0..9 {
+            for line in 0..2 {
+                for j in 0..9 {
+                    let mass = self.squares[i][j];
+                    let level = ((mass / max).sqrt() * (ANSI_COLORS.len() - 1) as f64).round();
+                    let text = if line == 0 {
+                        piece_at(board, i, j).unwrap_or_else(|| " ・".to_string())
+                    } else {
+                        format!("{:>4.1}", mass * 100.0)
+                    };
+                    s += &format!(
+                        "\x1b[48;5;{}m {:<4} \x1b[0m",
+                        ANSI_COLORS[level as usize], text
+                    );
+                }
+                if line == 0 {
+                    s += &format!(" {}", i + 1);
+                }
+                s += "\n";
+            }
+        }
+
+        s += "\n";
+        for (plane, &mass) in self.planes.iter().enumerate() {
+            let bar = "#".repeat((mass * 50.0).round() as usize);
+            s += &format!("{:<18} {:>5.1}% {}\n", plane_name(plane), mass * 100.0, bar);
+        }
+
+        s += "\n";
+        for (rank, mv) in self.top_moves.iter().enumerate() {
+            let arrow = match mv.mv.mv.from.as_ref() {
+                Some(from) => arrow(from, &mv.mv.mv.to),
+                None => '*',
+            };
+            s += &format!(
+                "{:>2}. {} {} {:>5.1}%\n",
+                rank + 1,
+                mv.mv.to_csa(),
+                arrow,
+                mv.probability * 100.0
+            );
+        }
+        s
+    }
+
+    /// Board with colored squares and arrows of the top moves, and a bar chart of the planes.
+    pub fn to_svg(&self, board: &Board) -> String {
+        let max = self.max_square();
+        let width = MARGIN * 2 + CELL * 9 + 160;
+        let height = MARGIN * 2 + CELL * 9 + BAR_HEIGHT * PLANES;
+        let mut svg = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-family="sans-serif">
+<defs><marker id="arrow" viewBox="0 0 10 10" refX="8" refY="5" markerWidth="4" markerHeight="4" orient="auto"><path d="M0,0 L10,5 L0,10 z" fill="blue"/></marker></defs>
+<rect width="100%" height="100%" fill="white"/>
+"#,
+            width, height
+        );
+
+        for (j, file) in (1..=9).rev().enumerate() {
+            svg += &format!(
+                r#"<text x="{}" y="{}" text-anchor="middle">{}</text>
+"#,
+                MARGIN + CELL * j + CELL / 2,
+                MARGIN - 10,
+                file
+            );
+        }
+        for i in 0..9 {
+            svg += &format!(
+                r#"<text x="{}" y="{}">{}</text>
+"#,
+                MARGIN + CELL * 9 + 8,
+                MARGIN + CELL * i + CELL / 2 + 5,
+                i + 1
+            );
+            for j in 0..9 {
+                let (x, y) = (MARGIN + CELL * j, MARGIN + CELL * i);
+                let mass = self.squares[i][j];
+                let green = (255.0 * (1.0 - (mass / max).sqrt())).round() as u8;
+                svg += &format!(
+                    r#"<rect x="{}" y="{}" width="{}" height="{}" fill="rgb(255,{},{})" stroke="black"/>
+<text x="{}" y="{}" font-size="10" text-anchor="end">{:.1}</text>
+"#,
+                    x,
+                    y,
+                    CELL,
+                    CELL,
+                    green,
+                    green,
+                    x + CELL - 3,
+                    y + CELL - 4,
+                    mass * 100.0
+                );
+                if let Some(piece) = piece_at(board, i, j) {
+                    let (cx, cy) = (x + CELL / 2, y + CELL / 2);
+                    let rotation = if piece.starts_with('-') { 180 } else { 0 };
+                    svg += &format!(
+                        r#"<text x="{}" y="{}" font-size="16" text-anchor="middle" dominant-baseline="middle" transform="rotate({} {} {})">{}</text>
+"#,
+                        cx,
+                        cy,
+                        rotation,
+                        cx,
+                        cy,
+                        &piece[1..]
+                    );
+                }
+            }
+        }
+
+        for mv in self.top_moves.iter() {
+            let (x2, y2) = square_center(&mv.mv.mv.to);
+            let stroke_width = 1.0 + mv.probability * 10.0;
+            match mv.mv.mv.from.as_ref() {
+                Some(from) => {
+                    let (x1, y1) = square_center(from);
+                    svg += &format!(
+                        r#"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="blue" stroke-opacity="0.7" stroke-width="{:.1}" marker-end="url(#arrow)"/>
+"#,
+                        x1, y1, x2, y2, stroke_width
+                    );
+                }
+                None => {
+                    svg += &format!(
+                        r#"<circle cx="{}" cy="{}" r="{}" fill="none" stroke="blue" stroke-opacity="0.7" stroke-width="{:.1}"/>
+"#,
+                        x2,
+                        y2,
+                        CELL / 3,
+                        stroke_width
+                    );
+                }
+            }
+        }
+
+        let top = MARGIN * 2 + CELL * 9;
+        for (plane, &mass) in self.planes.iter().enumerate() {
+            let y = top + BAR_HEIGHT * plane;
+            svg += &format!(
+                r#"<text x="{}" y="{}" font-size="11">{}</text>
+<rect x="{}" y="{}" width="{:.1}" height="{}" fill="red"/>
+<text x="{:.1}" y="{}" font-size="11">{:.1}%</text>
+"#,
+                MARGIN,
+                y + BAR_HEIGHT - 3,
+                plane_name(plane),
+                MARGIN + 130,
+                y + 2,
+                mass * BAR_WIDTH,
+                BAR_HEIGHT - 4,
+                (MARGIN + 134) as f64 + mass * BAR_WIDTH,
+                y + BAR_HEIGHT - 3,
+                mass * 100.0
+            );
+        }
+        svg += "</svg>\n";
+        svg
+    }
+}
+
+/// Name of a plane such as `UpPromote` or `KE*`.
+pub fn plane_name(plane: usize) -> String {
+    if plane < MOVE_DIRECTIONS.len() {
+        format!("{:?}", MOVE_DIRECTIONS[plane])
+    } else {
+        format!("{}*", PIECE_NAMES[plane - MOVE_DIRECTIONS.len() + 1])
+    }
+}
+
+/// CSA name such as `+FU` of the piece on the square at `Square::to_pos()` `(i, j)`.
+fn piece_at(board: &Board, i: usize, j: usize) -> Option<String> {
+    let bit = 1 << (i * 9 + j);
+    for (color_id, sign) in [(0, '+'), (1, '-')].iter() {
+        for piece in 1..15 {
+            if (board.piece_bb[piece] & board.occupied[*color_id]).0 & bit != 0 {
+                return Some(format!("{}{}", sign, PIECE_NAMES[piece]));
+            }
+        }
+    }
+    None
+}
+
+fn square_center(square: &Square) -> (usize, usize) {
+    let (i, j) = (square.rank as usize - 1, 9 - square.file as usize);
+    (MARGIN + CELL * j + CELL / 2, MARGIN + CELL * i + CELL / 2)
+}
+
+/// Direction of a move on the board as seen from Black.
+fn arrow(from: &Square, to: &Square) -> char {
+    let down = (to.rank as i32 - from.rank as i32).signum();
+    let right = (from.file as i32 - to.file as i32).signum();
+    match (down, right) {
+        (-1, -1) => '↖',
+        (-1, 0) => '↑',
+        (-1, 1) => '↗',
+        (0, -1) => '←',
+        (0, 1) => '→',
+        (1, -1) => '↙',
+        (1, 0) => '↓',
+        _ => '↘',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heatmap_rotation() {
+        let mut probabilities = vec![0.0; PLANES * 81];
+        // Up to (2, 6) as seen from the player to move.
+        probabilities[2 * 9 + 6] = 0.75;
+        // Pawn drop to (4, 4).
+        probabilities[MOVE_DIRECTIONS.len() * 81 + 4 * 9 + 4] = 0.25;
+
+        let heatmap = Heatmap::new(&probabilities, Color::Black, vec![]);
+        assert_eq!(heatmap.squares[2][6], 0.75);
+        assert_eq!(heatmap.squares[4][4], 0.25);
+        assert_eq!(heatmap.planes[0], 0.75);
+        assert_eq!(heatmap.planes[MOVE_DIRECTIONS.len()], 0.25);
+
+        let heatmap = Heatmap::new(&probabilities, Color::White, vec![]);
+        assert_eq!(heatmap.squares[6][2], 0.75);
+        assert_eq!(heatmap.squares[4][4], 0.25);
+        assert_eq!(heatmap.planes[0], 0.75);
+    }
+
+    #[test]
+    fn test_plane_name() {
+        assert_eq!(plane_name(0), "Up");
+        assert_eq!(plane_name(19), "Up2RightPromote");
+        assert_eq!(plane_name(20), "FU*");
+        assert_eq!(plane_name(26), "HI*");
+    }
+
+    #[test]
+    fn test_arrow() {
+        let square = |file, rank| Square { file, rank };
+        assert_eq!(arrow(&square(7, 7), &square(7, 6)), '↑');
+        assert_eq!(arrow(&square(8, 8), &square(2, 2)), '↗');
+        assert_eq!(arrow(&square(2, 2), &square(8, 8)), '↙');
+        assert_eq!(arrow(&square(5, 1), &square(6, 1)), '←');
+    }
+}
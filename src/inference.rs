@@ -0,0 +1,140 @@
+use crate::features::{FeatureContext, InputFeatures};
+use crate::game::LegalMove;
+use crate::util::make_output_label::make_output_label;
+use shogiutil::{Board, Color, Move};
+use tch::kind::Kind::Double;
+use tch::nn::Module;
+use tch::{no_grad, Device, Tensor};
+
+#[derive(Debug, Clone)]
+pub struct MoveProbability {
+    /// The move with squares as seen from Black.
+    pub mv: LegalMove,
+    /// Output label of the move, as seen from the player to move.
+    pub label: i16,
+    pub logit: f64,
+    /// Softmax over all labels, including those of illegal moves.
+    pub probability: f64,
+}
+
+/// Outputs of `model`, a `PolicyNetwork` or a `TorchScriptPolicy`, for `features` of `batchsize`
+/// boards built by `InputFeatures::board_features`, one row of logits per board.
+pub fn forward_batch<M: Module + ?Sized>(
+    model: &M,
+    device: Device,
+    features: &[f32],
+    batchsize: usize,
+) -> Vec<Vec<f64>> {
+    let x = Tensor::of_slice(features)
+        .view((batchsize as i64, -1, 9, 9))
+        .to_device(device);
+    let y = no_grad(|| model.forward(&x));
+    let labels = y.size()[1] as usize;
+    let logits = Vec::<f64>::from(&y.view(-1).to_device(Device::Cpu).totype(Double));
+    logits.chunks(labels).map(|row| row.to_vec()).collect()
+}
+
+fn forward<M: Module + ?Sized>(
+    model: &M,
+    device: Device,
+    input_features: InputFeatures,
+    board: &Board,
+    next_turn: Color,
+    context: &FeatureContext,
+) -> Vec<f64> {
+    let features = input_features.board_features(board, next_turn, context);
+    forward_batch(model, device, &features, 1).pop().unwrap()
+}
+
+fn softmax(logits: &[f64]) -> Vec<f64> {
+    let max = logits.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let exp = logits.iter().map(|&x| (x - max).exp()).collect::<Vec<_>>();
+    let sum: f64 = exp.iter().sum();
+    exp.into_iter().map(|x| x / sum).collect()
+}
+
+/// Softmax over all labels, indexed by label as seen from `next_turn`.
+pub fn policy_distribution<M: Module + ?Sized>(
+    model: &M,
+    device: Device,
+    input_features: InputFeatures,
+    board: &Board,
+    next_turn: Color,
+    context: &FeatureContext,
+) -> Vec<f64> {
+    softmax(&forward(
+        model,
+        device,
+        input_features,
+        board,
+        next_turn,
+        context,
+    ))
+}
+
+/// Legal moves of `next_turn` with their policy, the most probable first. `input_features` must be
+/// the one `model` was trained on.
+pub fn predict<M: Module + ?Sized>(
+    model: &M,
+    device: Device,
+    input_features: InputFeatures,
+    board: &Board,
+    next_turn: Color,
+    context: &FeatureContext,
+) -> Vec<MoveProbability> {
+    let logits = forward(model, device, input_features, board, next_turn, context);
+    rank_legal_moves(board, next_turn, &logits)
+}
+
+/// Legal moves of `next_turn` ranked by `logits`, the output of the network for the board as seen
+/// from `next_turn`.
+pub fn rank_legal_moves(board: &Board, next_turn: Color, logits: &[f64]) -> Vec<MoveProbability> {
+    let rotated;
+    let board = if next_turn == Color::Black {
+        board
+    } else {
+        rotated = board.rotate180();
+        &rotated
+    };
+    let probabilities = softmax(logits);
+
+    let mut moves = vec![];
+    for mv in board.generate_legal_moves() {
+        let (mv, promoted) = (mv.mv, mv.promoted);
+        let label = make_output_label(&mv.from, &mv.to, mv.piece, promoted);
+        let logit = logits[label as usize];
+        let probability = probabilities[label as usize];
+
+        let mv = if next_turn == Color::Black {
+            mv
+        } else {
+            Move {
+                from: mv.from.map(|f| f.rotate()),
+                to: mv.to.rotate(),
+                piece: mv.piece,
+                color: next_turn,
+            }
+        };
+        moves.push(MoveProbability {
+            mv: LegalMove { mv, promoted },
+            label,
+            logit,
+            probability,
+        });
+    }
+    moves.sort_by(|a, b| b.probability.partial_cmp(&a.probability).unwrap());
+    moves
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_softmax() {
+        let probabilities = softmax(&[0.0, 2f64.ln(), 1000.0]);
+        assert!((probabilities.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+        assert!((probabilities[1] / probabilities[0] - 2.0).abs() < 1e-9);
+        assert!((probabilities[2] - 1.0).abs() < 1e-12);
+    }
+}
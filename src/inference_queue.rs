@@ -0,0 +1,217 @@
+//! Evaluates boards sent from many threads in batches on one `PolicyNetwork`.
+//!
+//! The network runs on its own thread, which waits for the first request, collects more until the
+//! batch is full or `max_wait` has passed since the first one, and runs a single `forward`.
+use crate::features::{FeatureContext, InputFeatures};
+use crate::inference::{forward_batch, rank_legal_moves, MoveProbability};
+use crate::network::policy::PolicyNetwork;
+use anyhow::{anyhow, Result};
+use shogiutil::{Board, Color};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tch::nn::VarStore;
+
+#[derive(Debug, Copy, Clone)]
+pub struct BatchConfig {
+    pub max_batch_size: usize,
+    pub max_wait: Duration,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_batch_size: 64,
+            max_wait: Duration::from_millis(2),
+        }
+    }
+}
+
+struct Request {
+    features: Vec<f32>,
+    response: Sender<Vec<f64>>,
+}
+
+#[derive(Debug, Default)]
+struct Stats {
+    batches: AtomicUsize,
+    requests: AtomicUsize,
+}
+
+/// Handle to send boards to the inference thread. Clone it for every thread; the inference thread
+/// stops when all handles are dropped.
+#[derive(Clone)]
+pub struct InferenceQueue {
+    sender: Sender<Request>,
+    stats: Arc<Stats>,
+    input_features: InputFeatures,
+}
+
+impl InferenceQueue {
+    /// Moves the network to a new inference thread.
+    pub fn spawn(model: PolicyNetwork, vs: VarStore, config: BatchConfig) -> Self {
+        let (sender, receiver) = channel();
+        let stats = Arc::new(Stats::default());
+        let input_features = model.config().input_features();
+        {
+            let stats = stats.clone();
+            thread::spawn(move || run(model, vs, config, receiver, &stats));
+        }
+        Self {
+            sender,
+            stats,
+            input_features,
+        }
+    }
+
+    /// Inputs of the network.
+    pub fn input_features(&self) -> InputFeatures {
+        self.input_features
+    }
+
+    /// Logits of all labels for `features` built by `InputFeatures::board_features`. Blocks until
+    /// the batch containing them has been evaluated.
+    pub fn evaluate(&self, features: Vec<f32>) -> Result<Vec<f64>> {
+        let (response, receiver) = channel();
+        self.sender
+            .send(Request { features, response })
+            .map_err(|_| anyhow!("The inference thread has stopped"))?;
+        receiver
+            .recv()
+            .map_err(|_| anyhow!("The inference thread has stopped"))
+    }
+
+    /// Same as `inference::predict`, evaluated in a batch with the requests of other threads.
+    pub fn predict(
+        &self,
+        board: &Board,
+        next_turn: Color,
+        context: &FeatureContext,
+    ) -> Result<Vec<MoveProbability>> {
+        let features = self
+            .input_features
+            .board_features(board, next_turn, context);
+        let logits = self.evaluate(features)?;
+        Ok(rank_legal_moves(board, next_turn, &logits))
+    }
+
+    /// Average number of boards per `forward` so far.
+    pub fn average_batch_size(&self) -> f64 {
+        let batches = self.stats.batches.load(Ordering::Relaxed);
+        let requests = self.stats.requests.load(Ordering::Relaxed);
+        requests as f64 / batches.max(1) as f64
+    }
+}
+
+fn run(
+    model: PolicyNetwork,
+    vs: VarStore,
+    config: BatchConfig,
+    receiver: Receiver<Request>,
+    stats: &Stats,
+) {
+    while let Ok(first) = receiver.recv() {
+        let deadline = Instant::now() + config.max_wait;
+        let mut batch = vec![first];
+        while batch.len() < config.max_batch_size {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            match receiver.recv_timeout(deadline - now) {
+                Ok(request) => batch.push(request),
+                Err(_) => break,
+            }
+        }
+
+        let features = batch
+            .iter()
+            .flat_map(|request| request.features.iter().cloned())
+            .collect::<Vec<_>>();
+        let logits = forward_batch(&model, vs.device(), &features, batch.len());
+        stats.batches.fetch_add(1, Ordering::Relaxed);
+        stats.requests.fetch_add(batch.len(), Ordering::Relaxed);
+        for (request, logits) in batch.into_iter().zip(logits.into_iter()) {
+            // The requesting thread may have given up waiting.
+            let _ = request.response.send(logits);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::{FeatureSet, History};
+    use crate::game::Game;
+    use crate::inference::predict;
+    use crate::network::config::ModelConfig;
+    use tch::Device;
+
+    #[test]
+    fn test_inference_queue() {
+        let vs = VarStore::new(Device::Cpu);
+        let config = ModelConfig {
+            feature_set: FeatureSet::V2,
+            history: History {
+                positions: 1,
+                moves: 1,
+            },
+            ..Default::default()
+        };
+        let model = PolicyNetwork::new(&vs.root(), &config);
+        let mut queue_vs = VarStore::new(Device::Cpu);
+        let queue_model = PolicyNetwork::new(&queue_vs.root(), &config);
+        queue_vs.copy(&vs).unwrap();
+        let queue = InferenceQueue::spawn(
+            queue_model,
+            queue_vs,
+            BatchConfig {
+                max_batch_size: 8,
+                max_wait: Duration::from_millis(500),
+            },
+        );
+
+        let openings = [
+            "startpos",
+            "startpos moves 7g7f",
+            "startpos moves 2g2f 8c8d",
+        ];
+        let handles = (0..8)
+            .map(|i| {
+                let queue = queue.clone();
+                let opening = openings[i % openings.len()];
+                thread::spawn(move || {
+                    let game = Game::from_opening(opening).unwrap();
+                    queue
+                        .predict(game.board(), game.next_turn(), &game.feature_context())
+                        .unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for (i, handle) in handles.into_iter().enumerate() {
+            let batched = handle.join().unwrap();
+            let game = Game::from_opening(openings[i % openings.len()]).unwrap();
+            let expected = predict(
+                &model,
+                Device::Cpu,
+                config.input_features(),
+                game.board(),
+                game.next_turn(),
+                &game.feature_context(),
+            );
+            assert_eq!(batched.len(), expected.len());
+            for expected in expected.iter() {
+                let batched = batched
+                    .iter()
+                    .find(|mv| mv.label == expected.label)
+                    .unwrap();
+                assert!((batched.probability - expected.probability).abs() < 1e-5);
+            }
+        }
+        // How the requests were batched depends on the scheduling of the threads.
+        assert!(queue.average_batch_size() >= 1.0);
+    }
+}
@@ -0,0 +1,171 @@
+//! Game records in KIF, the format of most Japanese shogi software. Records are written in UTF-8,
+//! which readers expect from files named `.kifu`.
+use shogiutil::{Color, Move, Square};
+use std::fmt::{self, Display, Formatter};
+
+const FILE_NAMES: [&str; 10] = ["", "１", "２", "３", "４", "５", "６", "７", "８", "９"];
+const RANK_NAMES: [&str; 10] = ["", "一", "二", "三", "四", "五", "六", "七", "八", "九"];
+/// Indexed like `PIECE_NAMES`.
+const KIF_PIECE_NAMES: [&str; 15] = [
+    "", "歩", "香", "桂", "銀", "金", "角", "飛", "玉", "と", "成香", "成桂", "成銀", "馬", "龍",
+];
+
+/// KIF notation of a move, e.g. `７六歩(77)`, `同　角成(88)` or `５五歩打`. `previous_to` is the
+/// destination of the previous move.
+pub fn format_kif_move(mv: &Move, promoted: bool, previous_to: Option<&Square>) -> String {
+    let to = if previous_to == Some(&mv.to) {
+        "同　".to_string()
+    } else {
+        format!(
+            "{}{}",
+            FILE_NAMES[mv.to.file as usize], RANK_NAMES[mv.to.rank as usize]
+        )
+    };
+    let piece = KIF_PIECE_NAMES[mv.piece.to_usize()];
+    match mv.from.as_ref() {
+        Some(from) => {
+            let promotion = if promoted { "成" } else { "" };
+            format!("{}{}{}({}{})", to, piece, promotion, from.file, from.rank)
+        }
+        None => format!("{}{}打", to, piece),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct KifMove {
+    pub mv: String,
+    /// Thinking time in seconds.
+    pub seconds: u64,
+    /// Comment written after the move, without `*`.
+    pub comment: Option<String>,
+}
+
+/// Game record in KIF format starting from the initial position.
+#[derive(Debug, Clone, Default)]
+pub struct KifRecord {
+    pub black_name: String,
+    pub white_name: String,
+    pub moves: Vec<KifMove>,
+    /// Move ending the game such as `投了` or `中断`.
+    pub end: Option<String>,
+    /// `None` for draws and unfinished games.
+    pub winner: Option<Color>,
+}
+
+impl KifRecord {
+    pub fn new(black_name: &str, white_name: &str) -> Self {
+        Self {
+            black_name: black_name.to_string(),
+            white_name: white_name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn push(&mut self, mv: String, seconds: u64, comment: Option<String>) {
+        self.moves.push(KifMove {
+            mv,
+            seconds,
+            comment,
+        });
+    }
+}
+
+impl Display for KifRecord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "手合割：平手")?;
+        writeln!(f, "先手：{}", self.black_name)?;
+        writeln!(f, "後手：{}", self.white_name)?;
+        writeln!(f, "手数----指手---------消費時間--")?;
+
+        let mut total_seconds = [0; 2];
+        for (i, mv) in self.moves.iter().enumerate() {
+            total_seconds[i % 2] += mv.seconds;
+            let total = total_seconds[i % 2];
+            writeln!(
+                f,
+                "{:>4} {}   ({:>2}:{:02}/{:02}:{:02}:{:02})",
+                i + 1,
+                mv.mv,
+                mv.seconds / 60,
+                mv.seconds % 60,
+                total / 3600,
+                total / 60 % 60,
+                total % 60
+            )?;
+            if let Some(comment) = mv.comment.as_ref() {
+                writeln!(f, "*{}", comment)?;
+            }
+        }
+
+        let n = self.moves.len();
+        if let Some(end) = self.end.as_ref() {
+            writeln!(f, "{:>4} {}", n + 1, end)?;
+        }
+        match self.winner {
+            Some(Color::Black) => writeln!(f, "まで{}手で先手の勝ち", n),
+            Some(Color::White) => writeln!(f, "まで{}手で後手の勝ち", n),
+            None => writeln!(f, "まで{}手で{}", n, self.end.as_deref().unwrap_or("中断")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shogiutil::Piece;
+
+    #[test]
+    fn test_format_kif_move() {
+        let mv = Move {
+            from: Some(Square { file: 7, rank: 7 }),
+            to: Square { file: 7, rank: 6 },
+            piece: Piece::Pawn,
+            color: Color::Black,
+        };
+        assert_eq!(format_kif_move(&mv, false, None), "７六歩(77)");
+
+        let mv = Move {
+            from: Some(Square { file: 8, rank: 8 }),
+            to: Square { file: 2, rank: 2 },
+            piece: Piece::Bishop,
+            color: Color::Black,
+        };
+        let previous_to = Square { file: 2, rank: 2 };
+        assert_eq!(
+            format_kif_move(&mv, true, Some(&previous_to)),
+            "同　角成(88)"
+        );
+
+        let mv = Move {
+            from: None,
+            to: Square { file: 5, rank: 5 },
+            piece: Piece::Pawn,
+            color: Color::White,
+        };
+        assert_eq!(format_kif_move(&mv, false, None), "５五歩打");
+    }
+
+    #[test]
+    fn test_kif_record() {
+        let mut record = KifRecord::new("engine1", "engine2");
+        record.push("７六歩(77)".to_string(), 1, None);
+        record.push("３四歩(33)".to_string(), 62, Some("p=0.5".to_string()));
+        record.push("２六歩(27)".to_string(), 3, None);
+        record.end = Some("投了".to_string());
+        record.winner = Some(Color::Black);
+        assert_eq!(
+            record.to_string(),
+            "手合割：平手
+先手：engine1
+後手：engine2
+手数----指手---------消費時間--
+   1 ７六歩(77)   ( 0:01/00:00:01)
+   2 ３四歩(33)   ( 1:02/00:01:02)
+*p=0.5
+   3 ２六歩(27)   ( 0:03/00:00:04)
+   4 投了
+まで3手で先手の勝ち
+"
+        );
+    }
+}
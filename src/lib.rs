@@ -1,7 +1,33 @@
+pub mod analysis;
+pub mod auxiliary;
+pub mod checkpoint;
 pub mod constants;
+pub mod csa;
+pub mod csa_client;
+pub mod csa_server;
 pub mod data_loader;
+pub mod distillation;
+pub mod elo;
+pub mod evaluation;
+pub mod features;
+pub mod game;
+pub mod game_record;
+pub mod heatmap;
+pub mod inference;
+pub mod inference_queue;
+pub mod kif;
+pub mod lr_scheduler;
+pub mod metrics_sink;
+pub mod mixed_precision;
 pub mod model;
 pub mod network;
+pub mod optimizer;
+pub mod player;
+pub mod policy_server;
 pub mod progressbar;
+pub mod sampling;
+pub mod tensorboard;
+pub mod torchscript;
 pub mod usi;
+pub mod usi_engine;
 pub mod util;
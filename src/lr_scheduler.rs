@@ -0,0 +1,188 @@
+use anyhow::{anyhow, Error};
+use serde::{Deserialize, Serialize};
+use std::cmp::max;
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum LrSchedule {
+    Constant,
+    /// Multiply the learning rate by `gamma` every `step_epochs` epochs.
+    Step {
+        step_epochs: usize,
+        gamma: f64,
+    },
+    /// Anneal from the base learning rate to `min_lr` over all epochs.
+    Cosine {
+        min_lr: f64,
+    },
+    /// Multiply the learning rate by `gamma` when the validation accuracy has not improved for
+    /// `patience` epochs.
+    Plateau {
+        patience: usize,
+        gamma: f64,
+        min_lr: f64,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LrScheduleKind {
+    Constant,
+    Step,
+    Cosine,
+    Plateau,
+}
+
+impl FromStr for LrScheduleKind {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "constant" => Ok(LrScheduleKind::Constant),
+            "step" => Ok(LrScheduleKind::Step),
+            "cosine" => Ok(LrScheduleKind::Cosine),
+            "plateau" => Ok(LrScheduleKind::Plateau),
+            _ => Err(anyhow!("Unknown learning rate schedule: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LrScheduler {
+    base_lr: f64,
+    schedule: LrSchedule,
+    warmup_iters: usize,
+    epochs: usize,
+    iters_per_epoch: usize,
+
+    plateau_lr: f64,
+    best_accuracy: Option<f64>,
+    bad_epochs: usize,
+}
+
+impl LrScheduler {
+    pub fn new(
+        base_lr: f64,
+        schedule: LrSchedule,
+        warmup_iters: usize,
+        epochs: usize,
+        iters_per_epoch: usize,
+    ) -> Self {
+        Self {
+            base_lr,
+            schedule,
+            warmup_iters,
+            epochs,
+            iters_per_epoch,
+            plateau_lr: base_lr,
+            best_accuracy: None,
+            bad_epochs: 0,
+        }
+    }
+
+    /// Learning rate for the `iter`-th iteration of `epoch`.
+    pub fn lr(&self, epoch: usize, iter: usize) -> f64 {
+        let global_iter = epoch * self.iters_per_epoch + iter;
+        let lr = match self.schedule {
+            LrSchedule::Constant => self.base_lr,
+            LrSchedule::Step { step_epochs, gamma } => {
+                self.base_lr * gamma.powi((epoch / step_epochs) as i32)
+            }
+            LrSchedule::Cosine { min_lr } => {
+                let total_iters = max(self.epochs * self.iters_per_epoch, 1);
+                let progress = (global_iter as f64 / total_iters as f64).min(1.0);
+                min_lr + (self.base_lr - min_lr) * (1.0 + (PI * progress).cos()) / 2.0
+            }
+            LrSchedule::Plateau { .. } => self.plateau_lr,
+        };
+
+        if global_iter < self.warmup_iters {
+            lr * (global_iter + 1) as f64 / self.warmup_iters as f64
+        } else {
+            lr
+        }
+    }
+
+    /// Called with the validation accuracy at the end of every epoch.
+    pub fn on_validation(&mut self, accuracy: f64) {
+        let improved = self
+            .best_accuracy
+            .map(|best| accuracy > best)
+            .unwrap_or(true);
+        if improved {
+            self.best_accuracy = Some(accuracy);
+            self.bad_epochs = 0;
+            return;
+        }
+
+        self.bad_epochs += 1;
+        if let LrSchedule::Plateau {
+            patience,
+            gamma,
+            min_lr,
+        } = self.schedule
+        {
+            if self.bad_epochs >= patience {
+                self.plateau_lr = (self.plateau_lr * gamma).max(min_lr);
+                self.bad_epochs = 0;
+                log::info!("Reducing learning rate to {}", self.plateau_lr);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_step() {
+        let schedule = LrSchedule::Step {
+            step_epochs: 2,
+            gamma: 0.1,
+        };
+        let scheduler = LrScheduler::new(0.1, schedule, 0, 10, 100);
+        assert_close(scheduler.lr(0, 0), 0.1);
+        assert_close(scheduler.lr(1, 99), 0.1);
+        assert_close(scheduler.lr(2, 0), 0.01);
+        assert_close(scheduler.lr(5, 50), 0.001);
+    }
+
+    #[test]
+    fn test_cosine_with_warmup() {
+        let schedule = LrSchedule::Cosine { min_lr: 0.0 };
+        let scheduler = LrScheduler::new(0.1, schedule, 10, 2, 100);
+        assert_close(scheduler.lr(0, 0), 0.01);
+        assert_close(
+            scheduler.lr(0, 9),
+            0.1 * (1.0 + (PI * 9.0 / 200.0).cos()) / 2.0,
+        );
+        assert_close(scheduler.lr(1, 0), 0.05);
+        assert_close(scheduler.lr(2, 0), 0.0);
+    }
+
+    #[test]
+    fn test_plateau() {
+        let schedule = LrSchedule::Plateau {
+            patience: 2,
+            gamma: 0.5,
+            min_lr: 0.02,
+        };
+        let mut scheduler = LrScheduler::new(0.1, schedule, 0, 10, 100);
+        scheduler.on_validation(0.3);
+        scheduler.on_validation(0.4);
+        scheduler.on_validation(0.35);
+        assert_close(scheduler.lr(3, 0), 0.1);
+        scheduler.on_validation(0.39);
+        assert_close(scheduler.lr(4, 0), 0.05);
+        scheduler.on_validation(0.3);
+        scheduler.on_validation(0.3);
+        assert_close(scheduler.lr(6, 0), 0.025);
+        scheduler.on_validation(0.3);
+        scheduler.on_validation(0.3);
+        assert_close(scheduler.lr(8, 0), 0.02);
+    }
+}
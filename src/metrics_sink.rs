@@ -0,0 +1,203 @@
+use crate::evaluation::Metrics;
+use crate::mixed_precision::Precision;
+use anyhow::Result;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// One row of the training metrics. Every eval interval and every epoch end writes a row, which
+/// is told apart by `kind`.
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricsRecord {
+    /// Seconds since the Unix epoch.
+    pub timestamp: f64,
+    /// `"eval"` or `"epoch"`.
+    pub kind: &'static str,
+    pub epoch: usize,
+    pub iteration: usize,
+    pub train_loss: f64,
+    pub val_loss: f64,
+    pub val_top1: f64,
+    pub val_top3: f64,
+    pub val_top5: f64,
+    pub val_top10: f64,
+    pub val_legal: f64,
+    pub learning_rate: f64,
+    pub samples_per_sec: f64,
+    /// `"fp32"`, `"fp16"` or `"bf16"`.
+    pub precision: &'static str,
+    /// Batches whose gradients are summed into one optimizer step.
+    pub accumulation_steps: usize,
+    /// Loss scale of fp16 training, 1 otherwise.
+    pub loss_scale: f64,
+    /// Optimizer steps skipped because the fp16 gradients overflowed.
+    pub skipped_steps: usize,
+    /// Mean train losses of the auxiliary heads over the interval, if the model has them. Empty in
+    /// CSV and `null` in JSON otherwise.
+    pub opponent_move_loss: Option<f64>,
+    pub game_length_loss: Option<f64>,
+}
+
+const CSV_HEADER: &str = "timestamp,kind,epoch,iteration,train_loss,val_loss,val_top1,val_top3,val_top5,val_top10,val_legal,learning_rate,samples_per_sec,precision,accumulation_steps,loss_scale,skipped_steps,opponent_move_loss,game_length_loss";
+
+impl MetricsRecord {
+    pub fn new(
+        kind: &'static str,
+        epoch: usize,
+        iteration: usize,
+        train_loss: f64,
+        metrics: &Metrics,
+        learning_rate: f64,
+        samples_per_sec: f64,
+    ) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or(0.0);
+        Self {
+            timestamp,
+            kind,
+            epoch,
+            iteration,
+            train_loss,
+            val_loss: metrics.loss(),
+            val_top1: metrics.top_k[0].accuracy(),
+            val_top3: metrics.top_k[1].accuracy(),
+            val_top5: metrics.top_k[2].accuracy(),
+            val_top10: metrics.top_k[3].accuracy(),
+            val_legal: metrics.legal.accuracy(),
+            learning_rate,
+            samples_per_sec,
+            precision: Precision::Fp32.name(),
+            accumulation_steps: 1,
+            loss_scale: 1.0,
+            skipped_steps: 0,
+            opponent_move_loss: None,
+            game_length_loss: None,
+        }
+    }
+
+    pub fn with_precision(
+        mut self,
+        precision: Precision,
+        accumulation_steps: usize,
+        loss_scale: f64,
+        skipped_steps: usize,
+    ) -> Self {
+        self.precision = precision.name();
+        self.accumulation_steps = accumulation_steps;
+        self.loss_scale = loss_scale;
+        self.skipped_steps = skipped_steps;
+        self
+    }
+
+    pub fn with_auxiliary_losses(
+        mut self,
+        opponent_move_loss: Option<f64>,
+        game_length_loss: Option<f64>,
+    ) -> Self {
+        self.opponent_move_loss = opponent_move_loss;
+        self.game_length_loss = game_length_loss;
+        self
+    }
+
+    fn to_csv_row(&self) -> String {
+        format!(
+            "{:.3},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            self.timestamp,
+            self.kind,
+            self.epoch,
+            self.iteration,
+            self.train_loss,
+            self.val_loss,
+            self.val_top1,
+            self.val_top3,
+            self.val_top5,
+            self.val_top10,
+            self.val_legal,
+            self.learning_rate,
+            self.samples_per_sec,
+            self.precision,
+            self.accumulation_steps,
+            self.loss_scale,
+            self.skipped_steps,
+            optional(self.opponent_move_loss),
+            optional(self.game_length_loss)
+        )
+    }
+}
+
+/// Appends `MetricsRecord`s to CSV and/or JSON Lines files. Files are appended to so that a
+/// resumed run continues the same file.
+#[derive(Default)]
+pub struct MetricsSink {
+    csv: Option<File>,
+    jsonl: Option<File>,
+}
+
+impl MetricsSink {
+    pub fn new<P: AsRef<Path>>(csv: Option<P>, jsonl: Option<P>) -> Result<Self> {
+        let csv = match csv {
+            Some(path) => {
+                let is_new = !path.as_ref().exists();
+                let mut file = open_append(path)?;
+                if is_new {
+                    writeln!(file, "{}", CSV_HEADER)?;
+                }
+                Some(file)
+            }
+            None => None,
+        };
+        let jsonl = match jsonl {
+            Some(path) => Some(open_append(path)?),
+            None => None,
+        };
+        Ok(Self { csv, jsonl })
+    }
+
+    pub fn write(&mut self, record: &MetricsRecord) -> Result<()> {
+        if let Some(csv) = self.csv.as_mut() {
+            writeln!(csv, "{}", record.to_csv_row())?;
+            csv.flush()?;
+        }
+        if let Some(jsonl) = self.jsonl.as_mut() {
+            writeln!(jsonl, "{}", serde_json::to_string(record)?)?;
+            jsonl.flush()?;
+        }
+        Ok(())
+    }
+}
+
+fn optional(value: Option<f64>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+fn open_append<P: AsRef<Path>>(path: P) -> Result<File> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_row_matches_header() {
+        let record = MetricsRecord::new("eval", 1, 100, 7.68, &Metrics::default(), 0.01, 512.0);
+        let row = record.to_csv_row();
+        assert_eq!(row.split(',').count(), CSV_HEADER.split(',').count());
+        assert!(row.contains(",eval,1,100,7.68,"));
+
+        let record = record.with_precision(Precision::Fp16, 4, 32768.0, 2);
+        let row = record.to_csv_row();
+        assert_eq!(row.split(',').count(), CSV_HEADER.split(',').count());
+        assert!(row.ends_with(",fp16,4,32768,2,,"));
+
+        let record = record.with_auxiliary_losses(Some(0.5), None);
+        let row = record.to_csv_row();
+        assert_eq!(row.split(',').count(), CSV_HEADER.split(',').count());
+        assert!(row.ends_with(",fp16,4,32768,2,0.5,"));
+    }
+}
@@ -0,0 +1,243 @@
+//! Mixed-precision training of `PolicyNetwork`.
+//!
+//! tch has no autocast, so the forward and backward passes run on a copy of the network in fp16
+//! or bf16, while the `Optimizer` keeps updating the float32 weights of the original `VarStore`.
+//! The copy is refreshed from the float32 weights before every optimizer step. The loss is scaled
+//! before the backward pass of fp16 so that small gradients do not flush to zero, and the scale is
+//! adjusted as by `torch.cuda.amp.GradScaler`. bf16 has the exponent range of float32 and is not
+//! scaled. libtorch has no fp16 convolutions on the CPU, so fp16 needs a GPU.
+use crate::network::config::ModelConfig;
+use crate::network::policy::{Heads, PolicyNetwork};
+use crate::optimizer::Optimizer;
+use anyhow::{anyhow, Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use tch::kind::Kind::{self, BFloat16, Float, Half};
+use tch::nn::{Module, VarStore};
+use tch::{no_grad, Tensor};
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Precision {
+    Fp32,
+    Fp16,
+    Bf16,
+}
+
+impl Precision {
+    pub fn kind(&self) -> Kind {
+        match self {
+            Precision::Fp32 => Float,
+            Precision::Fp16 => Half,
+            Precision::Bf16 => BFloat16,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Precision::Fp32 => "fp32",
+            Precision::Fp16 => "fp16",
+            Precision::Bf16 => "bf16",
+        }
+    }
+}
+
+impl FromStr for Precision {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fp32" => Ok(Precision::Fp32),
+            "fp16" => Ok(Precision::Fp16),
+            "bf16" => Ok(Precision::Bf16),
+            _ => Err(anyhow!("Unknown precision: {}", s)),
+        }
+    }
+}
+
+const GROWTH_FACTOR: f64 = 2.0;
+const BACKOFF_FACTOR: f64 = 0.5;
+const GROWTH_INTERVAL: usize = 2000;
+
+/// Dynamic loss scale. It is halved whenever the gradients overflow, and doubled after
+/// `GROWTH_INTERVAL` steps in a row without overflow.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LossScaler {
+    scale: f64,
+    good_steps: usize,
+    skipped_steps: usize,
+}
+
+impl LossScaler {
+    pub fn new(initial_scale: f64) -> Self {
+        Self {
+            scale: initial_scale,
+            good_steps: 0,
+            skipped_steps: 0,
+        }
+    }
+
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    /// Total number of steps skipped because their gradients overflowed.
+    pub fn skipped_steps(&self) -> usize {
+        self.skipped_steps
+    }
+
+    /// Updates the scale after the gradients of a step are checked. Returns whether the step
+    /// should be taken.
+    pub fn update(&mut self, finite: bool) -> bool {
+        if finite {
+            self.good_steps += 1;
+            if self.good_steps == GROWTH_INTERVAL {
+                self.scale *= GROWTH_FACTOR;
+                self.good_steps = 0;
+            }
+        } else {
+            self.scale *= BACKOFF_FACTOR;
+            self.good_steps = 0;
+            self.skipped_steps += 1;
+        }
+        finite
+    }
+}
+
+/// Low-precision copy of a `PolicyNetwork` whose gradients update the float32 original.
+pub struct MixedPrecision {
+    precision: Precision,
+    vs: VarStore,
+    model: PolicyNetwork,
+    scaler: Option<LossScaler>,
+}
+
+impl MixedPrecision {
+    /// `config` must be the config of the network of `master`.
+    pub fn new(
+        master: &VarStore,
+        config: &ModelConfig,
+        precision: Precision,
+        initial_scale: f64,
+    ) -> Result<Self> {
+        let mut vs = VarStore::new(master.device());
+        let model = PolicyNetwork::new(&vs.root(), config);
+        vs.set_kind(precision.kind());
+        vs.copy(master)?;
+        let scaler = match precision {
+            Precision::Fp16 => Some(LossScaler::new(initial_scale)),
+            Precision::Fp32 | Precision::Bf16 => None,
+        };
+        Ok(Self {
+            precision,
+            vs,
+            model,
+            scaler,
+        })
+    }
+
+    pub fn loss_scale(&self) -> f64 {
+        self.scaler.as_ref().map(|s| s.scale()).unwrap_or(1.0)
+    }
+
+    pub fn skipped_steps(&self) -> usize {
+        self.scaler.as_ref().map(|s| s.skipped_steps()).unwrap_or(0)
+    }
+
+    /// The loss scaler of fp16, to be saved with the training state.
+    pub fn loss_scaler(&self) -> Option<&LossScaler> {
+        self.scaler.as_ref()
+    }
+
+    /// Continues with the scale and counters of a saved `scaler`. Ignored unless this is fp16.
+    pub fn restore_loss_scaler(&mut self, scaler: LossScaler) {
+        if let Some(current) = self.scaler.as_mut() {
+            *current = scaler;
+        }
+    }
+
+    /// Copies the float32 weights and clears the gradients, before the first batch of a step.
+    pub fn start_step(&mut self, master: &VarStore) -> Result<()> {
+        self.vs.copy(master)?;
+        for (_, var) in self.vs.variables() {
+            let mut grad = var.grad();
+            if grad.defined() {
+                let _ = grad.detach_();
+                let _ = grad.zero_();
+            }
+        }
+        Ok(())
+    }
+
+    /// Float32 logits of the copy for float32 `xs`.
+    pub fn forward(&self, xs: &Tensor) -> Tensor {
+        self.model
+            .forward(&xs.totype(self.precision.kind()))
+            .totype(Float)
+    }
+
+    /// Float32 outputs of every head of the copy for float32 `xs`.
+    pub fn forward_heads(&self, xs: &Tensor) -> Heads {
+        let heads = self.model.forward_heads(&xs.totype(self.precision.kind()));
+        Heads {
+            policy: heads.policy.totype(Float),
+            opponent_move: heads.opponent_move.map(|y| y.totype(Float)),
+            game_length: heads.game_length.map(|y| y.totype(Float)),
+        }
+    }
+
+    pub fn backward(&self, loss: &Tensor) {
+        (loss * self.loss_scale()).backward();
+    }
+
+    /// Updates the float32 weights with the unscaled gradients of the copy. Returns `false` and
+    /// skips the step if they have overflowed.
+    pub fn step(&mut self, optimizer: &mut Optimizer) -> bool {
+        let scale = self.loss_scale();
+        let mut gradients = BTreeMap::new();
+        let mut finite = true;
+        no_grad(|| {
+            for (name, var) in self.vs.variables() {
+                let grad = var.grad();
+                if !grad.defined() {
+                    continue;
+                }
+                let grad = grad.totype(Float) / scale;
+                finite &= f64::from(&grad.sum(Float)).is_finite();
+                gradients.insert(name, grad);
+            }
+        });
+        let finite = match self.scaler.as_mut() {
+            Some(scaler) => scaler.update(finite),
+            None => finite,
+        };
+        if finite {
+            optimizer.step_with_gradients(&gradients);
+        }
+        finite
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_loss_scaler() {
+        let mut scaler = LossScaler::new(65536.0);
+        assert!(!scaler.update(false));
+        assert_eq!(scaler.scale(), 32768.0);
+        for _ in 0..GROWTH_INTERVAL - 1 {
+            assert!(scaler.update(true));
+        }
+        assert_eq!(scaler.scale(), 32768.0);
+        assert!(scaler.update(true));
+        assert_eq!(scaler.scale(), 65536.0);
+        assert_eq!(scaler.skipped_steps(), 1);
+    }
+
+    #[test]
+    fn test_precision_from_str() {
+        assert_eq!("bf16".parse::<Precision>().unwrap(), Precision::Bf16);
+        assert!("fp8".parse::<Precision>().is_err());
+    }
+}
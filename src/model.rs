@@ -66,11 +66,67 @@ impl MoveDirection {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Position {
     pub features: Vec<u128>,
     pub is_winner_turn: bool,
     pub move_label: i16,
+
+    /// Rating of the player to move, if the kifu has one.
+    pub rate: Option<f32>,
+    /// Rating of the opponent of the player to move.
+    pub opponent_rate: Option<f32>,
+    /// 1-origin index of this move in the game.
+    pub move_number: u16,
+    /// Line number of the game in the kifu list the position was read from.
+    pub game_id: u32,
+
+    /// `Piece::to_usize()` of the moved piece.
+    pub piece: u8,
+
+    /// Label of the reply of the opponent as seen from the opponent, `None` for the last move.
+    pub opponent_move_label: Option<i16>,
+    /// Number of moves of the game.
+    pub game_length: u16,
+
+    /// Probabilities of the legal moves by label, for positions of self-play. Positions of kifu
+    /// have none and are trained on `move_label` alone.
+    pub policy_target: Vec<(i16, f32)>,
+}
+
+impl Position {
+    /// A position of `features` played with `move_label` and no other data, for tests and
+    /// benchmarks. Override the other fields with struct update syntax.
+    pub fn new(features: Vec<u128>, move_label: i16) -> Position {
+        Position {
+            features,
+            is_winner_turn: true,
+            move_label,
+            rate: None,
+            opponent_rate: None,
+            move_number: 1,
+            game_id: 0,
+            piece: 1,
+            opponent_move_label: None,
+            game_length: 1,
+            policy_target: vec![],
+        }
+    }
+}
+
+/// Sets the targets of the auxiliary heads of `positions`, every move of a game in order.
+pub fn set_auxiliary_targets(positions: &mut [Position]) {
+    let game_length = positions.len() as u16;
+    let replies = positions
+        .iter()
+        .skip(1)
+        .map(|position| Some(position.move_label))
+        .chain(std::iter::once(None))
+        .collect::<Vec<_>>();
+    for (position, reply) in positions.iter_mut().zip(replies.into_iter()) {
+        position.opponent_move_label = reply;
+        position.game_length = game_length;
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,129 @@
+use crate::features::{FeatureSet, History, InputFeatures};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs::{read_to_string, write};
+use std::path::{Path, PathBuf};
+
+/// Everything besides the weights needed to build a network, saved as JSON next to them.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModelConfig {
+    pub feature_set: FeatureSet,
+    pub history: History,
+    pub auxiliary: AuxiliaryHeads,
+    /// Filters of the 3x3 convolutions.
+    pub channels: usize,
+    /// Number of the 3x3 convolutions before the policy head.
+    pub layers: usize,
+}
+
+impl Default for ModelConfig {
+    fn default() -> Self {
+        ModelConfig {
+            feature_set: FeatureSet::default(),
+            history: History::default(),
+            auxiliary: AuxiliaryHeads::default(),
+            channels: 192,
+            layers: 12,
+        }
+    }
+}
+
+/// Loss weights of the heads trained along the policy to regularize the trunk. A head only exists
+/// if its weight is positive, and is not evaluated at inference.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuxiliaryHeads {
+    /// Predicts the reply of the opponent.
+    pub opponent_move: f64,
+    /// Predicts the number of moves left in the game.
+    pub game_length: f64,
+}
+
+impl AuxiliaryHeads {
+    pub fn any(&self) -> bool {
+        self.opponent_move > 0.0 || self.game_length > 0.0
+    }
+}
+
+impl ModelConfig {
+    pub fn input_features(&self) -> InputFeatures {
+        InputFeatures {
+            feature_set: self.feature_set,
+            history: self.history,
+        }
+    }
+
+    pub fn input_channels(&self) -> usize {
+        self.input_features().input_channels()
+    }
+
+    /// Whether the weights of a network of `self` fit a network of `other`, which is the case
+    /// when only the loss weights of existing auxiliary heads differ.
+    pub fn same_architecture(&self, other: &ModelConfig) -> bool {
+        let heads = |config: &ModelConfig| {
+            (
+                config.auxiliary.opponent_move > 0.0,
+                config.auxiliary.game_length > 0.0,
+            )
+        };
+        self.input_features() == other.input_features()
+            && self.channels == other.channels
+            && self.layers == other.layers
+            && heads(self) == heads(other)
+    }
+
+    /// Path of the config of the weights at `weights`, e.g. `model.ot.config.json`.
+    pub fn path_for<P: AsRef<Path>>(weights: P) -> PathBuf {
+        let mut path = weights.as_ref().as_os_str().to_owned();
+        path.push(".config.json");
+        PathBuf::from(path)
+    }
+
+    pub fn save_for<P: AsRef<Path>>(&self, weights: P) -> Result<()> {
+        write(Self::path_for(weights), serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Weights saved without a config are of the default config.
+    pub fn load_for<P: AsRef<Path>>(weights: P) -> Result<Self> {
+        let path = Self::path_for(weights);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_str(&read_to_string(path)?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_config() {
+        assert_eq!(
+            ModelConfig::path_for("./model.ot"),
+            PathBuf::from("./model.ot.config.json")
+        );
+        let config: ModelConfig = serde_json::from_str(r#"{"feature_set": "v2"}"#).unwrap();
+        assert_eq!(config.feature_set, FeatureSet::V2);
+        assert_eq!(config.input_channels(), 113);
+        let config: ModelConfig =
+            serde_json::from_str(r#"{"feature_set": "v2", "history": {"moves": 1}}"#).unwrap();
+        assert_eq!(config.history.positions, 0);
+        assert_eq!(config.input_channels(), 115);
+        assert!(!config.auxiliary.any());
+
+        let mut other = config;
+        other.auxiliary.game_length = 0.5;
+        assert!(!config.same_architecture(&other));
+        let mut reweighted = other;
+        reweighted.auxiliary.game_length = 2.0;
+        assert!(other.same_architecture(&reweighted));
+        reweighted.channels += 1;
+        assert!(!other.same_architecture(&reweighted));
+        assert_eq!((config.channels, config.layers), (192, 12));
+        let config: ModelConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(config, ModelConfig::default());
+    }
+}
@@ -1,5 +1,6 @@
-use crate::constants::{INPUT_CHANNELS, MOVE_DIRECTION_LABEL_NUM};
-use tch::nn::{Conv2D, ConvConfig, Module, Path};
+use crate::constants::MOVE_DIRECTION_LABEL_NUM;
+use crate::network::config::ModelConfig;
+use tch::nn::{Conv2D, ConvConfig, Linear, Module, Path};
 use tch::Tensor;
 
 #[derive(Debug)]
@@ -21,86 +22,141 @@ impl Module for Bias {
     }
 }
 
+/// Outputs of every head of a `PolicyNetwork`.
+pub struct Heads {
+    /// Logits of the move labels.
+    pub policy: Tensor,
+    /// Logits of the labels of the reply of the opponent, as seen from the opponent.
+    pub opponent_move: Option<Tensor>,
+    /// Moves left in the game divided by `features::MAX_MOVE_NUMBER`, of shape `[N]`.
+    pub game_length: Option<Tensor>,
+}
+
 #[derive(Debug)]
 pub struct PolicyNetwork {
-    l1: Conv2D,
-    l2: Conv2D,
-    l3: Conv2D,
-    l4: Conv2D,
-    l5: Conv2D,
-    l6: Conv2D,
-    l7: Conv2D,
-    l8: Conv2D,
-    l9: Conv2D,
-    l10: Conv2D,
-    l11: Conv2D,
-    l12: Conv2D,
-    l13: Conv2D,
-    l13_bias: Bias,
+    /// 3x3 convolutions of `config.channels` filters, each followed by ReLU.
+    layers: Vec<Conv2D>,
+    output: Conv2D,
+    output_bias: Bias,
+    opponent_move: Option<(Conv2D, Bias)>,
+    game_length: Option<(Conv2D, Linear)>,
+    config: ModelConfig,
 }
 
-const CH: i64 = 192;
 impl PolicyNetwork {
-    pub fn new(vs: &Path) -> Self {
+    pub fn new(vs: &Path, config: &ModelConfig) -> Self {
+        assert!(
+            config.layers >= 1 && config.channels >= 1,
+            "A network needs at least one layer and one channel: {:?}",
+            config
+        );
+        let channels = config.channels as i64;
         let conv_config = ConvConfig {
             padding: 1,
             ..Default::default()
         };
-        let l1 = tch::nn::conv2d(vs, INPUT_CHANNELS as i64, CH, 3, conv_config);
-        let l2 = tch::nn::conv2d(vs, CH, CH, 3, conv_config);
-        let l3 = tch::nn::conv2d(vs, CH, CH, 3, conv_config);
-        let l4 = tch::nn::conv2d(vs, CH, CH, 3, conv_config);
-        let l5 = tch::nn::conv2d(vs, CH, CH, 3, conv_config);
-        let l6 = tch::nn::conv2d(vs, CH, CH, 3, conv_config);
-        let l7 = tch::nn::conv2d(vs, CH, CH, 3, conv_config);
-        let l8 = tch::nn::conv2d(vs, CH, CH, 3, conv_config);
-        let l9 = tch::nn::conv2d(vs, CH, CH, 3, conv_config);
-        let l10 = tch::nn::conv2d(vs, CH, CH, 3, conv_config);
-        let l11 = tch::nn::conv2d(vs, CH, CH, 3, conv_config);
-        let l12 = tch::nn::conv2d(vs, CH, CH, 3, conv_config);
+        let layers = (0..config.layers)
+            .map(|i| {
+                let input_channels = if i == 0 {
+                    config.input_channels() as i64
+                } else {
+                    channels
+                };
+                tch::nn::conv2d(vs, input_channels, channels, 3, conv_config)
+            })
+            .collect();
 
         let conv_config = ConvConfig {
             bias: false,
             ..Default::default()
         };
-        let l13 = tch::nn::conv2d(vs, CH, MOVE_DIRECTION_LABEL_NUM, 1, conv_config);
-        let l13_bias = Bias::new(vs, 9 * 9 * MOVE_DIRECTION_LABEL_NUM);
+        let output = tch::nn::conv2d(vs, channels, MOVE_DIRECTION_LABEL_NUM, 1, conv_config);
+        let output_bias = Bias::new(vs, 9 * 9 * MOVE_DIRECTION_LABEL_NUM);
+
+        // The heads are created after the policy so that its variables keep their names.
+        let opponent_move = if config.auxiliary.opponent_move > 0.0 {
+            Some((
+                tch::nn::conv2d(vs, channels, MOVE_DIRECTION_LABEL_NUM, 1, conv_config),
+                Bias::new(vs, 9 * 9 * MOVE_DIRECTION_LABEL_NUM),
+            ))
+        } else {
+            None
+        };
+        let game_length = if config.auxiliary.game_length > 0.0 {
+            Some((
+                tch::nn::conv2d(vs, channels, 1, 1, Default::default()),
+                tch::nn::linear(vs, 9 * 9, 1, Default::default()),
+            ))
+        } else {
+            None
+        };
         Self {
-            l1,
-            l2,
-            l3,
-            l4,
-            l5,
-            l6,
-            l7,
-            l8,
-            l9,
-            l10,
-            l11,
-            l12,
-            l13,
-            l13_bias,
+            layers,
+            output,
+            output_bias,
+            opponent_move,
+            game_length,
+            config: *config,
+        }
+    }
+
+    pub fn config(&self) -> &ModelConfig {
+        &self.config
+    }
+
+    /// Convolutions in the order `forward` applies them. All but the last are followed by ReLU.
+    pub fn convolutions(&self) -> Vec<&Conv2D> {
+        self.layers.iter().chain(Some(&self.output)).collect()
+    }
+
+    /// Bias added to the flattened output of the last convolution.
+    pub fn output_bias(&self) -> &Tensor {
+        &self.output_bias.bias
+    }
+}
+
+impl PolicyNetwork {
+    /// Outputs of the policy and of the auxiliary heads, for training.
+    pub fn forward_heads(&self, x: &Tensor) -> Heads {
+        let h = self.trunk(x);
+        let batchsize = h.size()[0];
+        let opponent_move = self.opponent_move.as_ref().map(|(conv, bias)| {
+            h.apply(conv)
+                .reshape(&[batchsize, 9 * 9 * MOVE_DIRECTION_LABEL_NUM])
+                .apply(bias)
+        });
+        let game_length = self.game_length.as_ref().map(|(conv, linear)| {
+            h.apply(conv)
+                .relu()
+                .reshape(&[batchsize, 9 * 9])
+                .apply(linear)
+                .sigmoid()
+                .view(-1)
+        });
+        Heads {
+            policy: self.policy(&h),
+            opponent_move,
+            game_length,
         }
     }
+
+    /// Output of the last layer shared by the heads.
+    fn trunk(&self, x: &Tensor) -> Tensor {
+        self.layers
+            .iter()
+            .fold(x.shallow_clone(), |h, conv| h.apply(conv).relu())
+    }
+
+    fn policy(&self, h: &Tensor) -> Tensor {
+        let h = h.apply(&self.output);
+        let batchsize = h.size()[0];
+        h.reshape(&[batchsize, 9 * 9 * MOVE_DIRECTION_LABEL_NUM])
+            .apply(&self.output_bias)
+    }
 }
 
 impl Module for PolicyNetwork {
     fn forward(&self, x: &Tensor) -> Tensor {
-        let h1 = x.apply(&self.l1).relu();
-        let h2 = h1.apply(&self.l2).relu();
-        let h3 = h2.apply(&self.l3).relu();
-        let h4 = h3.apply(&self.l4).relu();
-        let h5 = h4.apply(&self.l5).relu();
-        let h6 = h5.apply(&self.l6).relu();
-        let h7 = h6.apply(&self.l7).relu();
-        let h8 = h7.apply(&self.l8).relu();
-        let h9 = h8.apply(&self.l9).relu();
-        let h10 = h9.apply(&self.l10).relu();
-        let h11 = h10.apply(&self.l11).relu();
-        let h12 = h11.apply(&self.l12).relu();
-        let h13 = h12.apply(&self.l13);
-        let batchsize = h13.size()[0];
-        h13.reshape(&[batchsize, 9 * 9 * MOVE_DIRECTION_LABEL_NUM])
-            .apply(&self.l13_bias)
+        self.policy(&self.trunk(x))
     }
 }
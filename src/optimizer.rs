@@ -0,0 +1,205 @@
+use anyhow::{anyhow, bail, Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::str::FromStr;
+use tch::nn::VarStore;
+use tch::{no_grad, Tensor};
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum OptimizerKind {
+    Sgd,
+    Adam,
+    AdamW,
+}
+
+impl FromStr for OptimizerKind {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sgd" => Ok(OptimizerKind::Sgd),
+            "adam" => Ok(OptimizerKind::Adam),
+            "adamw" => Ok(OptimizerKind::AdamW),
+            _ => Err(anyhow!("Unknown optimizer: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct OptimizerOptions {
+    pub kind: OptimizerKind,
+    pub learning_rate: f64,
+    pub momentum: f64,
+    pub nesterov: bool,
+    pub weight_decay: f64,
+}
+
+const ADAM_BETA1: f64 = 0.9;
+const ADAM_BETA2: f64 = 0.999;
+const ADAM_EPS: f64 = 1e-8;
+
+/// SGD/Adam/AdamW over all trainable variables of a `VarStore`.
+///
+/// The update rules follow `torch.optim`, but the moment buffers live on the Rust side so that
+/// they can be written to and restored from a checkpoint. Plain SGD keeps no buffers.
+pub struct Optimizer {
+    options: OptimizerOptions,
+    lr: f64,
+    step: i64,
+    variables: Vec<(String, Tensor)>,
+    first_moments: BTreeMap<String, Tensor>,
+    second_moments: BTreeMap<String, Tensor>,
+}
+
+impl Optimizer {
+    pub fn build(vs: &VarStore, options: &OptimizerOptions) -> Result<Self> {
+        if options.kind == OptimizerKind::Sgd && options.nesterov && options.momentum <= 0.0 {
+            bail!("Nesterov momentum requires a positive momentum");
+        }
+        let mut variables = vs
+            .variables()
+            .into_iter()
+            .filter(|(_, var)| var.requires_grad())
+            .collect::<Vec<_>>();
+        variables.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(Self {
+            options: *options,
+            lr: options.learning_rate,
+            step: 0,
+            variables,
+            first_moments: BTreeMap::new(),
+            second_moments: BTreeMap::new(),
+        })
+    }
+
+    pub fn set_lr(&mut self, lr: f64) {
+        self.lr = lr;
+    }
+
+    pub fn zero_grad(&mut self) {
+        for (_, var) in self.variables.iter() {
+            let mut grad = var.grad();
+            if grad.defined() {
+                let _ = grad.detach_();
+                let _ = grad.zero_();
+            }
+        }
+    }
+
+    pub fn backward_step(&mut self, loss: &Tensor) {
+        self.zero_grad();
+        loss.backward();
+        self.step();
+    }
+
+    pub fn step(&mut self) {
+        self.update(|_, var| var.grad());
+    }
+
+    /// Updates the variables with `gradients` by name instead of their own gradients, e.g. those
+    /// computed on a copy of the network in lower precision.
+    pub fn step_with_gradients(&mut self, gradients: &BTreeMap<String, Tensor>) {
+        self.update(|name, _| match gradients.get(name) {
+            Some(gradient) => gradient.shallow_clone(),
+            None => Tensor::new(),
+        });
+    }
+
+    /// Variables whose gradient is undefined are left as they are.
+    fn update<F: Fn(&str, &Tensor) -> Tensor>(&mut self, gradient: F) {
+        self.step += 1;
+        let options = self.options;
+        let lr = self.lr;
+        let step = self.step;
+        let variables = &mut self.variables;
+        let first_moments = &mut self.first_moments;
+        let second_moments = &mut self.second_moments;
+        no_grad(|| {
+            for (name, var) in variables.iter_mut() {
+                let grad = gradient(name, var);
+                if !grad.defined() {
+                    continue;
+                }
+                let update = match options.kind {
+                    OptimizerKind::Sgd => {
+                        let grad = grad + &*var * options.weight_decay;
+                        if options.momentum == 0.0 {
+                            grad
+                        } else {
+                            let first = first_moments
+                                .entry(name.clone())
+                                .or_insert_with(|| var.zeros_like());
+                            let buf = &*first * options.momentum + &grad;
+                            first.copy_(&buf);
+                            if options.nesterov {
+                                grad + buf * options.momentum
+                            } else {
+                                buf
+                            }
+                        }
+                    }
+                    OptimizerKind::Adam | OptimizerKind::AdamW => {
+                        let grad = if options.kind == OptimizerKind::Adam {
+                            grad + &*var * options.weight_decay
+                        } else {
+                            let decayed = &*var * (1.0 - lr * options.weight_decay);
+                            var.copy_(&decayed);
+                            grad
+                        };
+                        let first = first_moments
+                            .entry(name.clone())
+                            .or_insert_with(|| var.zeros_like());
+                        let second = second_moments
+                            .entry(name.clone())
+                            .or_insert_with(|| var.zeros_like());
+                        let new_first = &*first * ADAM_BETA1 + &grad * (1.0 - ADAM_BETA1);
+                        let new_second = &*second * ADAM_BETA2 + &grad * &grad * (1.0 - ADAM_BETA2);
+                        first.copy_(&new_first);
+                        second.copy_(&new_second);
+                        let bias_correction1 = 1.0 - ADAM_BETA1.powi(step as i32);
+                        let bias_correction2 = 1.0 - ADAM_BETA2.powi(step as i32);
+                        let denom = (&*second / bias_correction2).sqrt() + ADAM_EPS;
+                        &*first / bias_correction1 / denom
+                    }
+                };
+                let updated = &*var - update * lr;
+                var.copy_(&updated);
+            }
+        });
+    }
+
+    /// Writes the moment buffers and the step counter.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut named_tensors = vec![("step".to_string(), Tensor::of_slice(&[self.step]))];
+        for (name, tensor) in self.first_moments.iter() {
+            named_tensors.push((format!("first.{}", name), tensor.shallow_clone()));
+        }
+        for (name, tensor) in self.second_moments.iter() {
+            named_tensors.push((format!("second.{}", name), tensor.shallow_clone()));
+        }
+        Tensor::save_multi(&named_tensors, path)?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let device = match self.variables.first() {
+            Some((_, var)) => var.device(),
+            None => return Ok(()),
+        };
+        self.first_moments.clear();
+        self.second_moments.clear();
+        for (name, tensor) in Tensor::load_multi(path)? {
+            let tensor = tensor.to_device(device);
+            if name == "step" {
+                self.step = tensor.int64_value(&[0]);
+            } else if let Some(name) = name.strip_prefix("first.") {
+                self.first_moments.insert(name.to_string(), tensor);
+            } else if let Some(name) = name.strip_prefix("second.") {
+                self.second_moments.insert(name.to_string(), tensor);
+            } else {
+                return Err(anyhow!("Unknown optimizer state: {}", name));
+            }
+        }
+        Ok(())
+    }
+}
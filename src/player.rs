@@ -0,0 +1,198 @@
+use crate::features::{FeatureContext, InputFeatures};
+use crate::game::can_declare_win;
+use crate::game_record::{GameRecorder, MoveComment};
+use crate::inference::predict;
+use crate::network::policy::PolicyNetwork;
+use crate::sampling::MoveSampler;
+use crate::usi::{GameOver, GoTime, UsiPlayer};
+use rand::prelude::*;
+use shogiutil::{Board, Color, UsiRequest, UsiResponse};
+use std::time::Instant;
+use tch::nn::{Module, VarStore};
+use tch::Device;
+
+/// Plays the move chosen by `sampler` from the output of `PolicyNetwork`, without search.
+pub struct PolicyPlayer {
+    model: Box<dyn Module>,
+    device: Device,
+    input_features: InputFeatures,
+    board: Option<Board>,
+    next_turn: Option<Color>,
+    context: FeatureContext,
+    sampler: MoveSampler,
+    seed: u64,
+    rng: StdRng,
+    games: u64,
+    own_moves: usize,
+    recorder: Option<GameRecorder>,
+}
+
+/// Number of alternatives to the played move written in game records.
+const RECORDED_ALTERNATIVES: usize = 3;
+
+impl PolicyPlayer {
+    /// The n-th game of the session samples with seed `seed + n`.
+    pub fn new(model: PolicyNetwork, vs: VarStore, sampler: MoveSampler, seed: u64) -> Self {
+        let input_features = model.config().input_features();
+        Self::from_module(Box::new(model), vs.device(), input_features, sampler, seed)
+    }
+
+    /// Plays with any network following the contract of `TorchScriptPolicy` with the inputs of
+    /// `input_features`.
+    pub fn from_module(
+        model: Box<dyn Module>,
+        device: Device,
+        input_features: InputFeatures,
+        sampler: MoveSampler,
+        seed: u64,
+    ) -> Self {
+        Self {
+            model,
+            device,
+            input_features,
+            board: None,
+            next_turn: None,
+            context: FeatureContext::default(),
+            sampler,
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            games: 0,
+            own_moves: 0,
+            recorder: None,
+        }
+    }
+
+    pub fn with_recorder(mut self, recorder: GameRecorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    fn init(&mut self) {}
+
+    fn finish_record(&mut self, result: Option<GameOver>) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            if let Err(e) = recorder.finish(result) {
+                log::warn!("Failed to save the game: {}", e);
+            }
+        }
+    }
+
+    fn new_game(&mut self) {
+        self.finish_record(None);
+        self.rng = StdRng::seed_from_u64(self.seed.wrapping_add(self.games));
+        self.games += 1;
+        self.own_moves = 0;
+    }
+}
+
+impl UsiPlayer for PolicyPlayer {
+    fn play(&mut self, request: UsiRequest) -> Vec<UsiResponse> {
+        use UsiResponse::*;
+        match request {
+            UsiRequest::Usi => vec![
+                Id {
+                    name: "policy_player".to_string(),
+                },
+                UsiOk,
+            ],
+            UsiRequest::IsReady => {
+                self.init();
+                vec![ReadyOk]
+            }
+            UsiRequest::SetOption { .. } => vec![],
+            UsiRequest::NewGame => {
+                self.new_game();
+                vec![]
+            }
+            UsiRequest::Position { board, next_turn } => {
+                if let Some(recorder) = self.recorder.as_mut() {
+                    recorder.position(&board, next_turn);
+                }
+                self.board = Some(board);
+                self.next_turn = Some(next_turn);
+                vec![]
+            }
+            UsiRequest::Go => {
+                let board = self.board.take().unwrap();
+                let next_turn = self.next_turn.take().unwrap();
+                log::info!("next_turn={:?}", next_turn);
+                let start = Instant::now();
+                let moves = predict(
+                    self.model.as_ref(),
+                    self.device,
+                    self.input_features,
+                    &board,
+                    next_turn,
+                    &self.context,
+                );
+                for mv in moves.iter() {
+                    log::info!("{:?} {:.5}", mv.mv.mv, mv.probability);
+                }
+                if moves.is_empty() {
+                    log::info!("no legal moves, resigning");
+                    return vec![];
+                }
+
+                let logits = moves.iter().map(|mv| mv.logit).collect::<Vec<_>>();
+                let index = self.sampler.sample(&logits, self.own_moves, &mut self.rng);
+                self.own_moves += 1;
+                log::info!("chosen {:?} rank={}", moves[index].mv.mv, index + 1);
+                if let Some(recorder) = self.recorder.as_mut() {
+                    let alternatives = moves
+                        .iter()
+                        .enumerate()
+                        .filter(|&(i, _)| i != index)
+                        .take(RECORDED_ALTERNATIVES)
+                        .map(|(_, mv)| (mv.mv.clone(), mv.probability))
+                        .collect();
+                    let comment = MoveComment {
+                        probability: moves[index].probability,
+                        alternatives,
+                        seconds: start.elapsed().as_secs_f64(),
+                    };
+                    recorder.own_move(&moves[index].mv, comment);
+                }
+                let best_move = moves.into_iter().nth(index).unwrap().mv;
+                if let Some(from) = best_move.mv.from {
+                    vec![TravelMove {
+                        from,
+                        to: best_move.mv.to,
+                        promoted: best_move.promoted,
+                    }]
+                } else {
+                    vec![UsiResponse::DropMove {
+                        to: best_move.mv.to,
+                        piece: best_move.mv.piece,
+                    }]
+                }
+            }
+            UsiRequest::Quit => {
+                self.finish_record(None);
+                vec![]
+            }
+        }
+    }
+
+    fn game_over(&mut self, result: GameOver) {
+        self.finish_record(Some(result));
+    }
+
+    fn go_time(&mut self, time: GoTime) {
+        // Moves are chosen without search, in far less than any byoyomi.
+        log::info!("{:?}", time);
+    }
+
+    fn declare_win(&mut self) -> bool {
+        match (self.board.as_ref(), self.next_turn) {
+            (Some(board), Some(next_turn)) => can_declare_win(board, next_turn),
+            _ => false,
+        }
+    }
+
+    fn position_command(&mut self, command: &str) {
+        self.context = FeatureContext::from_position_command(command).unwrap_or_else(|e| {
+            log::warn!("{}", e);
+            FeatureContext::default()
+        });
+    }
+}
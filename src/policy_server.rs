@@ -0,0 +1,280 @@
+//! HTTP server returning the legal moves of a position ranked by `PolicyNetwork`, for tools which
+//! do not link tch.
+//!
+//! `POST /predict` takes `{"sfen": "<sfen or startpos>", "moves": ["7g7f"], "top": 10}`, where
+//! `moves` and `top` are optional, and returns `{"moves": [{"usi": "2g2f", "csa": "+2726FU",
+//! "label": 1239, "probability": 0.41}, ...]}` with the most probable move first. `GET /health`
+//! returns `ok`. Requests of concurrent connections are evaluated in batches by `InferenceQueue`.
+use crate::features::FeatureContext;
+use crate::inference_queue::InferenceQueue;
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use shogiutil::UsiRequest;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+/// Largest request line, header line or body accepted. An SFEN with its moves fits easily.
+const MAX_BODY_BYTES: usize = 16 * 1024;
+/// Connections which send nothing for this long are dropped so they do not hold a worker.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_HEADERS: usize = 64;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PredictRequest {
+    /// SFEN of the position, or `startpos`.
+    pub sfen: String,
+    /// USI moves played from the position.
+    #[serde(default)]
+    pub moves: Vec<String>,
+    /// Number of moves to return. All legal moves are returned if omitted.
+    pub top: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RankedMove {
+    pub usi: String,
+    pub csa: String,
+    /// Output label as seen from the player to move.
+    pub label: i16,
+    pub probability: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PredictResponse {
+    pub moves: Vec<RankedMove>,
+}
+
+pub fn predict(queue: &InferenceQueue, request: &PredictRequest) -> Result<PredictResponse> {
+    let mut command = if request.sfen == "startpos" {
+        "position startpos".to_string()
+    } else {
+        format!("position sfen {}", request.sfen)
+    };
+    if !request.moves.is_empty() {
+        command += " moves ";
+        command += &request.moves.join(" ");
+    }
+    let (board, next_turn) = match UsiRequest::parse(&command)? {
+        UsiRequest::Position { board, next_turn } => (board, next_turn),
+        _ => bail!("Invalid position: {}", command),
+    };
+
+    let context = FeatureContext::from_position_command(&command)?;
+    let mut moves = queue.predict(&board, next_turn, &context)?;
+    if let Some(top) = request.top {
+        moves.truncate(top);
+    }
+    let moves = moves
+        .into_iter()
+        .map(|mv| RankedMove {
+            usi: mv.mv.to_usi(),
+            csa: mv.mv.to_csa(),
+            label: mv.label,
+            probability: mv.probability,
+        })
+        .collect();
+    Ok(PredictResponse { moves })
+}
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    /// `None` if the client announced a body larger than `MAX_BODY_BYTES`.
+    body: Option<Vec<u8>>,
+}
+
+fn read_line(reader: &mut BufReader<TcpStream>) -> Result<String> {
+    let mut line = String::new();
+    reader
+        .by_ref()
+        .take(MAX_BODY_BYTES as u64)
+        .read_line(&mut line)?;
+    Ok(line)
+}
+
+fn read_request(reader: &mut BufReader<TcpStream>) -> Result<HttpRequest> {
+    let request_line = read_line(reader)?;
+    let mut tokens = request_line.split_whitespace();
+    let method = tokens.next().ok_or_else(|| anyhow!("Empty request"))?;
+    let path = tokens.next().ok_or_else(|| anyhow!("No path"))?;
+
+    let mut content_length = 0;
+    for i in 0.. {
+        if i == MAX_HEADERS {
+            bail!("Too many headers");
+        }
+        let header = read_line(reader)?;
+        if header.trim().is_empty() {
+            break;
+        }
+        let mut name_value = header.splitn(2, ':');
+        if let (Some(name), Some(value)) = (name_value.next(), name_value.next()) {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse()?;
+            }
+        }
+    }
+    let body = if content_length > MAX_BODY_BYTES {
+        None
+    } else {
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+        Some(body)
+    };
+
+    Ok(HttpRequest {
+        method: method.to_string(),
+        path: path.to_string(),
+        body,
+    })
+}
+
+fn write_response(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn handle(mut stream: TcpStream, queue: &InferenceQueue) -> Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let request = read_request(&mut BufReader::new(stream.try_clone()?))?;
+    let body = match request.body {
+        Some(body) => body,
+        None => {
+            return write_response(
+                &mut stream,
+                "413 Payload Too Large",
+                "text/plain",
+                "payload too large",
+            )
+        }
+    };
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/health") => write_response(&mut stream, "200 OK", "text/plain", "ok"),
+        ("POST", "/predict") => {
+            let response = serde_json::from_slice::<PredictRequest>(&body)
+                .map_err(anyhow::Error::from)
+                .and_then(|request| predict(queue, &request));
+            match response {
+                Ok(response) => write_response(
+                    &mut stream,
+                    "200 OK",
+                    "application/json",
+                    &serde_json::to_string(&response)?,
+                ),
+                Err(e) => {
+                    let error = serde_json::json!({ "error": e.to_string() });
+                    write_response(
+                        &mut stream,
+                        "400 Bad Request",
+                        "application/json",
+                        &error.to_string(),
+                    )
+                }
+            }
+        }
+        _ => write_response(&mut stream, "404 Not Found", "text/plain", "not found"),
+    }
+}
+
+/// Serves connections on `workers` threads, each accepting and handling one connection at a
+/// time, so at most `workers` connections are served concurrently. Never returns unless
+/// accepting fails.
+pub fn serve(listener: TcpListener, queue: InferenceQueue, workers: usize) -> Result<()> {
+    if workers == 0 {
+        bail!("serve needs at least one worker");
+    }
+    let handles = (0..workers)
+        .map(|_| {
+            let listener = listener.try_clone()?;
+            let queue = queue.clone();
+            Ok(thread::spawn(move || -> Result<()> {
+                for stream in listener.incoming() {
+                    if let Err(e) = handle(stream?, &queue) {
+                        log::warn!("{}", e);
+                    }
+                }
+                Ok(())
+            }))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    for handle in handles {
+        handle
+            .join()
+            .map_err(|_| anyhow!("Server worker panicked"))??;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inference_queue::BatchConfig;
+    use crate::network::config::ModelConfig;
+    use crate::network::policy::PolicyNetwork;
+    use tch::nn::VarStore;
+    use tch::Device;
+
+    fn post(port: u16, body: &str) -> (String, String) {
+        send(port, body.len(), body)
+    }
+
+    fn send(port: u16, content_length: usize, body: &str) -> (String, String) {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        write!(
+            stream,
+            "POST /predict HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+            content_length, body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let mut parts = response.splitn(2, "\r\n\r\n");
+        let head = parts.next().unwrap().to_string();
+        let body = parts.next().unwrap().to_string();
+        (head, body)
+    }
+
+    #[test]
+    fn test_serve() {
+        let vs = VarStore::new(Device::Cpu);
+        let model = PolicyNetwork::new(&vs.root(), &ModelConfig::default());
+        let queue = InferenceQueue::spawn(model, vs, BatchConfig::default());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || serve(listener, queue, 2));
+
+        let (head, body) = post(port, r#"{"sfen": "startpos"}"#);
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        let response: PredictResponse = serde_json::from_str(&body).unwrap();
+        assert_eq!(response.moves.len(), 30);
+        for pair in response.moves.windows(2) {
+            assert!(pair[0].probability >= pair[1].probability);
+        }
+
+        let (_, body) = post(port, r#"{"sfen": "startpos", "moves": ["7g7f"], "top": 3}"#);
+        let response: PredictResponse = serde_json::from_str(&body).unwrap();
+        assert_eq!(response.moves.len(), 3);
+        assert!(response.moves.iter().all(|mv| mv.csa.starts_with('-')));
+
+        let (head, _) = post(port, r#"{"moves": ["7g7f"]}"#);
+        assert!(head.starts_with("HTTP/1.1 400 Bad Request"));
+
+        let (head, _) = send(port, 1 << 40, "");
+        assert!(head.starts_with("HTTP/1.1 413 Payload Too Large"));
+    }
+}
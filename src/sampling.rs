@@ -0,0 +1,169 @@
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
+
+/// Samples an index with probability proportional to `exp(logit / temperature)`. A temperature of
+/// zero picks the largest logit.
+pub fn sample_with_temperature<R: Rng>(logits: &[f64], temperature: f64, rng: &mut R) -> usize {
+    let (argmax, &max) = logits
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
+        .expect("No logits to sample from");
+    if temperature <= 0.0 {
+        return argmax;
+    }
+
+    let weights = logits
+        .iter()
+        .map(|&logit| ((logit - max) / temperature).exp())
+        .collect::<Vec<_>>();
+    WeightedIndex::new(&weights)
+        .map(|distribution| distribution.sample(rng))
+        .unwrap_or(argmax)
+}
+
+/// How to pick a move from the policy.
+#[derive(Debug, Copy, Clone)]
+pub struct MoveSampler {
+    /// Softmax temperature during the opening. Zero always picks the most probable move.
+    pub temperature: f64,
+    /// Sample only from the `top_k` most probable moves.
+    pub top_k: Option<usize>,
+    /// Sample only from the most probable moves whose cumulative probability reaches `top_p`.
+    pub top_p: Option<f64>,
+    /// Number of moves played with `temperature`.
+    pub opening_moves: usize,
+    /// After the opening, the temperature is multiplied by this factor every move.
+    pub temperature_decay: f64,
+    /// The temperature does not decay below this.
+    pub min_temperature: f64,
+}
+
+impl Default for MoveSampler {
+    fn default() -> Self {
+        Self {
+            temperature: 0.0,
+            top_k: None,
+            top_p: None,
+            opening_moves: 0,
+            temperature_decay: 1.0,
+            min_temperature: 0.0,
+        }
+    }
+}
+
+impl MoveSampler {
+    /// Temperature of the `move_count`-th move, counted from 0.
+    pub fn temperature(&self, move_count: usize) -> f64 {
+        if move_count < self.opening_moves {
+            return self.temperature;
+        }
+        let decayed_moves = (move_count - self.opening_moves) as i32;
+        let temperature = self.temperature * self.temperature_decay.powi(decayed_moves);
+        if temperature < self.min_temperature {
+            self.min_temperature
+        } else {
+            temperature
+        }
+    }
+
+    /// Picks an index of `logits`, which must be sorted in descending order.
+    pub fn sample<R: Rng>(&self, logits: &[f64], move_count: usize, rng: &mut R) -> usize {
+        let temperature = self.temperature(move_count);
+        if temperature <= 0.0 {
+            return 0;
+        }
+
+        let mut candidates = match self.top_k {
+            Some(top_k) => &logits[..top_k.max(1).min(logits.len())],
+            None => logits,
+        };
+        if let Some(top_p) = self.top_p {
+            let max = candidates[0];
+            let weights = candidates
+                .iter()
+                .map(|&logit| ((logit - max) / temperature).exp())
+                .collect::<Vec<_>>();
+            let sum: f64 = weights.iter().sum();
+            let mut cumulative = 0.0;
+            let mut size = 0;
+            while size < weights.len() && cumulative < top_p * sum {
+                cumulative += weights[size];
+                size += 1;
+            }
+            candidates = &candidates[..size.max(1)];
+        }
+        sample_with_temperature(candidates, temperature, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_sample_with_temperature() {
+        let mut rng = StdRng::seed_from_u64(717);
+        let logits = [1.0, 3.0, 2.0];
+        assert_eq!(sample_with_temperature(&logits, 0.0, &mut rng), 1);
+
+        let mut counts = [0; 3];
+        for _ in 0..1000 {
+            counts[sample_with_temperature(&logits, 1.0, &mut rng)] += 1;
+        }
+        assert!(counts[1] > counts[2] && counts[2] > counts[0]);
+
+        let logits = [0.0, 100.0];
+        for _ in 0..100 {
+            assert_eq!(sample_with_temperature(&logits, 1.0, &mut rng), 1);
+        }
+    }
+
+    #[test]
+    fn test_temperature_decay() {
+        let sampler = MoveSampler {
+            temperature: 1.0,
+            opening_moves: 10,
+            temperature_decay: 0.5,
+            min_temperature: 0.2,
+            ..Default::default()
+        };
+        assert_eq!(sampler.temperature(0), 1.0);
+        assert_eq!(sampler.temperature(9), 1.0);
+        assert_eq!(sampler.temperature(10), 1.0);
+        assert_eq!(sampler.temperature(11), 0.5);
+        assert_eq!(sampler.temperature(12), 0.25);
+        assert_eq!(sampler.temperature(13), 0.2);
+    }
+
+    #[test]
+    fn test_top_k_and_top_p() {
+        let mut rng = StdRng::seed_from_u64(717);
+        let logits = [2.0, 1.9, 1.8, -5.0];
+
+        let sampler = MoveSampler {
+            temperature: 1.0,
+            top_k: Some(2),
+            ..Default::default()
+        };
+        for _ in 0..100 {
+            assert!(sampler.sample(&logits, 0, &mut rng) < 2);
+        }
+
+        let sampler = MoveSampler {
+            temperature: 1.0,
+            top_p: Some(0.5),
+            ..Default::default()
+        };
+        let mut counts = [0; 4];
+        for _ in 0..100 {
+            counts[sampler.sample(&logits, 0, &mut rng)] += 1;
+        }
+        assert!(counts[0] > 0 && counts[1] > 0);
+        assert_eq!(counts[2] + counts[3], 0);
+
+        assert_eq!(MoveSampler::default().sample(&logits, 0, &mut rng), 0);
+    }
+}
@@ -0,0 +1,250 @@
+//! Writer of TensorBoard event files.
+//!
+//! An event file is a sequence of TFRecords, each holding a serialized `tensorflow.Event`
+//! protobuf. Only the handful of fields TensorBoard needs for scalars, histograms and text are
+//! encoded here, by hand, to avoid depending on TensorFlow or a protobuf compiler.
+use anyhow::Result;
+use std::fs::{create_dir_all, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const HISTOGRAM_BUCKETS: usize = 30;
+
+pub struct SummaryWriter<W: Write> {
+    writer: W,
+}
+
+impl SummaryWriter<BufWriter<File>> {
+    /// Creates `events.out.tfevents.<timestamp>.<hostname>` in `log_dir`.
+    pub fn create<P: AsRef<Path>>(log_dir: P) -> Result<Self> {
+        create_dir_all(&log_dir)?;
+        let hostname = std::env::var("HOSTNAME").unwrap_or_else(|_| "localhost".to_string());
+        let filename = format!("events.out.tfevents.{}.{}", wall_time() as u64, hostname);
+        let file = File::create(log_dir.as_ref().join(filename))?;
+        SummaryWriter::new(BufWriter::new(file))
+    }
+}
+
+impl<W: Write> SummaryWriter<W> {
+    pub fn new(writer: W) -> Result<Self> {
+        let mut writer = Self { writer };
+        let mut event = event_header(0);
+        write_bytes_field(&mut event, 3, b"brain.Event:2");
+        writer.write_record(&event)?;
+        Ok(writer)
+    }
+
+    pub fn add_scalar(&mut self, tag: &str, value: f64, step: usize) -> Result<()> {
+        let mut summary_value = vec![];
+        write_bytes_field(&mut summary_value, 1, tag.as_bytes());
+        write_key(&mut summary_value, 2, WIRE_FIXED32);
+        summary_value.extend(&(value as f32).to_le_bytes());
+        self.write_summary(&summary_value, step)
+    }
+
+    pub fn add_histogram(&mut self, tag: &str, values: &[f64], step: usize) -> Result<()> {
+        if values.is_empty() {
+            return Ok(());
+        }
+        let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+        let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        let width = (max - min) / HISTOGRAM_BUCKETS as f64;
+        let mut buckets = vec![0.0; HISTOGRAM_BUCKETS];
+        for &value in values.iter() {
+            let i = if width > 0.0 {
+                ((value - min) / width) as usize
+            } else {
+                0
+            };
+            buckets[i.min(HISTOGRAM_BUCKETS - 1)] += 1.0;
+        }
+        let bucket_limits = (1..=HISTOGRAM_BUCKETS)
+            .map(|i| {
+                if i == HISTOGRAM_BUCKETS {
+                    max
+                } else {
+                    min + width * i as f64
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut histogram = vec![];
+        write_double_field(&mut histogram, 1, min);
+        write_double_field(&mut histogram, 2, max);
+        write_double_field(&mut histogram, 3, values.len() as f64);
+        write_double_field(&mut histogram, 4, values.iter().sum());
+        write_double_field(&mut histogram, 5, values.iter().map(|v| v * v).sum());
+        write_packed_doubles(&mut histogram, 6, &bucket_limits);
+        write_packed_doubles(&mut histogram, 7, &buckets);
+
+        let mut summary_value = vec![];
+        write_bytes_field(&mut summary_value, 1, tag.as_bytes());
+        write_bytes_field(&mut summary_value, 5, &histogram);
+        self.write_summary(&summary_value, step)
+    }
+
+    /// Text is rendered as Markdown by TensorBoard.
+    pub fn add_text(&mut self, tag: &str, text: &str, step: usize) -> Result<()> {
+        let mut tensor = vec![];
+        write_key(&mut tensor, 1, WIRE_VARINT);
+        write_varint(&mut tensor, DT_STRING);
+        write_bytes_field(&mut tensor, 2, &[]);
+        write_bytes_field(&mut tensor, 8, text.as_bytes());
+
+        let mut plugin_data = vec![];
+        write_bytes_field(&mut plugin_data, 1, b"text");
+        let mut metadata = vec![];
+        write_bytes_field(&mut metadata, 1, &plugin_data);
+
+        let mut summary_value = vec![];
+        write_bytes_field(&mut summary_value, 1, tag.as_bytes());
+        write_bytes_field(&mut summary_value, 8, &tensor);
+        write_bytes_field(&mut summary_value, 9, &metadata);
+        self.write_summary(&summary_value, step)
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn write_summary(&mut self, summary_value: &[u8], step: usize) -> Result<()> {
+        let mut summary = vec![];
+        write_bytes_field(&mut summary, 1, summary_value);
+        let mut event = event_header(step);
+        write_bytes_field(&mut event, 5, &summary);
+        self.write_record(&event)
+    }
+
+    fn write_record(&mut self, data: &[u8]) -> Result<()> {
+        let length = (data.len() as u64).to_le_bytes();
+        self.writer.write_all(&length)?;
+        self.writer
+            .write_all(&masked_crc32c(&length).to_le_bytes())?;
+        self.writer.write_all(data)?;
+        self.writer.write_all(&masked_crc32c(data).to_le_bytes())?;
+        Ok(())
+    }
+}
+
+const WIRE_VARINT: u64 = 0;
+const WIRE_FIXED64: u64 = 1;
+const WIRE_LENGTH_DELIMITED: u64 = 2;
+const WIRE_FIXED32: u64 = 5;
+const DT_STRING: u64 = 7;
+
+fn wall_time() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+fn event_header(step: usize) -> Vec<u8> {
+    let mut event = vec![];
+    write_double_field(&mut event, 1, wall_time());
+    write_key(&mut event, 2, WIRE_VARINT);
+    write_varint(&mut event, step as u64);
+    event
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn write_key(buf: &mut Vec<u8>, field: u64, wire_type: u64) {
+    write_varint(buf, (field << 3) | wire_type);
+}
+
+fn write_bytes_field(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    write_key(buf, field, WIRE_LENGTH_DELIMITED);
+    write_varint(buf, bytes.len() as u64);
+    buf.extend(bytes);
+}
+
+fn write_double_field(buf: &mut Vec<u8>, field: u64, value: f64) {
+    write_key(buf, field, WIRE_FIXED64);
+    buf.extend(&value.to_le_bytes());
+}
+
+fn write_packed_doubles(buf: &mut Vec<u8>, field: u64, values: &[f64]) {
+    let bytes = values
+        .iter()
+        .flat_map(|v| v.to_le_bytes().to_vec())
+        .collect::<Vec<_>>();
+    write_bytes_field(buf, field, &bytes);
+}
+
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0x82F6_3B78 & mask);
+        }
+    }
+    !crc
+}
+
+fn masked_crc32c(data: &[u8]) -> u32 {
+    let crc = crc32c(data);
+    crc.rotate_right(15).wrapping_add(0xA282_EAD8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32c() {
+        assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+        assert_eq!(crc32c(b""), 0);
+    }
+
+    #[test]
+    fn test_varint() {
+        let mut buf = vec![];
+        write_varint(&mut buf, 1);
+        write_varint(&mut buf, 300);
+        assert_eq!(buf, vec![0x01, 0xAC, 0x02]);
+    }
+
+    #[test]
+    fn test_record_framing() {
+        let mut buf = vec![];
+        {
+            let mut writer = SummaryWriter::new(&mut buf).unwrap();
+            writer.add_scalar("train/loss", 7.68, 100).unwrap();
+            writer
+                .add_histogram("l1/weight", &[0.0, 0.5, 1.0], 100)
+                .unwrap();
+            writer.add_text("predictions", "`Up 7f`", 100).unwrap();
+        }
+
+        let mut records = 0;
+        let mut pos = 0;
+        while pos < buf.len() {
+            let mut length = [0; 8];
+            length.copy_from_slice(&buf[pos..(pos + 8)]);
+            let mut length_crc = [0; 4];
+            length_crc.copy_from_slice(&buf[(pos + 8)..(pos + 12)]);
+            assert_eq!(u32::from_le_bytes(length_crc), masked_crc32c(&length));
+
+            let length = u64::from_le_bytes(length) as usize;
+            let data = &buf[(pos + 12)..(pos + 12 + length)];
+            let mut data_crc = [0; 4];
+            data_crc.copy_from_slice(&buf[(pos + 12 + length)..(pos + 16 + length)]);
+            assert_eq!(u32::from_le_bytes(data_crc), masked_crc32c(data));
+
+            pos += 16 + length;
+            records += 1;
+        }
+        assert_eq!(records, 4);
+    }
+}
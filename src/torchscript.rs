@@ -0,0 +1,177 @@
+//! Policy networks saved as TorchScript modules, which can be used without the Rust definition of
+//! `PolicyNetwork`. tch cannot create TorchScript modules, so `scripts/export_torchscript.py`
+//! converts a `VarStore` checkpoint to one.
+//!
+//! Any module following this contract can replace `PolicyNetwork`:
+//!
+//! - Input: `float32` tensor of shape `[N, input_channels, 9, 9]` built by
+//!   `InputFeatures::board_features` of the `ModelConfig` saved next to the module, `V1` without
+//!   history if there is none. When White is to move the board is rotated by 180 degrees first, so the
+//!   player to move is always Black. Plane `(i, j)` is the square of rank `i + 1` and file
+//!   `9 - j`. The first 104 planes are, for the player to move and then the opponent, 14 planes of
+//!   pieces in the order of `PIECE_NAMES` (`FU`, `KY`, ..., `OU`, `TO`, ..., `RY`) and the pieces
+//!   in hand as 18 pawn, 4 lance, 4 knight, 4 silver, 4 gold, 2 bishop and 2 rook planes, where
+//!   the first `n` planes of a piece are ones when `n` of them are in hand. See `features` for the
+//!   planes of later feature sets and of the history.
+//! - Output: `float32` logits of shape `[N, 2187]`. Label `81 * plane + 9 * i + j` is a move to
+//!   `(i, j)`, where planes 0 to 19 are the `MoveDirection`s in the order of `to_byte` and planes
+//!   20 to 26 are drops of `FU`, `KY`, `KE`, `GI`, `KI`, `KA` and `HI`. See `make_output_label`.
+use anyhow::Result;
+use std::fmt::{self, Debug, Formatter};
+use std::path::Path;
+use tch::nn::Module;
+use tch::{CModule, Device, Tensor};
+
+pub struct TorchScriptPolicy {
+    module: CModule,
+}
+
+impl TorchScriptPolicy {
+    pub fn load<P: AsRef<Path>>(path: P, device: Device) -> Result<Self> {
+        let module = CModule::load_on_device(path, device)?;
+        Ok(Self { module })
+    }
+}
+
+impl Debug for TorchScriptPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "TorchScriptPolicy")
+    }
+}
+
+impl Module for TorchScriptPolicy {
+    fn forward(&self, xs: &Tensor) -> Tensor {
+        self.module
+            .forward_ts(&[xs])
+            .expect("Failed to run the TorchScript module")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::FeatureContext;
+    use crate::game::Game;
+    use crate::inference::{predict, MoveProbability};
+    use crate::network::config::ModelConfig;
+    use crate::network::policy::PolicyNetwork;
+    use crate::player::PolicyPlayer;
+    use crate::sampling::MoveSampler;
+    use crate::usi::UsiPlayer;
+    use shogiutil::UsiRequest;
+    use std::collections::BTreeMap;
+    use std::fs::create_dir_all;
+    use std::process::Command;
+    use tch::nn::VarStore;
+
+    fn position(command: &str) -> UsiRequest {
+        let request = UsiRequest::parse(command).unwrap();
+        assert!(matches!(request, UsiRequest::Position { .. }));
+        request
+    }
+
+    fn probabilities(moves: Vec<MoveProbability>) -> BTreeMap<i16, f64> {
+        moves
+            .into_iter()
+            .map(|mv| (mv.label, mv.probability))
+            .collect()
+    }
+
+    /// Exports a `PolicyNetwork` with `scripts/export_torchscript.py` and checks that the module
+    /// loaded by `TorchScriptPolicy` plays the same policy, so that the script finds every
+    /// variable under the name tch gave it. Needs `python3` with PyTorch.
+    #[test]
+    fn test_export_round_trip() {
+        let dir = std::env::temp_dir().join(format!(
+            "torchscript_test_{}_{:016x}",
+            std::process::id(),
+            rand::random::<u64>()
+        ));
+        create_dir_all(&dir).unwrap();
+        let weights = dir.join("model.ot");
+        let module = dir.join("model.pt");
+
+        let config = ModelConfig {
+            channels: 8,
+            layers: 3,
+            ..ModelConfig::default()
+        };
+        let vs = VarStore::new(Device::Cpu);
+        let model = PolicyNetwork::new(&vs.root(), &config);
+        vs.save(&weights).unwrap();
+        config.save_for(&weights).unwrap();
+
+        let status = Command::new("python3")
+            .arg(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/scripts/export_torchscript.py"
+            ))
+            .arg(&weights)
+            .arg(&module)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let policy = TorchScriptPolicy::load(&module, Device::Cpu).unwrap();
+        let input_features = ModelConfig::load_for(&module).unwrap().input_features();
+        assert_eq!(input_features, config.input_features());
+        for command in &[
+            "position startpos",
+            "position startpos moves 7g7f 3c3d 8h2b+ 3a2b B*4e",
+        ] {
+            let (board, next_turn) = match position(command) {
+                UsiRequest::Position { board, next_turn } => (board, next_turn),
+                _ => unreachable!(),
+            };
+            let context = FeatureContext::from_position_command(command).unwrap();
+            let expected = probabilities(predict(
+                &model,
+                Device::Cpu,
+                input_features,
+                &board,
+                next_turn,
+                &context,
+            ));
+            let actual = probabilities(predict(
+                &policy,
+                Device::Cpu,
+                input_features,
+                &board,
+                next_turn,
+                &context,
+            ));
+            assert_eq!(
+                expected.keys().collect::<Vec<_>>(),
+                actual.keys().collect::<Vec<_>>()
+            );
+            for (label, probability) in expected.iter() {
+                assert!(
+                    (probability - actual[label]).abs() < 1e-5,
+                    "label {}",
+                    label
+                );
+            }
+        }
+
+        let game = Game::from_opening("startpos").unwrap();
+        let mut players = vec![
+            PolicyPlayer::new(model, vs, MoveSampler::default(), 0),
+            PolicyPlayer::from_module(
+                Box::new(policy),
+                Device::Cpu,
+                input_features,
+                MoveSampler::default(),
+                0,
+            ),
+        ];
+        let moves = players
+            .iter_mut()
+            .map(|player| {
+                player.play(position("position startpos"));
+                let responses = player.play(UsiRequest::Go);
+                game.find_response_move(&responses).unwrap().to_csa()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(moves[0], moves[1]);
+    }
+}
@@ -1,19 +1,187 @@
-use anyhow::Result;
-use shogiutil::{SfenBoard, SfenMove, UsiRequest, UsiResponse};
-use std::io::{stdin, Read};
+use crate::constants::HAND_PIECE_USI_NAMES;
+use anyhow::{anyhow, Result};
+use shogiutil::{Move, Square, UsiRequest, UsiResponse};
+use std::fmt::{self, Display, Formatter};
+use std::io::stdin;
+use std::str::FromStr;
+
+/// Result of `gameover`, which `UsiRequest` does not parse.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum GameOver {
+    Win,
+    Lose,
+    Draw,
+}
+
+impl FromStr for GameOver {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "win" => Ok(GameOver::Win),
+            "lose" => Ok(GameOver::Lose),
+            "draw" => Ok(GameOver::Draw),
+            _ => Err(anyhow!("Unknown game result: {}", s)),
+        }
+    }
+}
+
+impl Display for GameOver {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let result = match self {
+            GameOver::Win => "win",
+            GameOver::Lose => "lose",
+            GameOver::Draw => "draw",
+        };
+        write!(f, "{}", result)
+    }
+}
+
+/// Time arguments of `go` in milliseconds, which `UsiRequest` does not parse.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct GoTime {
+    pub btime: u64,
+    pub wtime: u64,
+    pub byoyomi: u64,
+}
+
+impl GoTime {
+    /// Parses a command such as `go btime 60000 wtime 50000 byoyomi 10000`.
+    pub fn parse(command: &str) -> Self {
+        let mut time = GoTime::default();
+        let tokens = command.split_whitespace().collect::<Vec<_>>();
+        for pair in tokens.windows(2) {
+            let value = match pair[1].parse() {
+                Ok(value) => value,
+                Err(_) => continue,
+            };
+            match pair[0] {
+                "btime" => time.btime = value,
+                "wtime" => time.wtime = value,
+                "byoyomi" => time.byoyomi = value,
+                _ => {}
+            }
+        }
+        time
+    }
+}
 
 pub trait UsiPlayer {
+    /// Answers `request`. No move in the answer to `go` resigns.
     fn play(&mut self, request: UsiRequest) -> Vec<UsiResponse>;
+    fn game_over(&mut self, _result: GameOver) {}
+    /// Called with every `position` command before `play` gets the board it leads to, for players
+    /// which need the moves played before.
+    fn position_command(&mut self, _command: &str) {}
+    /// Called with the time left before every `go`.
+    fn go_time(&mut self, _time: GoTime) {}
+    /// Whether to answer the next `go` with `bestmove win`, declaring a win by entering king.
+    fn declare_win(&mut self) -> bool {
+        false
+    }
     fn usi_play(&mut self) -> Result<()> {
         loop {
             let mut input = String::new();
             stdin().read_line(&mut input)?;
             log::info!("input: {}", input);
 
-            let responses = self.play(UsiRequest::parse(input.trim())?);
+            if let Some(result) = input.trim().strip_prefix("gameover ") {
+                self.game_over(result.parse()?);
+                continue;
+            }
+            if input.trim().starts_with("position ") {
+                self.position_command(input.trim());
+            }
+            let request = UsiRequest::parse(input.trim())?;
+            let quit = matches!(request, UsiRequest::Quit);
+            let go = matches!(request, UsiRequest::Go);
+            if go {
+                self.go_time(GoTime::parse(input.trim()));
+                if self.declare_win() {
+                    println!("bestmove win");
+                    continue;
+                }
+            }
+            let responses = self.play(request);
+            if go && responses.is_empty() {
+                println!("bestmove resign");
+            }
             for response in responses {
                 println!("{}", response.to_string());
             }
+            if quit {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// USI notation of a move, e.g. `7g7f`, `8h2b+` or `P*5e`.
+pub fn format_usi_move(mv: &Move, promoted: bool) -> String {
+    let to = format_usi_square(&mv.to);
+    match mv.from.as_ref() {
+        Some(from) => {
+            let promotion = if promoted { "+" } else { "" };
+            format!("{}{}{}", format_usi_square(from), to, promotion)
+        }
+        None => format!("{}*{}", HAND_PIECE_USI_NAMES[mv.piece.to_usize()], to),
+    }
+}
+
+fn format_usi_square(square: &Square) -> String {
+    format!("{}{}", square.file, (b'a' + square.rank as u8 - 1) as char)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shogiutil::{Color, Piece};
+
+    #[test]
+    fn test_go_time() {
+        assert_eq!(
+            GoTime::parse("go btime 60000 wtime 50000 byoyomi 10000"),
+            GoTime {
+                btime: 60000,
+                wtime: 50000,
+                byoyomi: 10000
+            }
+        );
+        assert_eq!(GoTime::parse("go infinite"), GoTime::default());
+    }
+
+    #[test]
+    fn test_game_over() {
+        for &result in [GameOver::Win, GameOver::Lose, GameOver::Draw].iter() {
+            assert_eq!(result.to_string().parse::<GameOver>().unwrap(), result);
         }
+        assert!("abort".parse::<GameOver>().is_err());
+    }
+
+    #[test]
+    fn test_format_usi_move() {
+        let mv = Move {
+            from: Some(Square { file: 7, rank: 7 }),
+            to: Square { file: 7, rank: 6 },
+            piece: Piece::Pawn,
+            color: Color::Black,
+        };
+        assert_eq!(format_usi_move(&mv, false), "7g7f");
+
+        let mv = Move {
+            from: Some(Square { file: 8, rank: 8 }),
+            to: Square { file: 2, rank: 2 },
+            piece: Piece::Bishop,
+            color: Color::Black,
+        };
+        assert_eq!(format_usi_move(&mv, true), "8h2b+");
+
+        let mv = Move {
+            from: None,
+            to: Square { file: 5, rank: 5 },
+            piece: Piece::Pawn,
+            color: Color::White,
+        };
+        assert_eq!(format_usi_move(&mv, false), "P*5e");
     }
 }
@@ -0,0 +1,102 @@
+use crate::usi::GameOver;
+use anyhow::{anyhow, bail, Result};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+/// A USI engine running as a child process.
+pub struct UsiEngine {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    pub name: String,
+}
+
+impl UsiEngine {
+    /// Starts `command`, split on whitespace, and waits for `usiok`. The stderr of the engine is
+    /// discarded.
+    pub fn spawn(command: &str) -> Result<Self> {
+        let mut args = command.split_whitespace();
+        let program = args.next().ok_or_else(|| anyhow!("Empty engine command"))?;
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut engine = Self {
+            child,
+            stdin,
+            stdout,
+            name: program.to_string(),
+        };
+
+        engine.send("usi")?;
+        for line in engine.read_until("usiok")? {
+            if let Some(name) = line.strip_prefix("id name ") {
+                engine.name = name.to_string();
+            }
+        }
+        Ok(engine)
+    }
+
+    pub fn send(&mut self, command: &str) -> Result<()> {
+        writeln!(self.stdin, "{}", command)?;
+        self.stdin.flush()?;
+        Ok(())
+    }
+
+    /// Reads lines until one starting with `prefix`, and returns all of them.
+    fn read_until(&mut self, prefix: &str) -> Result<Vec<String>> {
+        let mut lines = vec![];
+        loop {
+            let mut line = String::new();
+            if self.stdout.read_line(&mut line)? == 0 {
+                bail!("{} exited while waiting for {}", self.name, prefix);
+            }
+            let line = line.trim().to_string();
+            let done = line.starts_with(prefix);
+            lines.push(line);
+            if done {
+                return Ok(lines);
+            }
+        }
+    }
+
+    pub fn is_ready(&mut self) -> Result<()> {
+        self.send("isready")?;
+        self.read_until("readyok")?;
+        Ok(())
+    }
+
+    pub fn new_game(&mut self) -> Result<()> {
+        self.send("usinewgame")
+    }
+
+    /// Tells the engine how the game ended for it.
+    pub fn game_over(&mut self, result: GameOver) -> Result<()> {
+        self.send(&format!("gameover {}", result))
+    }
+
+    /// Sends `position` and `go`, and returns the move of `bestmove`, which may be `resign` or
+    /// `win`.
+    pub fn go(&mut self, position: &str, byoyomi_millis: u64) -> Result<String> {
+        self.send(position)?;
+        self.send(&format!("go btime 0 wtime 0 byoyomi {}", byoyomi_millis))?;
+        let lines = self.read_until("bestmove")?;
+        let bestmove = lines.last().unwrap();
+        bestmove
+            .split_whitespace()
+            .nth(1)
+            .map(|mv| mv.to_string())
+            .ok_or_else(|| anyhow!("Invalid bestmove: {}", bestmove))
+    }
+}
+
+impl Drop for UsiEngine {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        let _ = self.child.wait();
+    }
+}
@@ -1,5 +1,6 @@
 pub mod board_packer;
 pub mod make_output_label;
+pub mod rating;
 
 use anyhow::Result;
 use tch::kind::Kind::Double;
@@ -19,6 +20,24 @@ impl Accuracy for Tensor {
     }
 }
 
+/// Negative log likelihood averaged with per-sample `weight`.
+pub fn weighted_nll_loss(y: &Tensor, target: &Tensor, weight: &Tensor) -> Tensor {
+    let nll = -y
+        .log_softmax(-1, Double)
+        .gather(1, &target.view((-1, 1)), false)
+        .view(-1);
+    let weight = weight.totype(Double);
+    (nll * &weight).sum(Double) / weight.sum(Double)
+}
+
+/// Cross entropy to the distributions `target` of shape `[N, labels]`, averaged with per-sample
+/// `weight`.
+pub fn weighted_cross_entropy(y: &Tensor, target: &Tensor, weight: &Tensor) -> Tensor {
+    let weight = weight.totype(Double);
+    let cross_entropy = y.log_softmax(-1, Double) * target.totype(Double) * weight.view((-1, 1));
+    -cross_entropy.sum(Double) / weight.sum(Double)
+}
+
 pub trait CheckPoint {
     fn load_if_exists(&mut self, filepath: &str) -> Result<()>;
 }
@@ -32,3 +51,56 @@ impl CheckPoint for VarStore {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tch::kind::Kind::Int64;
+
+    #[test]
+    fn test_weighted_nll_loss() {
+        let y = Tensor::of_slice(&[1.0f32, 2.0, 3.0, 0.5, 0.0, -1.0]).view((2, 3));
+        let target = Tensor::of_slice(&[2i64, 1]).totype(Int64);
+        let nll = f64::from(&y.log_softmax(-1, Double).nll_loss(&target));
+        let uniform = f64::from(&weighted_nll_loss(
+            &y,
+            &target,
+            &Tensor::of_slice(&[3.0f32, 3.0]),
+        ));
+        assert!((uniform - nll).abs() < 1e-6);
+
+        // A sample with zero weight does not contribute.
+        let first = f64::from(
+            &y.narrow(0, 0, 1)
+                .log_softmax(-1, Double)
+                .nll_loss(&target.narrow(0, 0, 1)),
+        );
+        let weighted = f64::from(&weighted_nll_loss(
+            &y,
+            &target,
+            &Tensor::of_slice(&[1.0f32, 0.0]),
+        ));
+        assert!((weighted - first).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_weighted_cross_entropy() {
+        let y = Tensor::of_slice(&[1.0f32, 2.0, 3.0, 0.5, 0.0, -1.0]).view((2, 3));
+        let target = Tensor::of_slice(&[2i64, 1]);
+        let nll = f64::from(&y.log_softmax(-1, Double).nll_loss(&target));
+        let one_hot = Tensor::of_slice(&[0.0f32, 0.0, 1.0, 0.0, 1.0, 0.0]).view((2, 3));
+        let weight = Tensor::of_slice(&[1.0f32, 1.0]);
+        let cross_entropy = f64::from(&weighted_cross_entropy(&y, &one_hot, &weight));
+        assert!((cross_entropy - nll).abs() < 1e-6);
+
+        // Half of the target on each of the first two labels of the first sample.
+        let soft = Tensor::of_slice(&[0.5f32, 0.5, 0.0]).view((1, 3));
+        let log_p = Vec::<f64>::from(&y.narrow(0, 0, 1).log_softmax(-1, Double).view(-1));
+        let cross_entropy = f64::from(&weighted_cross_entropy(
+            &y.narrow(0, 0, 1),
+            &soft,
+            &Tensor::of_slice(&[2.0f32]),
+        ));
+        assert!((cross_entropy + 0.5 * (log_p[0] + log_p[1])).abs() < 1e-6);
+    }
+}
@@ -1,13 +1,23 @@
-use crate::constants::INPUT_CHANNELS;
 use shogiutil::{Bitboard, Board, Piece};
 
+/// Pieces = 14
+/// Pieces in hand
+///     - Pawn: 18
+///     - Lance: 4
+///     - Knight: 4
+///     - Silver: 4
+///     - Gold: 4
+///     - Bishop: 2
+///     - Rook: 2
+pub const BOARD_PLANES: usize = 104;
+
 pub trait BoardPacker {
-    fn encode(&self) -> [u128; INPUT_CHANNELS];
+    fn encode(&self) -> [u128; BOARD_PLANES];
 }
 
 impl BoardPacker for Board {
-    fn encode(&self) -> [u128; 104] {
-        let mut features = [0; INPUT_CHANNELS];
+    fn encode(&self) -> [u128; BOARD_PLANES] {
+        let mut features = [0; BOARD_PLANES];
         let mut pos = 0;
         for color_id in 0..2 {
             for piece_id in 1..15 {
@@ -27,7 +37,7 @@ impl BoardPacker for Board {
                 }
             }
         }
-        assert_eq!(pos, INPUT_CHANNELS);
+        assert_eq!(pos, BOARD_PLANES);
         features
     }
 }
@@ -42,27 +52,38 @@ const HANDY_PIECES: [Piece; 7] = [
     Piece::Rook,
 ];
 
+const SQUARES: usize = 9 * 9;
+const FULL_PLANE: u128 = (1 << SQUARES) - 1;
+
 pub trait ToFlatVec {
     fn to_flat_vec(&self) -> Vec<f32>;
+
+    /// Writes the same values as `to_flat_vec` to `out`, whose length must be the same, without
+    /// allocating.
+    fn write_flat(&self, out: &mut [f32]);
 }
 
 impl ToFlatVec for [u128] {
     fn to_flat_vec(&self) -> Vec<f32> {
-        let mut features = vec![vec![vec![0.0f32; 9]; 9]; self.len()];
-        for (channel, &board) in self.iter().enumerate() {
-            for i in 0..9 {
-                for j in 0..9 {
-                    let pos = i * 9 + j;
-                    if board & (1 << pos) != 0 {
-                        features[channel][i][j] = 1.0;
+        let mut features = vec![0.0f32; self.len() * SQUARES];
+        self.write_flat(&mut features);
+        features
+    }
+
+    fn write_flat(&self, out: &mut [f32]) {
+        assert_eq!(out.len(), self.len() * SQUARES);
+        for (&board, plane) in self.iter().zip(out.chunks_exact_mut(SQUARES)) {
+            // Most planes are empty, and the planes of pieces in hand are empty or full.
+            match board & FULL_PLANE {
+                0 => plane.iter_mut().for_each(|x| *x = 0.0),
+                FULL_PLANE => plane.iter_mut().for_each(|x| *x = 1.0),
+                board => {
+                    for (pos, x) in plane.iter_mut().enumerate() {
+                        *x = (board >> pos & 1) as f32;
                     }
                 }
             }
         }
-        features
-            .into_iter()
-            .flat_map(|board| board.into_iter().flat_map(|row| row))
-            .collect()
     }
 }
 
@@ -102,4 +123,17 @@ mod tests {
             ]
         )
     }
+
+    #[test]
+    fn test_write_flat() {
+        let v: Vec<u128> = vec![0, Bitboard::full().0, 0b101 << 40];
+        let mut out = vec![0.5; 3 * 81];
+        v.write_flat(&mut out);
+        assert!(out[..81].iter().all(|&x| x == 0.0));
+        assert!(out[81..162].iter().all(|&x| x == 1.0));
+        assert_eq!(out[162 + 40], 1.0);
+        assert_eq!(out[162 + 41], 0.0);
+        assert_eq!(out[162 + 42], 1.0);
+        assert_eq!(out[162..].iter().sum::<f32>(), 2.0);
+    }
 }
@@ -1,6 +1,6 @@
-use crate::constants::MOVE_DIRECTIONS;
+use crate::constants::{MOVE_DIRECTIONS, PIECE_NAMES};
 use crate::model::MoveDirection;
-use shogiutil::{Piece, Square};
+use shogiutil::{Board, Piece, Square};
 use std::cmp::{max, min};
 
 pub fn make_output_label(from: &Option<Square>, to: &Square, piece: Piece, promoted: bool) -> i16 {
@@ -35,6 +35,30 @@ pub fn make_output_label(from: &Option<Square>, to: &Square, piece: Piece, promo
     9 * 9 * direction + move_to
 }
 
+/// Labels of all legal moves of the player to move. `board` must already be rotated so that the
+/// player to move is Black.
+pub fn legal_move_labels(board: &Board) -> Vec<i16> {
+    board
+        .generate_legal_moves()
+        .into_iter()
+        .map(|mv| make_output_label(&mv.mv.from, &mv.mv.to, mv.mv.piece, mv.promoted))
+        .collect()
+}
+
+/// Human readable form of a label such as `UpPromote->(2,3)` or `KE*->(4,1)`, where the square is
+/// `Square::to_pos()` of the destination.
+pub fn describe_label(label: i16) -> String {
+    let direction = label as usize / (9 * 9);
+    let move_to = label as usize % (9 * 9);
+    let (i, j) = (move_to / 9, move_to % 9);
+    if direction < MOVE_DIRECTIONS.len() {
+        format!("{:?}->({},{})", MOVE_DIRECTIONS[direction], i, j)
+    } else {
+        let piece = direction - MOVE_DIRECTIONS.len() + 1;
+        format!("{}*->({},{})", PIECE_NAMES[piece], i, j)
+    }
+}
+
 const MOVE_DIRECTIONS_MAP: [[Option<MoveDirection>; 3]; 3] = [
     [
         Some(MoveDirection::UpLeft),
@@ -51,7 +75,7 @@ const MOVE_DIRECTIONS_MAP: [[Option<MoveDirection>; 3]; 3] = [
 
 #[cfg(test)]
 mod tests {
-    use crate::util::make_output_label::make_output_label;
+    use crate::util::make_output_label::{describe_label, make_output_label};
     use shogiutil::{Piece, Square};
 
     #[test]
@@ -75,4 +99,10 @@ mod tests {
         let label = make_output_label(&None, &Square { file: 2, rank: 4 }, Piece::Knight, false);
         assert_eq!(9 * 9 * 22 + 34, label);
     }
+
+    #[test]
+    fn test_describe_label() {
+        assert_eq!(describe_label(9 * 9 * 16 + 33), "DownLeftPromote->(3,6)");
+        assert_eq!(describe_label(9 * 9 * 22 + 34), "KE*->(3,7)");
+    }
 }
@@ -0,0 +1,69 @@
+use regex::Regex;
+
+/// Ratings written by Floodgate as `'black_rate:<player>:<rate>` comments.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Ratings {
+    pub black: Option<f64>,
+    pub white: Option<f64>,
+}
+
+pub fn parse_ratings(csa: &str) -> Ratings {
+    let rate_pattern = Regex::new("^'(black|white)_rate:.*:(.*)").unwrap();
+    let mut ratings = Ratings::default();
+    for line in csa.split("\n") {
+        if let Some(caps) = rate_pattern.captures(line) {
+            let rate = caps[2].trim().parse::<f64>().ok();
+            if &caps[1] == "black" {
+                ratings.black = rate;
+            } else {
+                ratings.white = rate;
+            }
+        }
+    }
+    ratings
+}
+
+/// Sample weight `2^((rate - base) / scale)`, i.e. a player `scale` points stronger than `base`
+/// counts twice as much. Positions without a rating get the weight of `base`.
+#[derive(Debug, Copy, Clone)]
+pub struct RatingWeight {
+    pub base: f64,
+    pub scale: f64,
+}
+
+impl RatingWeight {
+    pub fn weight(&self, rate: Option<f32>) -> f32 {
+        match rate {
+            Some(rate) => 2f64.powf((rate as f64 - self.base) / self.scale) as f32,
+            None => 1.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ratings() {
+        let csa =
+            "V2.2\n'black_rate:Gikou_2_6950XCPU:3911.0\n'white_rate:Apery_i9:3725.5\n+7776FU\n";
+        let ratings = parse_ratings(csa);
+        assert_eq!(ratings.black, Some(3911.0));
+        assert_eq!(ratings.white, Some(3725.5));
+
+        assert_eq!(parse_ratings("+7776FU\n"), Ratings::default());
+    }
+
+    #[test]
+    fn test_rating_weight() {
+        let weight = RatingWeight {
+            base: 3000.0,
+            scale: 400.0,
+        };
+        assert_eq!(weight.weight(Some(3000.0)), 1.0);
+        assert_eq!(weight.weight(Some(3400.0)), 2.0);
+        assert_eq!(weight.weight(Some(2600.0)), 0.5);
+        assert_eq!(weight.weight(None), 1.0);
+    }
+}
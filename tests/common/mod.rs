@@ -0,0 +1,49 @@
+//! Helpers shared by the integration tests.
+use shogiutil::{Board, Color, UsiRequest, UsiResponse};
+use std::path::PathBuf;
+use super_duper_dragon::game::legal_moves;
+use super_duper_dragon::usi::UsiPlayer;
+
+/// Plays the first legal move.
+#[derive(Default)]
+pub struct FirstMovePlayer {
+    position: Option<(Board, Color)>,
+}
+
+impl UsiPlayer for FirstMovePlayer {
+    fn play(&mut self, request: UsiRequest) -> Vec<UsiResponse> {
+        match request {
+            UsiRequest::Position { board, next_turn } => {
+                self.position = Some((board, next_turn));
+                vec![]
+            }
+            UsiRequest::Go => {
+                let (board, next_turn) = self.position.take().unwrap();
+                let mv = legal_moves(&board, next_turn).into_iter().next().unwrap();
+                match mv.mv.from {
+                    Some(from) => vec![UsiResponse::TravelMove {
+                        from,
+                        to: mv.mv.to,
+                        promoted: mv.promoted,
+                    }],
+                    None => vec![UsiResponse::DropMove {
+                        to: mv.mv.to,
+                        piece: mv.mv.piece,
+                    }],
+                }
+            }
+            _ => vec![],
+        }
+    }
+}
+
+/// A directory under the temporary directory which no other test run uses.
+#[allow(dead_code)]
+pub fn unique_temp_dir(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "{}_{}_{:016x}",
+        name,
+        std::process::id(),
+        rand::random::<u64>()
+    ))
+}
@@ -0,0 +1,146 @@
+mod common;
+
+use common::FirstMovePlayer;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+use super_duper_dragon::csa_client::{run, CsaClientConfig, GameEnd};
+
+const GAME_SUMMARY: &str = "BEGIN Game_Summary
+Protocol_Version:1.2
+Protocol_Mode:Server
+Format:Shogi 1.0
+Game_ID:mock-1
+Name+:test
+Name-:mock
+Your_Turn:+
+To_Move:+
+BEGIN Time
+Time_Unit:1sec
+Total_Time:600
+Byoyomi:10
+END Time
+BEGIN Position
+PI
++
+END Position
+END Game_Summary";
+
+struct MockConnection {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+impl MockConnection {
+    fn new(stream: TcpStream) -> Self {
+        let reader = BufReader::new(stream.try_clone().unwrap());
+        Self { stream, reader }
+    }
+
+    fn send(&mut self, line: &str) {
+        writeln!(self.stream, "{}", line).unwrap();
+    }
+
+    fn read(&mut self) -> String {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).unwrap();
+        line.trim().to_string()
+    }
+
+    fn login(&mut self) {
+        assert_eq!(self.read(), "LOGIN test password");
+        self.send("LOGIN:test OK");
+    }
+
+    /// Plays a game in which the client is Black and the mock resigns after one move.
+    fn serve_game(&mut self) {
+        self.send(GAME_SUMMARY);
+        assert_eq!(self.read(), "AGREE mock-1");
+        self.send("START:mock-1");
+
+        let mv = self.read();
+        assert!(mv.starts_with('+'), "{}", mv);
+        self.send(&format!("{},T1", mv));
+        self.send("");
+        self.send("-3334FU,T2");
+
+        let mv = self.read();
+        assert!(mv.starts_with('+'), "{}", mv);
+        self.send(&format!("{},T1", mv));
+        self.send("%TORYO,T3");
+        self.send("#RESIGN");
+        self.send("#WIN");
+
+        assert_eq!(self.read(), "LOGOUT");
+        self.send("LOGOUT:completed");
+    }
+}
+
+fn config(port: u16) -> CsaClientConfig {
+    CsaClientConfig {
+        host: "127.0.0.1".to_string(),
+        port,
+        name: "test".to_string(),
+        password: "password".to_string(),
+        games: 1,
+        reconnect_attempts: 1,
+        reconnect_wait: Duration::from_millis(10),
+    }
+}
+
+#[test]
+fn test_play_game() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let mut connection = MockConnection::new(listener.accept().unwrap().0);
+        connection.login();
+        connection.serve_game();
+    });
+
+    let results = run(&config(port), &mut FirstMovePlayer::default()).unwrap();
+    server.join().unwrap();
+
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].game_id, "mock-1");
+    assert_eq!(results[0].end, GameEnd::Win);
+    assert_eq!(results[0].reason.as_deref(), Some("#RESIGN"));
+    assert_eq!(results[0].moves, 3);
+}
+
+#[test]
+fn test_reconnect() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let mut connection = MockConnection::new(listener.accept().unwrap().0);
+        connection.login();
+        drop(connection);
+
+        let mut connection = MockConnection::new(listener.accept().unwrap().0);
+        connection.login();
+        connection.serve_game();
+    });
+
+    let results = run(&config(port), &mut FirstMovePlayer::default()).unwrap();
+    server.join().unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].end, GameEnd::Win);
+}
+
+#[test]
+fn test_login_failure() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        for _ in 0..2 {
+            let mut connection = MockConnection::new(listener.accept().unwrap().0);
+            connection.read();
+            connection.send("LOGIN:incorrect");
+        }
+    });
+
+    assert!(run(&config(port), &mut FirstMovePlayer::default()).is_err());
+    server.join().unwrap();
+}
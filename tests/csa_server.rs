@@ -0,0 +1,58 @@
+mod common;
+
+use common::{unique_temp_dir, FirstMovePlayer};
+use std::fs::{read_to_string, remove_dir_all};
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+use super_duper_dragon::csa_client::{run, CsaClientConfig, GameEnd};
+use super_duper_dragon::csa_server::{serve, CsaServerConfig};
+
+#[test]
+fn test_serve() {
+    let out_dir = unique_temp_dir("csa_server_test");
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let config = CsaServerConfig {
+        total_time: 60,
+        byoyomi: 10,
+        max_moves: 10,
+        out_dir: Some(out_dir.clone()),
+        standings_path: None,
+        games: Some(1),
+        initial_rate: 1500.0,
+    };
+    let server = thread::spawn(move || serve(listener, config).unwrap());
+
+    let clients = ["alice", "bob"]
+        .iter()
+        .map(|name| {
+            let config = CsaClientConfig {
+                host: "127.0.0.1".to_string(),
+                port,
+                name: name.to_string(),
+                password: "test".to_string(),
+                games: 1,
+                reconnect_attempts: 0,
+                reconnect_wait: Duration::from_secs(0),
+            };
+            thread::spawn(move || run(&config, &mut FirstMovePlayer::default()).unwrap())
+        })
+        .collect::<Vec<_>>();
+    for client in clients {
+        let results = client.join().unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].end, GameEnd::Chudan);
+        assert_eq!(results[0].reason, Some("#MAX_MOVES".to_string()));
+        assert_eq!(results[0].moves, 10);
+    }
+
+    let results = server.join().unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].winner, None);
+    assert_eq!(results[0].record.moves.len(), 10);
+    let csa = read_to_string(out_dir.join(format!("{}.csa", results[0].game_id))).unwrap();
+    assert!(csa.contains("'black_rate:"));
+    assert!(csa.contains("%HIKIWAKE"));
+    remove_dir_all(out_dir).unwrap();
+}